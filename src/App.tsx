@@ -4,7 +4,10 @@ import { CssBaseline, CircularProgress } from '@material-ui/core';
 import { wrap, transfer } from 'comlink';
 import { DependencyGraph } from './components/DependencyGraph';
 import { Sidebar } from './components/Sidebar';
+import { InterviewFlow } from './components/InterviewFlow';
 import { ComponentType, Component, Link } from './types/Components';
+import { Outline } from './types/Outline';
+import { Use } from './types/WhereUsed';
 
 const reader = new FileReader();
 const worker = new Worker('./web-worker', { name: 'analyzer', type: 'module' });
@@ -17,30 +20,41 @@ const colorMap = {
   'TrueFalse': '#dea2a2',
   'Date': '#48bed9',
   'Dialog': '#d57652',
+  'DialogElement': '#e0a57f',
   'Computation': '#0c4160'
 };
 
 function App() {
-  let [state, updateState] = React.useState({ loading: false, data: { nodes: new Array<Component>(), links: new Array<Link>() }, activeFilter: 'All' });
+  let [state, updateState] = React.useState({ loading: false, data: { nodes: new Array<Component>(), links: new Array<Link>() }, outline: { root: null, steps: [] } as Outline, activeFilter: 'All', selected: null as { component: string, uses: Use[] } | null });
 
   const loadComponentFile = (e: ChangeEvent<HTMLInputElement>) => {
     reader.onload = async componentFile => {
-      updateState({...state, loading: true, data: { nodes: [], links: []}});
+      updateState({...state, loading: true, data: { nodes: [], links: []}, outline: { root: null, steps: [] }, selected: null});
       let data = componentFile.target?.result as ArrayBuffer;
-      const [components, links] = await workerApi.analyze(transfer(data, [data]), colorMap);
+      const [components, links, outline] = await workerApi.analyze(transfer(data, [data]), colorMap);
 
-      updateState({ ...state, data: { nodes: components, links}, loading: false });
+      updateState({ ...state, data: { nodes: components, links}, outline, loading: false });
     };
     reader.readAsArrayBuffer(e.target.files![0]);
   };
 
+  const showWhereUsed = async (component: string) => {
+    const uses = await workerApi.whereUsed(component);
+    updateState({ ...state, selected: { component, uses } });
+  };
+
   const childElement = (() => {
     if (state.loading) {
       return <CircularProgress size={200}/>;
     }
 
     if (state.data.nodes.length > 0) {
-      return <DependencyGraph data={state.data}></DependencyGraph>
+      return (
+        <div style={{ display: 'flex' }}>
+          <DependencyGraph data={state.data} onClickNode={showWhereUsed}></DependencyGraph>
+          {state.outline.root && <InterviewFlow root={state.outline.root} steps={state.outline.steps}></InterviewFlow>}
+        </div>
+      );
     }
     return <div></div>;
   })();
@@ -58,7 +72,8 @@ function App() {
             }));
             updateState({ ...state, activeFilter: componentType, data: { nodes: newNodes, links: state.data.links }});
           }}
-          colorMap={colorMap}></Sidebar>
+          colorMap={colorMap}
+          selected={state.selected}></Sidebar>
         <main>
           <div>
             <input id='cmpLoader' type='file' onChange={loadComponentFile}></input>
@@ -1,24 +1,59 @@
 import { Component, Link } from './types/Components';
+import { Outline } from './types/Outline';
+import { Answer, Diagnostic, Evaluation } from './types/Answers';
+import { DialogState } from './types/Dialog';
+import { Coverage } from './types/Coverage';
+import { MetricsReport, Thresholds } from './types/Metrics';
+import { CloneOptions, CloneReport } from './types/Clones';
+import { Patch, Patched } from './types/Patch';
+import { Tokens } from './types/Tokens';
+import { Completions } from './types/Completion';
+import { Use } from './types/WhereUsed';
 
 const wasmLoader = import('./native/build');
 
-function getString(content: string | ArrayBuffer): string {
+type Wasm = typeof import('./native/build');
+
+// The last component file analyzed, with the uses of every component in it
+let lastAnalyzed: import('./native/build').Analyzed | null = null;
+
+// Component and answer files can be saved as UTF-8, UTF-16 or Windows-1252, so bytes are decoded by the native code
+function getString(wasm: Wasm, content: string | ArrayBuffer): string {
     if (content instanceof ArrayBuffer) {
-        const decoder = new TextDecoder('utf-8');
-        return decoder.decode(content);
+        return wasm.decode(new Uint8Array(content));
     }
 
     return content;
 }
 
-export async function analyze(content: string | ArrayBuffer | null | undefined, typeColorMap: { [K:string]: string }): Promise<[Component[], Link[]]> {
+// Arguments the native code can't read are reported as an error rather than replaced with defaults
+function errorDiagnostic(e: unknown): Diagnostic {
+    return { severity: 'Error', message: `${e}`, component: null, span: null, location: null };
+}
+
+const noOutline: Outline = { root: null, steps: [] };
+
+// The component file is read once, for the components, the interview outline and the where-used index
+export async function analyze(content: string | ArrayBuffer | null | undefined, typeColorMap: { [K:string]: string }): Promise<[Component[], Link[], Outline]> {
     if (content === undefined || content === null) {
-        return [[], []];
+        return [[], [], noOutline];
     }
 
     try {
         const wasm = await wasmLoader;
-        const visualElements = wasm.analyze(getString(content));
+        const analyzed = content instanceof ArrayBuffer
+            ? wasm.analyze_bytes(new Uint8Array(content))
+            : wasm.analyze(content);
+        lastAnalyzed?.free();
+        lastAnalyzed = analyzed;
+
+        const { components: visualElements, outline, diagnostics } = analyzed.analysis();
+        for (const diagnostic of diagnostics) {
+            const position = diagnostic.location ? `${diagnostic.location.start.line}:${diagnostic.location.start.column}: ` : '';
+            const message = diagnostic.component ? `${position}${diagnostic.component}: ${diagnostic.message}` : `${position}${diagnostic.message}`;
+            diagnostic.severity === 'Error' ? console.error(message) : console.warn(message);
+        }
+
         const names = Object.keys(visualElements);
 
         let components = names.map((name: string) => {
@@ -32,14 +67,10 @@ export async function analyze(content: string | ArrayBuffer | null | undefined,
                 type: ''
             };
             
-            if (typeof component === 'string') {
-                node.type = component;
-                node.color = typeColorMap[node.type];
-            } else if (component.Dialog) {
-                node.type = 'Dialog';
+            node.type = Object.keys(component)[0];
+            if (node.type === 'Dialog') {
                 node.symbolType = 'square';
-            } else {
-                node.type = 'Computation';
+            } else if (node.type === 'Computation') {
                 node.symbolType = 'triangle';
             }
             node.color = typeColorMap[node.type];
@@ -48,24 +79,216 @@ export async function analyze(content: string | ArrayBuffer | null | undefined,
         });
 
         let links = components
-            .filter(c => typeof c.component === 'object')
             .flatMap(c => {
-                if (c.component.Dialog) {
-                    const referencedNames = new Set<string>([...c.component.Dialog.children, ...c.component.Dialog.script]);
-                    let children = [];
-                    for (const name of referencedNames) {
-                        children.push({ source: name, target: c.id });
-                    }
-                    return children;
-                } else {
-                    return c.component.Computation.map((childName: string) => ({ source: childName, target: c.id }));
+                const referencedNames = c.type === 'Dialog'
+                    ? new Set<string>([...c.component.Dialog.children, ...c.component.Dialog.script, ...c.component.Dialog.fields])
+                    : new Set<string>(c.component[c.type]);
+                let children = [];
+                for (const name of referencedNames) {
+                    children.push({ source: name, target: c.id });
                 }
+                return children;
             })
             .filter(c => names.indexOf(c.source) !== -1 && names.indexOf(c.target) !== -1);
-        return [components, links];
+        if (outline) {
+            for (const diagnostic of outline.diagnostics) {
+                console.warn(diagnostic.message);
+            }
+        }
+        return [components, links, outline ? { root: outline.root, steps: outline.steps } : noOutline];
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return [[], [], noOutline];
+    }
+}
+
+export async function outline(content: string | ArrayBuffer | null | undefined, root?: string): Promise<Outline> {
+    if (content === undefined || content === null) {
+        return { root: null, steps: [] };
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        const { root: outlineRoot, steps, diagnostics } = wasm.outline(getString(wasm, content), root);
+        for (const diagnostic of diagnostics) {
+            console.warn(diagnostic.message);
+        }
+        return { root: outlineRoot, steps };
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { root: null, steps: [] };
+    }
+}
+
+export async function evaluate(content: string | ArrayBuffer | null | undefined, computation: string, answers: Answer[]): Promise<Evaluation> {
+    if (content === undefined || content === null) {
+        return { result: 'Unanswered', answers, asked: [] };
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        const today = new Date().toISOString().slice(0, 10);
+        const { result, answers: updatedAnswers, asked, diagnostics } = wasm.evaluate(getString(wasm, content), computation, answers, today);
+        for (const diagnostic of diagnostics) {
+            console.warn(diagnostic.message);
+        }
+        return { result, answers: updatedAnswers, asked };
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { result: 'Unanswered', answers, asked: [] };
+    }
+}
+
+export async function checkAnswers(content: string | ArrayBuffer | null | undefined, answerFile: string | ArrayBuffer): Promise<Diagnostic[]> {
+    if (content === undefined || content === null) {
+        return [];
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        const { diagnostics } = wasm.check_answers(getString(wasm, content), getString(wasm, answerFile));
+        return diagnostics;
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return [];
+    }
+}
+
+export async function simulateDialog(content: string | ArrayBuffer | null | undefined, dialog: string, answers: Answer[]): Promise<DialogState> {
+    const empty = { dialog, controls: [], limit: null, answers, diagnostics: [] };
+    if (content === undefined || content === null) {
+        return empty;
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        const today = new Date().toISOString().slice(0, 10);
+        return wasm.dialog(getString(wasm, content), dialog, answers, today);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { ...empty, diagnostics: [errorDiagnostic(e)] };
+    }
+}
+
+export async function coverage(content: string | ArrayBuffer | null | undefined, computation?: string): Promise<Coverage[]> {
+    if (content === undefined || content === null) {
+        return [];
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        const today = new Date().toISOString().slice(0, 10);
+        return wasm.coverage(getString(wasm, content), computation, today);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return [];
+    }
+}
+
+export async function metrics(content: string | ArrayBuffer | null | undefined, thresholds: Thresholds = {}): Promise<MetricsReport | null> {
+    if (content === undefined || content === null) {
+        return null;
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.metrics(getString(wasm, content), thresholds);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return null;
+    }
+}
+
+export async function clones(content: string | ArrayBuffer | null | undefined, options: CloneOptions = {}): Promise<CloneReport> {
+    if (content === undefined || content === null) {
+        return { groups: [], diagnostics: [] };
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.clones(getString(wasm, content), options);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { groups: [], diagnostics: [errorDiagnostic(e)] };
+    }
+}
+
+export async function rename(content: string | ArrayBuffer | null | undefined, oldName: string, newName: string): Promise<Patched | null> {
+    if (content === undefined || content === null) {
+        return null;
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.rename(getString(wasm, content), oldName, newName);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return null;
+    }
+}
+
+export async function patch(content: string | ArrayBuffer | null | undefined, patches: Patch[]): Promise<Patched | null> {
+    if (content === undefined || content === null) {
+        return null;
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.patch(getString(wasm, content), patches);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return null;
+    }
+}
+
+export async function tokens(content: string | ArrayBuffer | null | undefined, script: string): Promise<Tokens> {
+    if (content === undefined || content === null) {
+        return { tokens: [], diagnostics: [] };
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.tokens(getString(wasm, content), script);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { tokens: [], diagnostics: [] };
+    }
+}
+
+// Offset is a UTF-16 offset into the script, as JavaScript indexes strings, and so are the spans returned
+export async function complete(content: string | ArrayBuffer | null | undefined, script: string, offset: number): Promise<Completions> {
+    if (content === undefined || content === null) {
+        return { span: { start: offset, end: offset }, items: [], diagnostics: [] };
+    }
+
+    try {
+        const wasm = await wasmLoader;
+        return wasm.complete(getString(wasm, content), script, offset);
+    }
+    catch (e) {
+        console.error(`${e}`);
+        return { span: { start: offset, end: offset }, items: [], diagnostics: [] };
+    }
+}
+
+// Every place the component is used in the component file last given to analyze
+export async function whereUsed(name: string): Promise<Use[]> {
+    try {
+        return lastAnalyzed ? lastAnalyzed.uses(name) : [];
     }
     catch (e) {
         console.error(`${e}`);
-        return [[], []];
+        return [];
     }
 }
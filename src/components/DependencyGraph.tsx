@@ -1,7 +1,7 @@
 import React from 'react';
 import { Graph, GraphData, GraphNode, GraphLink } from 'react-d3-graph';
 
-export function DependencyGraph(props: {data: GraphData<GraphNode, GraphLink>}) {
+export function DependencyGraph(props: {data: GraphData<GraphNode, GraphLink>, onClickNode?: (id: string) => void}) {
     return (
         <Graph
             id="graph-id"
@@ -21,6 +21,7 @@ export function DependencyGraph(props: {data: GraphData<GraphNode, GraphLink>})
                 }
             }}
             data={props.data}
+            onClickNode={props.onClickNode}
         >
         </Graph>
     );   
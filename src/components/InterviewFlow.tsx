@@ -0,0 +1,67 @@
+import React from 'react';
+import { Graph } from 'react-d3-graph';
+import { OutlineStep, FlowNode, FlowLink } from '../types/Outline';
+
+// Lays the outline out as a flowchart, returning the nodes that the next step should follow on from
+function addSteps(steps: OutlineStep[], entries: string[], nodes: FlowNode[], links: FlowLink[]): string[] {
+    let exits = entries;
+    for (const step of steps) {
+        const id = `${nodes.length}`;
+        const link = (sources: string[]) => sources.forEach(source => links.push({ source, target: id }));
+        link(exits);
+
+        if ('Ask' in step) {
+            nodes.push({ id, label: `ASK ${step.Ask.dialog}`, symbolType: 'square' });
+            addSteps(step.Ask.inserted, [id], nodes, links);
+            exits = [id];
+        } else if ('Condition' in step) {
+            nodes.push({ id, label: `IF ${step.Condition.condition}`, symbolType: 'diamond' });
+            const thenExits = addSteps(step.Condition.then_body, [id], nodes, links);
+            const elseExits = addSteps(step.Condition.else_body, [id], nodes, links);
+            exits = [...thenExits, ...elseExits];
+        } else if ('Repeat' in step) {
+            nodes.push({ id, label: `REPEAT ${step.Repeat.dialog}`, symbolType: 'wye' });
+            addSteps(step.Repeat.body, [id], nodes, links).filter(e => e !== id).forEach(source => links.push({ source, target: id }));
+            exits = [id];
+        } else if ('While' in step) {
+            nodes.push({ id, label: `WHILE ${step.While.condition}`, symbolType: 'wye' });
+            addSteps(step.While.body, [id], nodes, links).filter(e => e !== id).forEach(source => links.push({ source, target: id }));
+            exits = [id];
+        } else if ('Computation' in step) {
+            nodes.push({ id, label: step.Computation.name, symbolType: 'triangle' });
+            exits = addSteps(step.Computation.body, [id], nodes, links);
+        } else {
+            nodes.push({ id, label: `${step.Recursion.name} (again)`, symbolType: 'cross' });
+            exits = [id];
+        }
+    }
+    return exits;
+}
+
+export function InterviewFlow(props: { root: string, steps: OutlineStep[] }) {
+    const nodes: FlowNode[] = [{ id: 'start', label: props.root, symbolType: 'circle' }];
+    const links: FlowLink[] = [];
+    addSteps(props.steps, ['start'], nodes, links);
+
+    return (
+        <Graph
+            id="interview-flow"
+            config={{
+                directed: true,
+                width: 800,
+                height: 1000,
+                node: {
+                    labelProperty: 'label',
+                    color: '#d57652',
+                    strokeColor: 'black',
+                    strokeWidth: 1
+                },
+                link: {
+                    color: '#c9cdff'
+                }
+            }}
+            data={{ nodes, links }}
+        >
+        </Graph>
+    );
+}
@@ -1,9 +1,38 @@
-import React from 'react';
-import { Drawer, List, ListItem, ListItemIcon, ListItemText } from '@material-ui/core';
+import React, { Fragment } from 'react';
+import { Divider, Drawer, List, ListItem, ListItemIcon, ListItemText, ListSubheader } from '@material-ui/core';
 import { Inbox, TextFields, CalendarToday, DoneOutline, FormatListBulleted } from '@material-ui/icons';
 import { ComponentType } from '../types/Components';
+import { Use, UseKind } from '../types/WhereUsed';
 
-export function Sidebar(props: { activeFilter: string, setComponentFilter(component: ComponentType): void, colorMap: { [x:string]: string } }) {
+const useLabels: { [K in UseKind]: string } = {
+  DialogContents: 'In dialog',
+  Read: 'Read by',
+  Write: 'Set by',
+  Ask: 'Asked by',
+  Display: 'Displayed by',
+  Repeat: 'Repeated by',
+  OptionTable: 'In options of',
+  MergeField: 'Merged into'
+};
+
+function WhereUsed(props: { component: string, uses: Use[] }) {
+    return (
+        <List dense subheader={<ListSubheader>Where {props.component} is used</ListSubheader>}>
+          {props.uses.length === 0 && <ListItem><ListItemText secondary="Not used by any component" /></ListItem>}
+          {
+            props.uses.map((use, i) =>
+              <ListItem key={i}>
+                <ListItemText
+                  primary={`${useLabels[use.kind]} ${use.component}`}
+                  secondary={use.location ? `Line ${use.location.start.line}, column ${use.location.start.column}` : null} />
+              </ListItem>
+            )
+          }
+        </List>
+    );
+}
+
+export function Sidebar(props: { activeFilter: string, setComponentFilter(component: ComponentType): void, colorMap: { [x:string]: string }, selected: { component: string, uses: Use[] } | null }) {
     return (
         <Drawer variant="permanent" style={{width: "220px" }}>
           <List>
@@ -23,6 +52,7 @@ export function Sidebar(props: { activeFilter: string, setComponentFilter(compon
               )
             }
           </List>
+          {props.selected && <Fragment><Divider /><WhereUsed component={props.selected.component} uses={props.selected.uses} /></Fragment>}
         </Drawer>
     );
 }
\ No newline at end of file
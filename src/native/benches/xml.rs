@@ -0,0 +1,48 @@
+use criterion::{ criterion_group, criterion_main, Criterion, Throughput };
+use hotdocs_parser::{ xml_parser, xml_reader };
+
+// A component library with the given number of variables, each used by a computation and asked on a dialog
+fn library(components: usize) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\" version=\"12\">\n",
+        "<hd:preferences><hd:preference name=\"INTERVIEW_COMPUTATION\">Interview</hd:preference></hd:preferences>\n",
+        "<hd:components>\n"
+    ));
+    for i in 0..components {
+        xml.push_str(&format!(
+            concat!(
+                "  <hd:text name=\"Text {i}\"><hd:prompt>Enter the value of «Text {i}» &amp; more</hd:prompt></hd:text>\n",
+                "  <hd:computation name=\"Computation {i}\" resultType=\"text\"><hd:script>IF Text {i} = \"a\"\n",
+                "  \"first &lt; second\"\nELSE\n  Text {i}\nEND IF</hd:script></hd:computation>\n",
+                "  <hd:dialog name=\"Dialog {i}\"><hd:contents><hd:item name=\"Text {i}\"/></hd:contents>",
+                "<hd:script>GRAY Text {i}</hd:script></hd:dialog>\n"
+            ),
+            i = i
+        ));
+    }
+    xml.push_str("</hd:components>\n</hd:componentLibrary>\n");
+    xml
+}
+
+fn parsers(c: &mut Criterion) {
+    for size in [100, 2_000] {
+        let document = library(size);
+        let mut group = c.benchmark_group(format!("{} components", size * 3));
+        group.throughput(Throughput::Bytes(document.len() as u64));
+
+        group.bench_function("tree parser", |b| b.iter(|| xml_parser::parse(&document).unwrap().1.children.len()));
+        group.bench_function("reader tree", |b| b.iter(|| xml_reader::read_tree(&document).unwrap().children.len()));
+        group.bench_function("reader events", |b| b.iter(|| xml_reader::Reader::new(&document).filter(|e| e.is_ok()).count()));
+        group.bench_function("streamed components", |b| b.iter(|| xml_reader::Components::new(&document).map(|c| c.unwrap().children.len()).sum::<usize>()));
+        group.bench_function("streamed library", |b| b.iter(|| {
+            let mut children = 0;
+            xml_reader::read_library(&document, |c, _| children += c.children.len()).unwrap();
+            children
+        }));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, parsers);
+criterion_main!(benches);
@@ -0,0 +1,388 @@
+use crate::xml_parser::{ Node, XmlElement };
+use crate::answers::{ AnswerSet, Date, Value };
+use crate::diagnostics::Diagnostic;
+use crate::hd_script_parser::Script;
+use crate::repeats;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AnswerFile {
+    pub title: Option<String>,
+    pub answers: AnswerSet,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+fn elements<'b, 'a>(element: &'b XmlElement<'a>) -> impl Iterator<Item = &'b XmlElement<'a>> {
+    element.children.iter().filter_map(|n| match n {
+        Node::XmlElement(e) => Some(e),
+        _ => None
+    })
+}
+
+fn has_stray_text(element: &XmlElement) -> bool {
+    element.children.iter().any(|n| match n {
+        Node::Value(text) => !text.value.trim().is_empty(),
+        _ => false
+    })
+}
+
+// Answer files store dates as d/m/yyyy, ISO dates are accepted as well
+fn parse_date(text: &str) -> Option<Date> {
+    let parts: Vec<&str> = text.trim().split('/').collect();
+    match parts.as_slice() {
+        [day, month, year] => Date::new(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?),
+        _ => Date::parse_iso(text)
+    }
+}
+
+fn parse_value(element: &XmlElement, diagnostics: &mut Vec<Diagnostic>) -> Option<Value> {
+    if element.attributes.get("unans").is_some_and(|u| u == "true") {
+        return Some(Value::Unanswered);
+    }
+
+    let text = element.text();
+    let value = match element.name {
+        "TextValue" => return Some(Value::Text(text)),
+        "NumValue" => text.trim().parse().ok().map(Value::Number),
+        "DateValue" => parse_date(&text).map(Value::Date),
+        "TFValue" => match text.trim() {
+            "true" => Some(Value::TrueFalse(true)),
+            "false" => Some(Value::TrueFalse(false)),
+            _ => None
+        },
+        "MCValue" => Some(Value::MultipleChoice(
+            elements(element)
+                .filter(|e| e.name == "SelValue")
+                .map(|e| e.text())
+                .collect()
+        )),
+        name => {
+            diagnostics.push(Diagnostic::error(format!("<{}> is not a HotDocs answer value", name)));
+            return None;
+        }
+    };
+
+    if value.is_none() {
+        diagnostics.push(Diagnostic::error(format!("\"{}\" is not a valid <{}>", text.trim(), element.name)));
+    }
+    value
+}
+
+// Repeated answers are stored as <RptValue> elements holding one value per iteration, nested for each level of repeat
+fn parse_answer(name: &str, element: &XmlElement, index: &mut Vec<usize>, answers: &mut AnswerSet, diagnostics: &mut Vec<Diagnostic>) {
+    if element.name == "RptValue" {
+        for (i, child) in elements(element).enumerate() {
+            index.push(i);
+            parse_answer(name, child, index, answers, diagnostics);
+            index.pop();
+        }
+    } else if let Some(value) = parse_value(element, diagnostics) {
+        answers.set(name, index.clone(), value);
+    }
+}
+
+pub fn parse(root: &XmlElement) -> AnswerFile {
+    let mut answers = AnswerSet::new();
+    let mut diagnostics = vec![];
+
+    if root.name != "AnswerSet" {
+        diagnostics.push(Diagnostic::error(format!("The root element is <{}>, expected a HotDocs AnswerSet", root.name)));
+        return AnswerFile { title: None, answers, diagnostics };
+    }
+    if has_stray_text(root) {
+        diagnostics.push(Diagnostic::warning(String::from("Unexpected text in the answer file was ignored")));
+    }
+
+    for element in elements(root) {
+        let name = match element.attributes.get("name") {
+            Some(name) if element.name == "Answer" && !name.is_empty() => name,
+            _ => {
+                diagnostics.push(Diagnostic::warning(format!("<{}> is not a named answer and was ignored", element.name)));
+                continue;
+            }
+        };
+
+        let mut answer_diagnostics = vec![];
+        let mut values = elements(element);
+        match (values.next(), values.next()) {
+            (Some(value), None) => parse_answer(name, value, &mut vec![], &mut answers, &mut answer_diagnostics),
+            (None, _) => answer_diagnostics.push(Diagnostic::warning(String::from("The answer has no value"))),
+            (Some(value), Some(_)) => {
+                answer_diagnostics.push(Diagnostic::warning(String::from("The answer has more than one value, only the first was used")));
+                parse_answer(name, value, &mut vec![], &mut answers, &mut answer_diagnostics);
+            }
+        }
+        diagnostics.extend(answer_diagnostics.into_iter().map(|d| d.for_component(name)));
+    }
+
+    AnswerFile { title: root.attributes.get("title").cloned().filter(|t| !t.is_empty()), answers, diagnostics }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn value_element(value: &Value) -> &'static str {
+    match value {
+        Value::Number(_) => "NumValue",
+        Value::Date(_) => "DateValue",
+        Value::TrueFalse(_) => "TFValue",
+        Value::MultipleChoice(_) => "MCValue",
+        _ => "TextValue"
+    }
+}
+
+fn write_value(value: &Value, element: &str, indent: &str, out: &mut String) {
+    let text = match value {
+        Value::Unanswered => {
+            out.push_str(&format!("{}<{} unans=\"true\"/>\n", indent, element));
+            return;
+        },
+        Value::Text(t) => escape(t),
+        Value::Number(n) => n.to_string(),
+        Value::Date(d) => format!("{}/{}/{}", d.day, d.month, d.year),
+        Value::TrueFalse(b) => b.to_string(),
+        Value::MultipleChoice(selections) => selections.iter().map(|s| format!("<SelValue>{}</SelValue>", escape(s))).collect()
+    };
+    out.push_str(&format!("{}<{}>{}</{}>\n", indent, element, text, element));
+}
+
+// Writes the answers whose index continues the given prefix, nesting a <RptValue> for each level of repeat and filling
+// the iterations without an answer with unanswered values
+fn write_answer(values: &[(&Vec<usize>, &Value)], depth: usize, element: &str, indent: &str, out: &mut String) {
+    if let Some((_, value)) = values.iter().find(|(index, _)| index.len() == depth) {
+        write_value(value, element, indent, out);
+        return;
+    }
+
+    out.push_str(&format!("{}<RptValue>\n", indent));
+    let iterations = values.iter().map(|(index, _)| index[depth] + 1).max().unwrap_or(0);
+    for i in 0..iterations {
+        let iteration: Vec<_> = values.iter().filter(|(index, _)| index[depth] == i).copied().collect();
+        if iteration.is_empty() {
+            write_value(&Value::Unanswered, element, &format!("{}  ", indent), out);
+        } else {
+            write_answer(&iteration, depth + 1, element, &format!("{}  ", indent), out);
+        }
+    }
+    out.push_str(&format!("{}</RptValue>\n", indent));
+}
+
+// Writes the answers as a HotDocs answer file
+pub fn write(answers: &AnswerSet, title: Option<&str>) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    out.push_str(&format!("<AnswerSet title=\"{}\" version=\"1.1\">\n", escape(title.unwrap_or_default())));
+
+    let mut names: Vec<&String> = answers.iter().map(|(name, _, _)| name).collect();
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let values: Vec<_> = answers.iter().filter(|(n, _, _)| *n == name).map(|(_, index, value)| (index, value)).collect();
+        let element = value_element(values[0].1);
+        out.push_str(&format!("  <Answer name=\"{}\">\n", escape(name)));
+        write_answer(&values, 0, element, "    ", &mut out);
+        out.push_str("  </Answer>\n");
+    }
+
+    out.push_str("</AnswerSet>\n");
+    out
+}
+
+// Describes where a repeated answer is in the terms HotDocs uses, e.g. " for iteration 2.1"
+fn describe_index(index: &[usize]) -> String {
+    if index.is_empty() {
+        String::new()
+    } else {
+        format!(" for iteration {}", index.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>().join("."))
+    }
+}
+
+fn expected_type(component: &Component) -> Option<&'static str> {
+    match component {
+        Component::Text(_) => Some("text"),
+        Component::Number(_) => Some("number"),
+        Component::Date(_) => Some("date"),
+        Component::TrueFalse(_) => Some("true/false"),
+        Component::MultipleChoice(_) => Some("multiple choice"),
+        _ => None
+    }
+}
+
+// Checks the answers against the variables in the component library. Answer files are shared between templates, so
+// answers for variables the library doesn't know about are only warnings.
+pub fn validate(answers: &AnswerSet, components: &HashMap<String, Component>, options: &HashMap<String, Vec<String>>, scripts: &HashMap<&str, Script>) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let variable_depths = repeats::variable_depths(components, scripts);
+
+    // Problems that apply to the whole variable rather than a single answer are only reported once
+    let mut reported = HashSet::new();
+    let mut answers: Vec<_> = answers.iter().collect();
+    answers.sort_by(|a, b| a.0.cmp(b.0).then_with(|| a.1.cmp(b.1)));
+
+    for (name, index, value) in answers {
+        let component = match components.get(name) {
+            Some(component) => component,
+            None => {
+                if reported.insert(name) {
+                    diagnostics.push(Diagnostic::warning(String::from("The answer is for a variable that is not in the component library")).for_component(name));
+                }
+                continue;
+            }
+        };
+
+        let expected = match expected_type(component) {
+            Some(expected) => expected,
+            None => {
+                if reported.insert(name) {
+                    diagnostics.push(Diagnostic::error(String::from("The answer is for a component that is not a variable")).for_component(name));
+                }
+                continue;
+            }
+        };
+
+        if value.type_name() != expected {
+            diagnostics.push(Diagnostic::error(format!("The answer{} is a {} value but the variable is a {} variable", describe_index(index), value.type_name(), expected)).for_component(name));
+        }
+
+        if let (Value::MultipleChoice(selections), Some(options)) = (value, options.get(name)) {
+            for selection in selections.iter().filter(|s| !options.contains(s)) {
+                diagnostics.push(Diagnostic::error(format!("\"{}\" is not one of the options of the multiple choice variable", selection)).for_component(name));
+            }
+        }
+
+        // Variables that no script repeats are left alone when they are repeated, as the REPEAT could be in a template
+        let depths = match variable_depths.get(name) {
+            Some(depths) if !depths.is_empty() && !depths.contains(&index.len()) => depths,
+            _ => continue
+        };
+        if reported.insert(name) {
+            let expected_depths = depths.iter().map(|d| d.to_string()).collect::<Vec<_>>().join(" or ");
+            diagnostics.push(Diagnostic::warning(format!("The answer is repeated {} level(s) deep but the variable is repeated {} level(s) deep", index.len(), expected_depths)).for_component(name));
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Severity;
+    use crate::xml_reader;
+
+    const ANSWERS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<AnswerSet title="Smith family" version="1.1">
+  <Answer name="Child Name">
+    <RptValue>
+      <TextValue>Ann</TextValue>
+      <TextValue unans="true"/>
+      <TextValue>Cat</TextValue>
+    </RptValue>
+  </Answer>
+  <Answer name="Grandchild Age">
+    <RptValue>
+      <RptValue>
+        <NumValue>4</NumValue>
+      </RptValue>
+      <RptValue>
+        <NumValue>1</NumValue>
+        <NumValue>7</NumValue>
+      </RptValue>
+    </RptValue>
+  </Answer>
+  <Answer name="Married">
+    <TFValue unans="true"/>
+  </Answer>
+  <Answer name="Pets">
+    <MCValue>
+      <SelValue>Dog</SelValue>
+      <SelValue>Fish</SelValue>
+    </MCValue>
+  </Answer>
+  <Answer name="Signed">
+    <DateValue>3/6/1990</DateValue>
+  </Answer>
+</AnswerSet>"#;
+
+    fn parsed(answers: &str) -> AnswerFile {
+        parse(&xml_reader::read_tree(answers).unwrap())
+    }
+
+    fn components() -> HashMap<String, Component> {
+        vec![
+            (String::from("Child Name"), Component::Text(HashSet::new())),
+            (String::from("Grandchild Age"), Component::Number(HashSet::new())),
+            (String::from("Married"), Component::TrueFalse(HashSet::new())),
+            (String::from("Pets"), Component::MultipleChoice(HashSet::new())),
+            (String::from("Signed"), Component::Date(HashSet::new()))
+        ].into_iter().collect()
+    }
+
+    #[test]
+    fn repeated_answers_are_read_at_their_iteration() {
+        let file = parsed(ANSWERS);
+        assert!(file.diagnostics.is_empty(), "{:?}", file.diagnostics);
+        assert_eq!(file.title.as_deref(), Some("Smith family"));
+
+        assert_eq!(file.answers.get("Child Name", &[0]), Value::Text(String::from("Ann")));
+        assert_eq!(file.answers.get("Child Name", &[2]), Value::Text(String::from("Cat")));
+        assert_eq!(file.answers.get("Grandchild Age", &[0, 0]), Value::Number(4.0));
+        assert_eq!(file.answers.get("Grandchild Age", &[1, 1]), Value::Number(7.0));
+        assert_eq!(file.answers.get("Signed", &[]), Value::Date(Date::new(1990, 6, 3).unwrap()));
+    }
+
+    #[test]
+    fn unanswered_values_have_no_answer() {
+        let file = parsed(ANSWERS);
+        assert_eq!(file.answers.get("Child Name", &[1]), Value::Unanswered);
+        assert_eq!(file.answers.get("Married", &[]), Value::Unanswered);
+
+        // The iterations between answers are written back as unanswered
+        let written = write(&file.answers, file.title.as_deref());
+        assert!(written.contains("<TextValue>Ann</TextValue>\n      <TextValue unans=\"true\"/>\n      <TextValue>Cat</TextValue>"), "{}", written);
+        assert_eq!(parsed(&written).answers, file.answers);
+    }
+
+    #[test]
+    fn multiple_choice_answers_keep_every_selection() {
+        let file = parsed(ANSWERS);
+        assert_eq!(file.answers.get("Pets", &[]), Value::MultipleChoice(vec![String::from("Dog"), String::from("Fish")]));
+
+        let options = vec![(String::from("Pets"), vec![String::from("Cat"), String::from("Dog")])].into_iter().collect();
+        let diagnostics = validate(&file.answers, &components(), &options, &HashMap::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "\"Fish\" is not one of the options of the multiple choice variable");
+        assert_eq!(diagnostics[0].component.as_deref(), Some("Pets"));
+    }
+
+    #[test]
+    fn answers_for_unknown_components_are_warned_about_once() {
+        let file = parsed(ANSWERS);
+        let mut components = components();
+        components.remove("Child Name");
+
+        let diagnostics = validate(&file.answers, &components, &HashMap::new(), &HashMap::new());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].message, "The answer is for a variable that is not in the component library");
+        assert_eq!(diagnostics[0].component.as_deref(), Some("Child Name"));
+    }
+
+    #[test]
+    fn answers_of_the_wrong_type_are_errors() {
+        let file = parsed(ANSWERS);
+        let mut components = components();
+        components.insert(String::from("Grandchild Age"), Component::Text(HashSet::new()));
+
+        let messages: Vec<String> = validate(&file.answers, &components, &HashMap::new(), &HashMap::new()).into_iter().map(|d| d.message).collect();
+        assert_eq!(messages, vec![
+            String::from("The answer for iteration 1.1 is a number value but the variable is a text variable"),
+            String::from("The answer for iteration 2.1 is a number value but the variable is a text variable"),
+            String::from("The answer for iteration 2.2 is a number value but the variable is a text variable")
+        ]);
+    }
+}
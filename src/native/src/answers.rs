@@ -0,0 +1,272 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        if (1..=12).contains(&month) && day >= 1 && day <= days_in_month(year, month) {
+            Some(Date { year, month, day })
+        } else {
+            None
+        }
+    }
+
+    // Parses the ISO 8601 form, e.g. 1990-06-03
+    pub fn parse_iso(text: &str) -> Option<Date> {
+        let mut parts = text.trim().splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+        Date::new(year, month, day)
+    }
+
+    // The number of days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    pub fn to_days(self) -> i64 {
+        let y = if self.month <= 2 { self.year as i64 - 1 } else { self.year as i64 };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let m = self.month as i64;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+    }
+
+    pub fn from_days(days: i64) -> Date {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        Date { year, month, day }
+    }
+
+    pub fn add_days(self, days: i64) -> Date {
+        Date::from_days(self.to_days() + days)
+    }
+
+    // Adding months keeps the day of the month where possible, otherwise it uses the last day of the month
+    pub fn add_months(self, months: i64) -> Date {
+        let total = self.year as i64 * 12 + (self.month as i64 - 1) + months;
+        let year = total.div_euclid(12) as i32;
+        let month = total.rem_euclid(12) as u32 + 1;
+        Date { year, month, day: self.day.min(days_in_month(year, month)) }
+    }
+
+    // 1 is Sunday, 7 is Saturday
+    pub fn day_of_week(self) -> u32 {
+        (self.to_days() + 4).rem_euclid(7) as u32 + 1
+    }
+
+    // The number of whole months from this date until the other date
+    pub fn months_until(self, other: Date) -> i64 {
+        let months = (other.year as i64 - self.year as i64) * 12 + other.month as i64 - self.month as i64;
+        if months > 0 && other.day < self.day {
+            months - 1
+        } else if months < 0 && other.day > self.day {
+            months + 1
+        } else {
+            months
+        }
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Value {
+    Unanswered,
+    Text(String),
+    Number(f64),
+    Date(Date),
+    TrueFalse(bool),
+    MultipleChoice(Vec<String>)
+}
+
+impl Value {
+    pub fn is_answered(&self) -> bool {
+        *self != Value::Unanswered
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Unanswered => "unanswered",
+            Value::Text(_) => "text",
+            Value::Number(_) => "number",
+            Value::Date(_) => "date",
+            Value::TrueFalse(_) => "true/false",
+            Value::MultipleChoice(_) => "multiple choice"
+        }
+    }
+}
+
+// A single answer, the index holds the zero based iteration of each repeat the answer was given in
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Answer {
+    pub name: String,
+    #[serde(default)]
+    pub index: Vec<usize>,
+    pub value: Value
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+#[serde(from = "Vec<Answer>", into = "Vec<Answer>")]
+pub struct AnswerSet {
+    answers: HashMap<String, BTreeMap<Vec<usize>, Value>>
+}
+
+impl From<Vec<Answer>> for AnswerSet {
+    fn from(answers: Vec<Answer>) -> AnswerSet {
+        let mut set = AnswerSet::default();
+        for answer in answers {
+            set.set(&answer.name, answer.index, answer.value);
+        }
+        set
+    }
+}
+
+impl From<AnswerSet> for Vec<Answer> {
+    fn from(set: AnswerSet) -> Vec<Answer> {
+        let mut answers: Vec<Answer> = set
+            .answers
+            .into_iter()
+            .flat_map(|(name, values)| values.into_iter().map(move |(index, value)| Answer { name: name.clone(), index, value }))
+            .collect();
+        answers.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.index.cmp(&b.index)));
+        answers
+    }
+}
+
+impl AnswerSet {
+    pub fn new() -> AnswerSet {
+        AnswerSet::default()
+    }
+
+    pub fn get(&self, name: &str, index: &[usize]) -> Value {
+        self.answers
+            .get(name)
+            .and_then(|values| values.get(index))
+            .cloned()
+            .unwrap_or(Value::Unanswered)
+    }
+
+    // Setting an answer to unanswered removes it
+    pub fn set(&mut self, name: &str, index: Vec<usize>, value: Value) {
+        if value.is_answered() {
+            self.answers.entry(name.to_owned()).or_default().insert(index, value);
+        } else if let Some(values) = self.answers.get_mut(name) {
+            values.remove(&index);
+        }
+    }
+
+    pub fn erase(&mut self, name: &str) {
+        self.answers.remove(name);
+    }
+
+    // Finds where an answer for the variable lives when it is used in the given repeat context. Variables that
+    // aren't repeated (or are repeated fewer times than the context) have their answers at a prefix of the context.
+    pub fn resolve(&self, name: &str, context: &[usize]) -> Option<Vec<usize>> {
+        let values = self.answers.get(name)?;
+        (0..=context.len())
+            .rev()
+            .map(|depth| &context[..depth])
+            .find(|index| values.contains_key(*index))
+            .map(|index| index.to_vec())
+    }
+
+    // The number of iterations of a repeat that have an answer for the variable within the given repeat context
+    pub fn iterations(&self, name: &str, context: &[usize]) -> usize {
+        match self.answers.get(name) {
+            Some(values) => values
+                .keys()
+                .filter(|index| index.len() > context.len() && index.starts_with(context))
+                .map(|index| index[context.len()] + 1)
+                .max()
+                .unwrap_or(0),
+            None => 0
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<usize>, &Value)> {
+        self.answers.iter().flat_map(|(name, values)| values.iter().map(move |(index, value)| (name, index, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Value {
+        Value::Text(value.to_owned())
+    }
+
+    #[test]
+    fn setting_an_answer_to_unanswered_removes_it() {
+        let mut answers = AnswerSet::new();
+        answers.set("Client", vec![], text("Ann"));
+        answers.set("Client", vec![], Value::Unanswered);
+
+        assert_eq!(answers.get("Client", &[]), Value::Unanswered);
+        assert_eq!(answers.iter().count(), 0);
+    }
+
+    #[test]
+    fn repeated_answers_resolve_within_their_repeat() {
+        let mut answers = AnswerSet::new();
+        answers.set("Surname", vec![], text("Smith"));
+        answers.set("Child", vec![0], text("Ann"));
+        answers.set("Child", vec![2], text("Cat"));
+        answers.set("Grandchild", vec![2, 0], text("Dee"));
+
+        // Answers that aren't repeated as deeply as the context are found at a prefix of it
+        assert_eq!(answers.resolve("Surname", &[2, 0]), Some(vec![]));
+        assert_eq!(answers.resolve("Child", &[2, 0]), Some(vec![2]));
+        assert_eq!(answers.resolve("Child", &[1]), None);
+        assert_eq!(answers.resolve("Unknown", &[]), None);
+
+        assert_eq!(answers.iterations("Child", &[]), 3);
+        assert_eq!(answers.iterations("Grandchild", &[2]), 1);
+        assert_eq!(answers.iterations("Grandchild", &[0]), 0);
+    }
+
+    #[test]
+    fn answers_are_listed_in_name_and_iteration_order() {
+        let mut answers = AnswerSet::new();
+        answers.set("Child", vec![1], text("Bob"));
+        answers.set("Age", vec![], Value::Number(4.0));
+        answers.set("Child", vec![0], text("Ann"));
+
+        let listed: Vec<Answer> = answers.clone().into();
+        assert_eq!(listed.iter().map(|a| (a.name.as_str(), a.index.clone())).collect::<Vec<_>>(), vec![
+            ("Age", vec![]), ("Child", vec![0]), ("Child", vec![1])
+        ]);
+        assert_eq!(AnswerSet::from(listed), answers);
+    }
+}
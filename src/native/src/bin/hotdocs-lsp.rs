@@ -0,0 +1,20 @@
+// A Language Server Protocol server for HotDocs scripts, talking to the editor over stdin and stdout
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    use std::io;
+    use std::process;
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match hotdocs_parser::lsp::run(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("hotdocs-lsp: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+// There is no stdin or stdout to serve over in WebAssembly
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,664 @@
+use crate::hd_script_parser::*;
+use crate::answers::{ AnswerSet, Date, Value };
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::interpreter::Interpreter;
+use crate::interval::Interval;
+use crate::preferences::LibraryPreferences;
+use crate::formats;
+use crate::answer_file;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Conditions that expand to more alternatives than this are only partly explored
+const MAX_ALTERNATIVES: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Branch {
+    pub condition: String,
+    // Whether this is the branch run when the condition holds, or the ELSE branch
+    pub holds: bool,
+    pub span: Option<Span>,
+    pub covered: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TestCase {
+    pub answers: AnswerSet,
+    pub result: Value,
+    // The branches the answers run, as indices into the coverage's branches
+    pub branches: Vec<usize>,
+    // The same answers as a HotDocs answer file, for regression runs
+    pub answer_file: String
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Coverage {
+    pub computation: String,
+    pub branches: Vec<Branch>,
+    pub cases: Vec<TestCase>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    // A variable compared with a constant
+    Compare(String, BinaryOperation, Value),
+    Answered(String, bool),
+    // A condition the solver doesn't understand, the interpreter decides whether the answers satisfy it
+    Opaque
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Formula {
+    All(Vec<Formula>),
+    Any(Vec<Formula>),
+    Atom(Atom),
+    Constant(bool)
+}
+
+// What has to hold for a statement to run, the REPEATs only run when their dialog has been answered at least once
+#[derive(Clone)]
+enum Requirement<'s, 'a> {
+    Condition(&'s Expression<'a>, bool),
+    Repeat(String)
+}
+
+struct Target<'s, 'a> {
+    path: Vec<Requirement<'s, 'a>>
+}
+
+fn negate(op: &BinaryOperation) -> Option<BinaryOperation> {
+    match op {
+        BinaryOperation::EQ => Some(BinaryOperation::NE),
+        BinaryOperation::NE => Some(BinaryOperation::EQ),
+        BinaryOperation::GT => Some(BinaryOperation::LTE),
+        BinaryOperation::GTE => Some(BinaryOperation::LT),
+        BinaryOperation::LT => Some(BinaryOperation::GTE),
+        BinaryOperation::LTE => Some(BinaryOperation::GT),
+        _ => None
+    }
+}
+
+fn describe(atom: &Atom, preferences: &LibraryPreferences) -> String {
+    match atom {
+        Atom::Compare(name, op, Value::Text(t)) => format!("{} {} \"{}\"", name, op, t),
+        Atom::Compare(name, op, value) => format!("{} {} {}", name, op, formats::format_value(value, None, preferences)),
+        Atom::Answered(name, true) => format!("ANSWERED({})", name),
+        Atom::Answered(name, false) => format!("NOT ANSWERED({})", name),
+        Atom::Opaque => String::new()
+    }
+}
+
+// Expands a formula into alternatives that each need all of their atoms to hold
+fn alternatives(formula: &Formula) -> Vec<Vec<Atom>> {
+    match formula {
+        Formula::Constant(true) => vec![vec![]],
+        Formula::Constant(false) => vec![],
+        Formula::Atom(atom) => vec![vec![atom.clone()]],
+        Formula::Any(formulas) => formulas.iter().flat_map(alternatives).take(MAX_ALTERNATIVES).collect(),
+        Formula::All(formulas) => formulas.iter().fold(vec![vec![]], |acc, f| {
+            let next = alternatives(f);
+            acc.iter()
+                .flat_map(|a| next.iter().map(move |b| a.iter().chain(b.iter()).cloned().collect::<Vec<_>>()))
+                .take(MAX_ALTERNATIVES)
+                .collect()
+        })
+    }
+}
+
+#[derive(Default)]
+struct Bounds {
+    interval: Interval,
+    equal: Option<f64>,
+    not_equal: Vec<f64>
+}
+
+impl Bounds {
+    fn add(&mut self, op: &BinaryOperation, n: f64) {
+        match op {
+            // Two different values can't both be equal, NaN allows no value at all
+            BinaryOperation::EQ => self.equal = match self.equal {
+                Some(e) if e != n => Some(f64::NAN),
+                _ => Some(n)
+            },
+            BinaryOperation::NE => self.not_equal.push(n),
+            op => {
+                if let Some(interval) = Interval::of(op, n) {
+                    self.interval = self.interval.and(&interval);
+                }
+            }
+        }
+    }
+
+    fn allows(&self, n: f64) -> bool {
+        self.interval.contains(n)
+            && !self.not_equal.contains(&n)
+            && self.equal.is_none_or(|e| e == n)
+    }
+
+    // Prefers whole numbers near the bounds, as those are the answers people would try
+    fn solve(&self, default: f64) -> Option<f64> {
+        let mut candidates = vec![];
+        if let Some(e) = self.equal {
+            candidates.push(e);
+        }
+        if let Some((l, strict)) = self.interval.lower {
+            candidates.push(if strict { l.floor() + 1.0 } else { l.ceil() });
+        }
+        if let Some((u, strict)) = self.interval.upper {
+            candidates.push(if strict { u.ceil() - 1.0 } else { u.floor() });
+        }
+        if let (Some((l, _)), Some((u, _))) = (self.interval.lower, self.interval.upper) {
+            candidates.push((l + u) / 2.0);
+        }
+        candidates.push(default);
+
+        let stepped: Vec<f64> = candidates.iter().flat_map(|c| (0..=self.not_equal.len()).map(move |i| c + i as f64)).collect();
+        stepped.into_iter().find(|n| self.allows(*n))
+    }
+}
+
+struct Generator<'s, 'a> {
+    computation: &'s str,
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    options: &'s HashMap<String, Vec<String>>,
+    preferences: &'s LibraryPreferences,
+    today: Date,
+    // The variables the script changes, conditions on them can't be proven impossible from the answers alone
+    assigned: HashSet<String>
+}
+
+impl<'s, 'a> Generator<'s, 'a> {
+    fn interpreter(&self, answers: AnswerSet) -> Interpreter<'s, 'a> {
+        Interpreter::new(self.components, self.scripts, self.preferences, answers, self.today)
+    }
+
+    fn uses_variables(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Literal(_) => false,
+            Expression::Variable(v) => v.name.trim() != "TODAY",
+            Expression::FunctionCall(call) => self.scripts.contains_key(call.name.trim()) || call.args.iter().any(|a| self.uses_variables(a)),
+            Expression::Binary(b) => self.uses_variables(&b.left) || b.right.iter().any(|(_, e)| self.uses_variables(e)),
+            Expression::Unary(u) => self.uses_variables(&u.expression),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.uses_variables(e)
+        }
+    }
+
+    fn constant(&self, expression: &Expression) -> Option<Value> {
+        if self.uses_variables(expression) {
+            return None;
+        }
+        self.interpreter(AnswerSet::new()).value_of(expression).ok().filter(Value::is_answered)
+    }
+
+    // A variable the solver can give an answer to
+    fn input(&self, expression: &Expression) -> Option<String> {
+        match expression {
+            Expression::Variable(v) if v.indexer.is_none() => {
+                let name = v.name.trim();
+                match self.components.get(name) {
+                    Some(Component::Computation(_)) | Some(Component::Dialog { .. }) | None => None,
+                    Some(_) => Some(name.to_owned())
+                }
+            },
+            _ => None
+        }
+    }
+
+    fn comparison(&self, left: &Expression, op: &BinaryOperation, right: &Expression, holds: bool) -> Formula {
+        let atom = match (self.input(left), self.constant(right), self.input(right), self.constant(left)) {
+            (Some(name), Some(value), _, _) => Some((name, op.clone(), value)),
+            (_, _, Some(name), Some(value)) => op.flipped().map(|op| (name, op, value)),
+            _ => self.age_comparison(left, op, right)
+        };
+
+        match atom {
+            Some((name, op, value)) if holds => Formula::Atom(Atom::Compare(name, op, value)),
+            Some((name, op, value)) => match negate(&op) {
+                Some(op) => Formula::Atom(Atom::Compare(name, op, value)),
+                None => Formula::Atom(Atom::Opaque)
+            },
+            None => Formula::Atom(Atom::Opaque)
+        }
+    }
+
+    // AGE(Date of Birth) > 60 is a common condition, it holds for birth dates on or before a date 61 years ago
+    fn age_comparison(&self, left: &Expression, op: &BinaryOperation, right: &Expression) -> Option<(String, BinaryOperation, Value)> {
+        let (name, years) = match (left, self.constant(right)) {
+            (Expression::FunctionCall(call), Some(Value::Number(years))) if call.name.trim() == "AGE" && call.args.len() == 1 => (self.input(&call.args[0])?, years.floor() as i64),
+            _ => return None
+        };
+        let years_ago = |years: i64| Value::Date(self.today.add_months(-12 * years));
+
+        match op {
+            BinaryOperation::GTE => Some((name, BinaryOperation::LTE, years_ago(years))),
+            BinaryOperation::GT => Some((name, BinaryOperation::LTE, years_ago(years + 1))),
+            BinaryOperation::LT => Some((name, BinaryOperation::GT, years_ago(years))),
+            BinaryOperation::LTE => Some((name, BinaryOperation::GT, years_ago(years + 1))),
+            _ => None
+        }
+    }
+
+    fn formula(&self, expression: &Expression, holds: bool) -> Formula {
+        match expression {
+            Expression::Literal(LiteralExpression::Boolean(b)) => Formula::Constant(*b == holds),
+            Expression::Unary(u) if u.operation == UnaryOperation::Not => self.formula(&u.expression, !holds),
+            Expression::Variable(_) => match self.input(expression) {
+                Some(name) => Formula::Atom(Atom::Compare(name, BinaryOperation::EQ, Value::TrueFalse(holds))),
+                None => Formula::Atom(Atom::Opaque)
+            },
+            Expression::FunctionCall(call) if call.name.trim() == "ANSWERED" && call.args.len() == 1 => match self.input(&call.args[0]) {
+                Some(name) => Formula::Atom(Atom::Answered(name, holds)),
+                None => Formula::Atom(Atom::Opaque)
+            },
+            // The operators of a binary expression apply left to right, so all but the last one form its left operand
+            Expression::Binary(b) if b.right.len() > 1 => {
+                let (last, init) = b.right.split_last().unwrap();
+                let left = Expression::Binary(Box::new(BinaryExpression { left: b.left.clone(), right: init.to_vec() }));
+                self.formula(&Expression::Binary(Box::new(BinaryExpression { left, right: vec![last.clone()] })), holds)
+            },
+            Expression::Binary(b) => match &b.right[0] {
+                (op, right) if op.is_comparison() || matches!(op, BinaryOperation::Contains | BinaryOperation::StartsWith | BinaryOperation::EndsWith) => self.comparison(&b.left, op, right, holds),
+                // AND and OR swap places when the whole condition has to fail
+                (op, right) if *op == BinaryOperation::And || *op == BinaryOperation::Or => {
+                    let both = vec![self.formula(&b.left, holds), self.formula(right, holds)];
+                    if (*op == BinaryOperation::And) == holds { Formula::All(both) } else { Formula::Any(both) }
+                },
+                _ => Formula::Atom(Atom::Opaque)
+            },
+            _ => Formula::Atom(Atom::Opaque)
+        }
+    }
+
+    fn default_value(&self, name: &str) -> Value {
+        match self.components.get(name) {
+            Some(Component::Number(_)) => Value::Number(0.0),
+            Some(Component::Date(_)) => Value::Date(self.today),
+            Some(Component::TrueFalse(_)) => Value::TrueFalse(true),
+            Some(Component::MultipleChoice(_)) => Value::MultipleChoice(vec![self.options.get(name).and_then(|o| o.first()).cloned().unwrap_or_else(|| String::from("Other"))]),
+            _ => Value::Text(String::from("a"))
+        }
+    }
+
+    fn solve_variable(&self, name: &str, atoms: &[&Atom]) -> Result<Option<Value>, String> {
+        let contradiction = || {
+            let conditions: Vec<String> = atoms.iter().map(|a| describe(a, self.preferences)).collect();
+            format!("{} can't all hold", conditions.join(" and "))
+        };
+
+        let unanswered = atoms.iter().any(|a| **a == Atom::Answered(name.to_owned(), false));
+        let compares: Vec<(&BinaryOperation, &Value)> = atoms.iter().filter_map(|a| match a {
+            Atom::Compare(_, op, value) => Some((op, value)),
+            _ => None
+        }).collect();
+        let answered = !compares.is_empty() || atoms.iter().any(|a| **a == Atom::Answered(name.to_owned(), true));
+
+        match (unanswered, answered) {
+            (true, true) => return Err(contradiction()),
+            (true, false) => return Ok(None),
+            _ => ()
+        }
+
+        let value = match self.components.get(name) {
+            Some(Component::Number(_)) | Some(Component::Date(_)) => {
+                let is_date = matches!(self.components.get(name), Some(Component::Date(_)));
+                let mut bounds = Bounds::default();
+                for (op, value) in compares.iter() {
+                    match value {
+                        Value::Number(n) if !is_date => bounds.add(op, *n),
+                        Value::Date(d) if is_date => bounds.add(op, d.to_days() as f64),
+                        _ => ()
+                    }
+                }
+                let default = if is_date { self.today.to_days() as f64 } else { 0.0 };
+                match bounds.solve(default) {
+                    Some(n) if is_date => Value::Date(Date::from_days(n as i64)),
+                    Some(n) => Value::Number(n),
+                    None => return Err(contradiction())
+                }
+            },
+            Some(Component::TrueFalse(_)) => {
+                let wanted: HashSet<bool> = compares.iter().filter_map(|(op, value)| match (op, value) {
+                    (BinaryOperation::EQ, Value::TrueFalse(b)) => Some(*b),
+                    (BinaryOperation::NE, Value::TrueFalse(b)) => Some(!*b),
+                    _ => None
+                }).collect();
+                match wanted.len() {
+                    0 => Value::TrueFalse(true),
+                    1 => Value::TrueFalse(*wanted.iter().next().unwrap()),
+                    _ => return Err(contradiction())
+                }
+            },
+            Some(Component::MultipleChoice(_)) => {
+                let mut selected = vec![];
+                let mut excluded = vec![];
+                for (op, value) in compares.iter() {
+                    match (op, value) {
+                        (BinaryOperation::EQ, Value::Text(t)) | (BinaryOperation::Contains, Value::Text(t)) => selected.push(t.to_owned()),
+                        (BinaryOperation::NE, Value::Text(t)) => excluded.push(t.to_lowercase()),
+                        _ => ()
+                    }
+                }
+                if selected.iter().any(|s| excluded.contains(&s.to_lowercase())) {
+                    return Err(contradiction());
+                }
+                if selected.is_empty() {
+                    let options = self.options.get(name).cloned().unwrap_or_default();
+                    let option = options.into_iter().chain(std::iter::once(String::from("Other"))).find(|o| !excluded.contains(&o.to_lowercase()));
+                    selected.extend(option);
+                }
+                selected.dedup();
+                Value::MultipleChoice(selected)
+            },
+            _ => {
+                let texts: Vec<(&BinaryOperation, &String)> = compares.iter().filter_map(|(op, value)| match value {
+                    Value::Text(t) => Some((*op, t)),
+                    _ => None
+                }).collect();
+                // Text comparisons ignore case, but the answers keep the case the script used
+                let equal: HashSet<String> = texts.iter().filter(|(op, _)| **op == BinaryOperation::EQ).map(|(_, t)| t.to_lowercase()).collect();
+                let text = match (equal.len(), texts.iter().find(|(op, _)| **op == BinaryOperation::EQ)) {
+                    (0, _) => {
+                        let part = |wanted: BinaryOperation| texts.iter().filter(|(op, _)| **op == wanted).map(|(_, t)| t.as_str()).collect::<String>();
+                        let text = format!("{}{}{}", part(BinaryOperation::StartsWith), part(BinaryOperation::Contains), part(BinaryOperation::EndsWith));
+                        if text.is_empty() { String::from("a") } else { text }
+                    },
+                    (1, Some((_, text))) => text.to_string(),
+                    _ => return Err(contradiction())
+                };
+                let text = (0..=texts.len()).map(|i| format!("{}{}", text, "x".repeat(i))).find(|t| {
+                    let t = t.to_lowercase();
+                    texts.iter().all(|(op, other)| {
+                        let other = other.to_lowercase();
+                        match op {
+                            BinaryOperation::EQ => t == other,
+                            BinaryOperation::NE => t != other,
+                            BinaryOperation::Contains => t.contains(&other),
+                            BinaryOperation::StartsWith => t.starts_with(&other),
+                            BinaryOperation::EndsWith => t.ends_with(&other),
+                            _ => true
+                        }
+                    })
+                });
+                match text {
+                    Some(text) => Value::Text(text),
+                    None => return Err(contradiction())
+                }
+            }
+        };
+
+        Ok(Some(value))
+    }
+
+    // Finds answers for one alternative, or explains why its conditions can't all hold
+    fn solve(&self, atoms: &[Atom], path: &[Requirement]) -> Result<AnswerSet, String> {
+        let mut by_variable: BTreeMap<&str, Vec<&Atom>> = BTreeMap::new();
+        for atom in atoms.iter() {
+            match atom {
+                Atom::Compare(name, _, _) | Atom::Answered(name, _) => by_variable.entry(name).or_default().push(atom),
+                Atom::Opaque => ()
+            }
+        }
+
+        // Variables on a repeated dialog are answered in the first iteration of each REPEAT around them
+        let repeats: Vec<&String> = path.iter().filter_map(|r| match r {
+            Requirement::Repeat(dialog) => Some(dialog),
+            _ => None
+        }).collect();
+        let index_of = |name: &str| {
+            let depth = repeats.iter().rposition(|dialog| match self.components.get(dialog.as_str()) {
+                Some(Component::Dialog { children, .. }) => children.iter().any(|c| c == name),
+                _ => false
+            });
+            vec![0; depth.map_or(0, |d| d + 1)]
+        };
+
+        let mut answers = AnswerSet::new();
+        for (name, atoms) in by_variable.iter() {
+            if let Some(value) = self.solve_variable(name, atoms)? {
+                answers.set(name, index_of(name), value);
+            }
+        }
+
+        for (depth, dialog) in repeats.iter().enumerate() {
+            let children = match self.components.get(dialog.as_str()) {
+                Some(Component::Dialog { children, .. }) => children,
+                _ => continue
+            };
+            if let Some(child) = children.first().filter(|_| children.iter().all(|c| answers.iterations(c, &vec![0; depth]) == 0)) {
+                answers.set(child, vec![0; depth + 1], self.default_value(child));
+            }
+        }
+
+        Ok(answers)
+    }
+
+    fn involves_assigned(&self, atoms: &[Atom]) -> bool {
+        atoms.iter().any(|a| match a {
+            Atom::Compare(name, _, _) | Atom::Answered(name, _) => self.assigned.contains(name),
+            Atom::Opaque => false
+        })
+    }
+}
+
+fn collect_assigned(statements: &[Spanned<Statement>], assigned: &mut HashSet<String>) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::If(s) => {
+                collect_assigned(&s.then_body, assigned);
+                collect_assigned(&s.else_body, assigned);
+            },
+            Statement::Loop(LoopStatement::While(s)) => collect_assigned(&s.body, assigned),
+            Statement::Loop(LoopStatement::Repeat(s)) => collect_assigned(&s.body, assigned),
+            Statement::Instruction(Instruction::Script(
+                ScriptInstruction::Set(v, _) | ScriptInstruction::Add(v, _) | ScriptInstruction::Default(v, _)
+                | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) | ScriptInstruction::Erase(v)
+            )) => {
+                assigned.insert(v.name.trim().to_owned());
+            },
+            _ => ()
+        }
+    }
+}
+
+fn collect_branches<'s, 'a>(
+    source: &'a str,
+    statements: &'s [Spanned<'a, Statement<'a>>],
+    path: &mut Vec<Requirement<'s, 'a>>,
+    branches: &mut Vec<Branch>,
+    targets: &mut Vec<Target<'s, 'a>>,
+    sources: &mut HashMap<usize, usize>
+) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::If(s) => {
+                sources.insert(statement.source.as_ptr() as usize, branches.len());
+                for (holds, body) in [(true, &s.then_body), (false, &s.else_body)].iter() {
+                    branches.push(Branch { condition: s.condition.to_string(), holds: *holds, span: Span::of(source, statement.source), covered: false });
+                    path.push(Requirement::Condition(&s.condition, *holds));
+                    targets.push(Target { path: path.clone() });
+                    collect_branches(source, body, path, branches, targets, sources);
+                    path.pop();
+                }
+            },
+            Statement::Loop(LoopStatement::While(s)) => {
+                path.push(Requirement::Condition(&s.condition, true));
+                collect_branches(source, &s.body, path, branches, targets, sources);
+                path.pop();
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                path.push(Requirement::Repeat(s.dialog.name.trim().to_owned()));
+                collect_branches(source, &s.body, path, branches, targets, sources);
+                path.pop();
+            },
+            _ => ()
+        }
+    }
+}
+
+// Finds answers that run each branch of the IF statements in the computation, and the branches no answers can run
+pub fn generate(
+    computation: &str,
+    components: &HashMap<String, Component>,
+    scripts: &HashMap<&str, Script>,
+    options: &HashMap<String, Vec<String>>,
+    preferences: &LibraryPreferences,
+    today: Date
+) -> Coverage {
+    let script = match scripts.get(computation) {
+        Some(script) => script,
+        None => {
+            let diagnostic = Diagnostic::error(format!("{} is not a computation with a script", computation));
+            return Coverage { computation: computation.to_owned(), branches: vec![], cases: vec![], diagnostics: vec![diagnostic] };
+        }
+    };
+
+    let mut assigned = HashSet::new();
+    collect_assigned(&script.body, &mut assigned);
+    let generator = Generator { computation, components, scripts, options, preferences, today, assigned };
+
+    let mut branches = vec![];
+    let mut targets = vec![];
+    let mut sources = HashMap::new();
+    collect_branches(script.source, &script.body, &mut vec![], &mut branches, &mut targets, &mut sources);
+
+    let mut cases = vec![];
+    let mut diagnostics = vec![];
+
+    for (i, target) in targets.iter().enumerate() {
+        if branches[i].covered {
+            continue;
+        }
+
+        let formula = Formula::All(target.path.iter().filter_map(|r| match r {
+            Requirement::Condition(e, holds) => Some(generator.formula(e, *holds)),
+            Requirement::Repeat(_) => None
+        }).collect());
+
+        let mut reasons = vec![];
+        let mut provable = true;
+        let options = alternatives(&formula);
+        for atoms in options.iter() {
+            let answers = match generator.solve(atoms, &target.path) {
+                Ok(answers) => answers,
+                Err(reason) => {
+                    provable &= !generator.involves_assigned(atoms);
+                    reasons.push(reason);
+                    continue;
+                }
+            };
+
+            let (evaluation, taken) = generator.interpreter(answers.clone()).run_traced(generator.computation);
+            let covers: Vec<usize> = taken
+                .iter()
+                .filter_map(|(source, holds)| sources.get(&(source.as_ptr() as usize)).map(|b| b + if *holds { 0 } else { 1 }))
+                .collect::<std::collections::BTreeSet<_>>()
+                .into_iter()
+                .collect();
+
+            if covers.iter().any(|b| !branches[*b].covered) {
+                for b in covers.iter() {
+                    branches[*b].covered = true;
+                }
+                let title = format!("{} branch coverage {}", computation, cases.len() + 1);
+                let answer_file = answer_file::write(&answers, Some(&title));
+                cases.push(TestCase { answers, result: evaluation.result, branches: covers, answer_file });
+            }
+            if branches[i].covered {
+                break;
+            }
+        }
+
+        if branches[i].covered {
+            continue;
+        }
+        let branch = &branches[i];
+        let what = if branch.holds { format!("IF {}", branch.condition) } else { format!("the ELSE of IF {}", branch.condition) };
+        let diagnostic = if options.is_empty() || (provable && reasons.len() == options.len()) {
+            let reason = if reasons.is_empty() { String::from("its conditions are always false") } else { reasons.join(", or ") };
+            Diagnostic::warning(format!("{} can never run: {}", what, reason))
+        } else {
+            Diagnostic::warning(format!("No answers were found that run {}", what))
+        };
+        diagnostics.push(diagnostic.for_component(computation).at(branch.span));
+    }
+
+    Coverage { computation: computation.to_owned(), branches, cases, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+
+    fn coverage(script: &str, components: HashMap<String, Component>) -> Coverage {
+        let (_, ast) = hd_script_parser::parse(script).unwrap();
+        let scripts: HashMap<&str, Script> = vec![("Test", ast)].into_iter().collect();
+        generate("Test", &components, &scripts, &HashMap::new(), &LibraryPreferences::default(), Date::new(2020, 6, 15).unwrap())
+    }
+
+    fn number(name: &str) -> (String, Component) {
+        (name.to_owned(), Component::Number(HashSet::new()))
+    }
+
+    #[test]
+    fn finds_answers_for_both_branches_of_an_if() {
+        let script = "IF Age > 17 AND Age <= 65\n\"Adult\"\nELSE\n\"Other\"\nEND IF";
+        let coverage = coverage(script, vec![number("Age")].into_iter().collect());
+
+        assert!(coverage.diagnostics.is_empty(), "{:?}", coverage.diagnostics);
+        assert!(coverage.branches.iter().all(|b| b.covered));
+        let results: Vec<&Value> = coverage.cases.iter().map(|c| &c.result).collect();
+        assert!(results.contains(&&Value::Text(String::from("Adult"))));
+        assert!(results.contains(&&Value::Text(String::from("Other"))));
+        for case in coverage.cases.iter() {
+            if let Value::Number(age) = case.answers.get("Age", &[]) {
+                let adult = age > 17.0 && age <= 65.0;
+                assert_eq!(case.result, Value::Text(String::from(if adult { "Adult" } else { "Other" })));
+            }
+        }
+    }
+
+    #[test]
+    fn reports_branches_that_can_never_run() {
+        let script = "IF Age > 10 AND Age < 5\n\"Never\"\nEND IF";
+        let coverage = coverage(script, vec![number("Age")].into_iter().collect());
+
+        assert!(coverage.branches[0].holds && !coverage.branches[0].covered);
+        assert_eq!(coverage.diagnostics.len(), 1);
+        assert!(coverage.diagnostics[0].message.contains("can never run"), "{}", coverage.diagnostics[0].message);
+    }
+
+    #[test]
+    fn finds_answers_for_every_link_of_an_else_if_chain() {
+        let script = "IF Age > 65\n\"Senior\"\nELSE IF Age > 17\n\"Adult\"\nELSE\n\"Child\"\nEND IF";
+        let coverage = coverage(script, vec![number("Age")].into_iter().collect());
+
+        assert!(coverage.diagnostics.is_empty(), "{:?}", coverage.diagnostics);
+        assert_eq!(coverage.branches.len(), 4);
+        assert!(coverage.branches.iter().all(|b| b.covered), "{:?}", coverage.branches);
+        for case in coverage.cases.iter() {
+            let expected = match case.answers.get("Age", &[]) {
+                Value::Number(age) if age > 65.0 => "Senior",
+                Value::Number(age) if age > 17.0 => "Adult",
+                _ => "Child"
+            };
+            assert_eq!(case.result, Value::Text(String::from(expected)));
+        }
+    }
+
+    #[test]
+    fn answers_branches_inside_nested_repeats() {
+        let dialog = |name: &str, child: &str| (name.to_owned(), Component::Dialog { children: vec![child.to_owned()], script: HashSet::new(), fields: HashSet::new() });
+        let components = vec![dialog("Children", "Child Name"), dialog("Grandchildren", "Grandchild Age"), number("Grandchild Age"), (String::from("Child Name"), Component::Text(HashSet::new()))];
+        let script = "REPEAT Children\nREPEAT Grandchildren\nIF Grandchild Age > 10\nADD \"Older\" TO Result\nEND IF\nEND REPEAT\nEND REPEAT";
+        let coverage = coverage(script, components.into_iter().collect());
+
+        assert!(coverage.diagnostics.is_empty(), "{:?}", coverage.diagnostics);
+        assert!(coverage.branches.iter().all(|b| b.covered), "{:?}", coverage.branches);
+        assert!(coverage.cases.iter().any(|c| matches!(c.answers.get("Grandchild Age", &[0, 0]), Value::Number(age) if age > 10.0)));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::type_checker::Type::{ self, * };
+
+// A built in function with the types of its arguments and result, unknown arguments take any type
+pub struct Function {
+    pub name: &'static str,
+    pub parameters: &'static [Type],
+    pub result: Type
+}
+
+const fn function(name: &'static str, parameters: &'static [Type], result: Type) -> Function {
+    Function { name, parameters, result }
+}
+
+// Every built in function. The interpreter, type checker, highlighter and completion all read this table.
+pub const FUNCTIONS: [Function; 30] = [
+    function("ANSWERED", &[Unknown], TrueFalse),
+    function("ZERO", &[Number], Number),
+    function("LENGTH", &[Text], Number),
+    function("FIRST", &[Text, Number], Text),
+    function("LAST", &[Text, Number], Text),
+    function("MID", &[Text, Number, Number], Text),
+    function("POSITION", &[Text, Text], Number),
+    function("CAPITALIZE", &[Text], Text),
+    function("STRIP", &[Text], Text),
+    function("INTEGER", &[Text], Number),
+    function("FORMAT", &[Unknown, Text], Text),
+    function("ROUND", &[Number, Number], Number),
+    function("TRUNCATE", &[Number, Number], Number),
+    function("REMAINDER", &[Number, Number], Number),
+    function("POWER", &[Number, Number], Number),
+    function("MAX", &[Number, Number], Number),
+    function("MIN", &[Number, Number], Number),
+    function("ABSOLUTE VALUE", &[Number], Number),
+    function("SQUARE ROOT", &[Number], Number),
+    function("DATE OF", &[Number, Number, Number], Date),
+    function("DAY OF", &[Date], Number),
+    function("MONTH OF", &[Date], Number),
+    function("YEAR OF", &[Date], Number),
+    function("DAY OF WEEK", &[Date], Number),
+    function("AGE", &[Date], Number),
+    function("DAYS FROM", &[Date, Date], Number),
+    function("MONTHS FROM", &[Date, Date], Number),
+    function("YEARS FROM", &[Date, Date], Number),
+    function("SELECTION", &[MultipleChoice, Number], Text),
+    function("COUNT", &[Dialog], Number)
+];
+
+// Names the parser reads as variables that are built into HotDocs
+pub const CONSTANTS: [(&str, Type); 3] = [("TODAY", Date), ("COUNTER", Number), ("UNANSWERED", Unknown)];
+
+// The words that start a statement
+pub const STATEMENTS: [&str; 18] = [
+    "IF", "SET", "ASK", "REPEAT", "WHILE", "DEFAULT", "ADD", "ERASE", "INCREMENT", "DECREMENT", "GRAY", "UNGRAY", "SHOW",
+    "HIDE", "REQUIRE", "LIMIT", "QUIT", "ASSEMBLE"
+];
+
+// The rest of the words the parser reads as part of a statement or a date interval
+pub const KEYWORDS: [&str; 10] = ["ELSE", "END", "TO", "ALL", "DAYS", "MONTHS", "YEARS", "DAY", "MONTH", "YEAR"];
+
+pub const OPERATOR_WORDS: [&str; 7] = ["AND", "OR", "NOT", "CONTAINS", "STARTS", "ENDS", "WITH"];
+
+pub fn function_named(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+pub fn is_function(name: &str) -> bool {
+    function_named(name).is_some()
+}
+
+pub fn constant(name: &str) -> Option<Type> {
+    CONSTANTS.iter().find(|(constant, _)| *constant == name).map(|(_, t)| *t)
+}
+
+pub fn is_keyword(word: &str) -> bool {
+    STATEMENTS.contains(&word) || KEYWORDS.contains(&word)
+}
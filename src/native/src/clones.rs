@@ -0,0 +1,387 @@
+use crate::hd_script_parser::*;
+use crate::builtins;
+use crate::diagnostics::{ Diagnostic, Span };
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct CloneOptions {
+    // How alike two scripts must be to be near-clones, from 0 to 1
+    pub threshold: f64,
+    // Scripts with fewer statements than this are too small to be worth consolidating
+    pub min_statements: usize
+}
+
+impl Default for CloneOptions {
+    fn default() -> Self {
+        CloneOptions { threshold: 0.8, min_statements: 3 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum CloneKind {
+    // The same script apart from comments and whitespace
+    Exact,
+    // The same script apart from the names of the variables it uses
+    Renamed,
+    // Scripts that share most of their statements once variables are renamed
+    Near
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloneMember {
+    pub component: String,
+    // The statements of this script that differ from the rest of the group
+    pub differences: Vec<Span>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloneGroup {
+    pub kind: CloneKind,
+    // The lowest similarity between the first member and the others
+    pub similarity: f64,
+    pub members: Vec<CloneMember>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CloneReport {
+    pub groups: Vec<CloneGroup>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// Gives variables placeholder names in the order the script first uses them, or keeps their names
+struct Names {
+    renamed: Option<HashMap<String, usize>>
+}
+
+impl Names {
+    fn get(&mut self, name: &str) -> String {
+        let name = name.trim();
+        match &mut self.renamed {
+            Some(renamed) if builtins::constant(name).is_none() => {
+                let next = renamed.len() + 1;
+                format!("v{}", renamed.entry(name.to_owned()).or_insert(next))
+            },
+            _ => name.to_owned()
+        }
+    }
+}
+
+fn normalize_variable(v: &VariableReference, names: &mut Names) -> String {
+    let name = names.get(v.name);
+    match &v.indexer {
+        Some(indexer) => format!("{}[{}]", name, indexer.args.iter().map(|a| normalize(a, names)).collect::<Vec<_>>().join(", ")),
+        None => name
+    }
+}
+
+// Writes an expression fully parenthesized, so expressions that only differ in layout are written the same way
+fn normalize(expression: &Expression, names: &mut Names) -> String {
+    match expression {
+        Expression::Variable(v) => normalize_variable(v, names),
+        Expression::Literal(_) => expression.to_string(),
+        Expression::FunctionCall(call) => {
+            format!("{}({})", call.name.trim(), call.args.iter().map(|a| normalize(a, names)).collect::<Vec<_>>().join(", "))
+        },
+        Expression::Binary(b) => b.right.iter().fold(normalize(&b.left, names), |acc, (op, e)| format!("({} {} {})", acc, op, normalize(e, names))),
+        Expression::Unary(u) => match u.operation {
+            UnaryOperation::Not => format!("NOT {}", normalize(&u.expression, names)),
+            UnaryOperation::Negate => format!("-{}", normalize(&u.expression, names))
+        },
+        Expression::Days(e) => format!("{} DAYS", normalize(e, names)),
+        Expression::Months(e) => format!("{} MONTHS", normalize(e, names)),
+        Expression::Years(e) => format!("{} YEARS", normalize(e, names))
+    }
+}
+
+fn normalize_instruction(instruction: &Instruction, names: &mut Names) -> String {
+    match instruction {
+        Instruction::Script(instruction) => match instruction {
+            ScriptInstruction::Set(v, e) => format!("SET {} TO {}", normalize_variable(v, names), normalize(e, names)),
+            ScriptInstruction::Add(v, e) => format!("ADD {} TO {}", normalize(e, names), normalize_variable(v, names)),
+            ScriptInstruction::Default(v, e) => format!("DEFAULT {} TO {}", normalize_variable(v, names), normalize(e, names)),
+            ScriptInstruction::Increment(v) => format!("INCREMENT {}", normalize_variable(v, names)),
+            ScriptInstruction::Decrement(v) => format!("DECREMENT {}", normalize_variable(v, names)),
+            ScriptInstruction::Erase(v) => format!("ERASE {}", normalize_variable(v, names)),
+            ScriptInstruction::Assemble(v) => format!("ASSEMBLE {}", normalize_variable(v, names)),
+            instruction => format!("{:?}", instruction).to_uppercase()
+        },
+        Instruction::Display(instruction) => match instruction {
+            DisplayInstruction::Ask(n) => format!("ASK {}", names.get(n)),
+            DisplayInstruction::Gray(n) => format!("GRAY {}", names.get(n)),
+            DisplayInstruction::Ungray(n) => format!("UNGRAY {}", names.get(n)),
+            DisplayInstruction::Hide(n) => format!("HIDE {}", names.get(n)),
+            DisplayInstruction::Show(n) => format!("SHOW {}", names.get(n)),
+            DisplayInstruction::Require(n) => format!("REQUIRE {}", names.get(n)),
+            DisplayInstruction::Limit(LimitExpression::Number(n)) => format!("LIMIT {}", n),
+            DisplayInstruction::Limit(LimitExpression::Variable(n)) => format!("LIMIT {}", names.get(n)),
+            DisplayInstruction::Limit(LimitExpression::Function(call)) => format!("LIMIT {}", normalize(&Expression::FunctionCall(call.clone()), names)),
+            instruction => format!("{:?}", instruction).to_uppercase()
+        }
+    }
+}
+
+// A statement of a script written without comments, layout or, when renamed, variable names. Statements with a body
+// are split into a line for each of their parts, so a near-clone that changes a body still shares the rest.
+struct Line<'a> {
+    text: String,
+    source: Option<&'a str>
+}
+
+fn lines<'a>(statements: &[Spanned<'a, Statement<'a>>], names: &mut Names, out: &mut Vec<Line<'a>>) {
+    for statement in statements.iter() {
+        let source = Some(statement.first_line());
+        match &statement.node {
+            Statement::Comment(_) => (),
+            Statement::If(s) => {
+                out.push(Line { text: format!("IF {}", normalize(&s.condition, names)), source });
+                lines(&s.then_body, names, out);
+                let mut else_body = &s.else_body;
+                // ELSE IF is written as the IF it nests in the ELSE
+                while let [link @ Spanned { node: Statement::If(next), .. }] = else_body.as_slice() {
+                    out.push(Line { text: format!("ELSE IF {}", normalize(&next.condition, names)), source: Some(link.first_line()) });
+                    lines(&next.then_body, names, out);
+                    else_body = &next.else_body;
+                }
+                if !else_body.is_empty() {
+                    out.push(Line { text: String::from("ELSE"), source: None });
+                    lines(else_body, names, out);
+                }
+                out.push(Line { text: String::from("END IF"), source: None });
+            },
+            Statement::Loop(LoopStatement::While(s)) => {
+                out.push(Line { text: format!("WHILE {}", normalize(&s.condition, names)), source });
+                lines(&s.body, names, out);
+                out.push(Line { text: String::from("END WHILE"), source: None });
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                out.push(Line { text: format!("REPEAT {}", normalize_variable(&s.dialog, names)), source });
+                lines(&s.body, names, out);
+                out.push(Line { text: String::from("END REPEAT"), source: None });
+            },
+            Statement::Instruction(instruction) => out.push(Line { text: normalize_instruction(instruction, names), source }),
+            Statement::Return(e) => out.push(Line { text: normalize(e, names), source })
+        }
+    }
+}
+
+struct Normalized<'a> {
+    name: &'a str,
+    source: &'a str,
+    exact: Vec<Line<'a>>,
+    renamed: Vec<Line<'a>>
+}
+
+impl Normalized<'_> {
+    fn span(&self, line: &Line) -> Option<Span> {
+        line.source.and_then(|s| Span::of(self.source, s))
+    }
+}
+
+fn texts<'l>(lines: &'l [Line]) -> Vec<&'l str> {
+    lines.iter().map(|l| l.text.as_str()).collect()
+}
+
+// Which lines of each side are part of their longest common subsequence
+fn common_lines(a: &[Line], b: &[Line]) -> (Vec<bool>, Vec<bool>) {
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a[i].text == b[j].text { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+
+    let (mut in_a, mut in_b) = (vec![false; a.len()], vec![false; b.len()]);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].text == b[j].text {
+            in_a[i] = true;
+            in_b[j] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (in_a, in_b)
+}
+
+fn similarity(a: &[Line], b: &[Line]) -> f64 {
+    let (in_a, in_b) = common_lines(a, b);
+    let common = in_a.iter().filter(|c| **c).count() + in_b.iter().filter(|c| **c).count();
+    common as f64 / (a.len() + b.len()).max(1) as f64
+}
+
+// The lines that differ from the first member of the group, and the first member's lines that differ from any member
+fn differences(scripts: &[&Normalized], kind: CloneKind) -> Vec<Vec<Span>> {
+    let first = scripts[0];
+    let mut first_differs = vec![false; first.renamed.len()];
+    let mut result = vec![vec![]];
+
+    for other in scripts[1..].iter() {
+        let (in_first, in_other) = match kind {
+            CloneKind::Exact => (vec![true; first.exact.len()], vec![true; other.exact.len()]),
+            // Renamed clones line up one to one, the lines that differ are the ones that use different names
+            CloneKind::Renamed => {
+                let same: Vec<bool> = first.exact.iter().zip(other.exact.iter()).map(|(a, b)| a.text == b.text).collect();
+                (same.clone(), same)
+            },
+            CloneKind::Near => common_lines(&first.renamed, &other.renamed)
+        };
+        for (i, common) in in_first.iter().enumerate() {
+            first_differs[i] |= !common;
+        }
+        result.push(other.renamed.iter().zip(in_other.iter()).filter(|(_, common)| !**common).filter_map(|(l, _)| other.span(l)).collect());
+    }
+
+    result[0] = first.renamed.iter().zip(first_differs.iter()).filter(|(_, differs)| **differs).filter_map(|(l, _)| first.span(l)).collect();
+    result
+}
+
+fn group(kind: CloneKind, members: &[&Normalized]) -> CloneGroup {
+    let similarity = match kind {
+        CloneKind::Near => members[1..].iter().map(|m| similarity(&members[0].renamed, &m.renamed)).fold(1.0, f64::min),
+        _ => 1.0
+    };
+    let members = members
+        .iter()
+        .zip(differences(members, kind))
+        .map(|(m, differences)| CloneMember { component: m.name.to_owned(), differences })
+        .collect();
+    CloneGroup { kind, similarity, members }
+}
+
+// Finds scripts that are copies of each other, exactly, with their variables renamed, or with small edits
+pub fn find(scripts: &HashMap<&str, Script>, options: &CloneOptions) -> CloneReport {
+    let mut names: Vec<&&str> = scripts.keys().collect();
+    names.sort();
+
+    let normalized: Vec<Normalized> = names
+        .into_iter()
+        .map(|name| {
+            let script = &scripts[*name];
+            let (mut exact, mut renamed) = (vec![], vec![]);
+            lines(&script.body, &mut Names { renamed: None }, &mut exact);
+            lines(&script.body, &mut Names { renamed: Some(HashMap::new()) }, &mut renamed);
+            Normalized { name, source: script.source, exact, renamed }
+        })
+        .filter(|n| n.exact.iter().filter(|l| l.source.is_some()).count() >= options.min_statements)
+        .collect();
+
+    // Each script belongs to at most one group, the closest kind of clone it has
+    let mut grouped = vec![false; normalized.len()];
+    let mut groups = vec![];
+
+    let mut by_text: HashMap<Vec<&str>, Vec<usize>> = HashMap::new();
+    for (i, n) in normalized.iter().enumerate() {
+        by_text.entry(texts(&n.renamed)).or_default().push(i);
+    }
+    let mut found: Vec<Vec<usize>> = by_text.into_values().filter(|members| members.len() > 1).collect();
+    found.sort();
+    for members in found {
+        let members: Vec<&Normalized> = members.iter().map(|i| {
+            grouped[*i] = true;
+            &normalized[*i]
+        }).collect();
+        let exact = members.iter().all(|m| texts(&m.exact) == texts(&members[0].exact));
+        groups.push(group(if exact { CloneKind::Exact } else { CloneKind::Renamed }, &members));
+    }
+
+    // Near-clones gather around the first script that is alike enough to each of them
+    for i in 0..normalized.len() {
+        if grouped[i] {
+            continue;
+        }
+        let members: Vec<usize> = std::iter::once(i)
+            .chain((i + 1..normalized.len()).filter(|j| !grouped[*j] && similarity(&normalized[i].renamed, &normalized[*j].renamed) >= options.threshold))
+            .collect();
+        if members.len() > 1 {
+            for j in members.iter() {
+                grouped[*j] = true;
+            }
+            groups.push(group(CloneKind::Near, &members.iter().map(|j| &normalized[*j]).collect::<Vec<_>>()));
+        }
+    }
+
+    let diagnostics = groups
+        .iter()
+        .flat_map(|g| {
+            let others: Vec<&str> = g.members.iter().map(|m| m.component.as_str()).collect();
+            let description = match g.kind {
+                CloneKind::Exact => String::from("a copy of"),
+                CloneKind::Renamed => String::from("a copy with renamed variables of"),
+                CloneKind::Near => format!("{:.0}% the same as", g.similarity * 100.0)
+            };
+            let first = others[0];
+            others[1..].iter().map(move |name| Diagnostic::warning(format!("The script is {} the script of {}", description, first)).for_component(name)).collect::<Vec<_>>()
+        })
+        .collect();
+
+    CloneReport { groups, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+
+    fn find_in(scripts: &[(&'static str, &'static str)], threshold: f64) -> CloneReport {
+        let scripts: HashMap<&str, Script> = scripts.iter().map(|(name, script)| (*name, hd_script_parser::parse(script).unwrap().1)).collect();
+        find(&scripts, &CloneOptions { threshold, ..CloneOptions::default() })
+    }
+
+    fn members(group: &CloneGroup) -> Vec<&str> {
+        group.members.iter().map(|m| m.component.as_str()).collect()
+    }
+
+    #[test]
+    fn identical_scripts_are_grouped_as_copies() {
+        let report = find_in(&[
+            ("First", "SET Total TO 0\nSET Count TO 1\nTotal + Count"),
+            ("Second", "// the same again\nSET Total TO 0\n  SET Count TO 1\nTotal + Count"),
+            ("Other", "SET Total TO 0\nSET Count TO 1\nTotal + Count\nASK Details")
+        ], 1.0);
+
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].kind, CloneKind::Exact);
+        assert_eq!(members(&report.groups[0]), vec!["First", "Second"]);
+        assert!(report.groups[0].members.iter().all(|m| m.differences.is_empty()));
+        let messages: Vec<(&str, Option<&str>)> = report.diagnostics.iter().map(|d| (d.message.as_str(), d.component.as_deref())).collect();
+        assert_eq!(messages, vec![("The script is a copy of the script of First", Some("Second"))]);
+    }
+
+    #[test]
+    fn scripts_that_only_rename_variables_are_grouped_as_renamed_copies() {
+        let report = find_in(&[
+            ("First", "SET Total TO 0\nSET Count TO 1\nTotal + Count"),
+            ("Second", "SET Sum TO 0\nSET Count TO 1\nSum + Count")
+        ], 1.0);
+
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].kind, CloneKind::Renamed);
+        let differences: Vec<usize> = report.groups[0].members.iter().map(|m| m.differences.len()).collect();
+        assert_eq!(differences, vec![2, 2]);
+    }
+
+    #[test]
+    fn near_clones_below_the_threshold_are_left_out() {
+        let scripts = [
+            ("First", "SET Total TO 0\nSET Count TO 1\nINCREMENT Count\n\"done\""),
+            ("Second", "SET Total TO 0\nSET Count TO 1\nINCREMENT Count\n\"other\"")
+        ];
+
+        assert!(find_in(&scripts, 0.8).groups.is_empty());
+
+        let report = find_in(&scripts, 0.7);
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].kind, CloneKind::Near);
+        assert_eq!(report.groups[0].similarity, 0.75);
+        assert_eq!(report.diagnostics[0].message, "The script is 75% the same as the script of First");
+        let second = &report.groups[0].members[1];
+        assert_eq!(second.differences.iter().map(|s| &scripts[1].1[s.start..s.end]).collect::<Vec<_>>(), vec!["\"other\""]);
+    }
+}
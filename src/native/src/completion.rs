@@ -0,0 +1,561 @@
+use crate::hd_script_parser::{ self, * };
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::builtins;
+use crate::type_checker::Type;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum CompletionKind {
+    Keyword,
+    Operator,
+    Variable,
+    Computation,
+    Dialog,
+    Function,
+    // A value of a multiple choice variable
+    Option
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: Option<String>,
+    // The text that replaces what has been typed, functions include their opening parenthesis
+    pub insert: String,
+    // Higher scores fit the position better, candidates are sorted by it
+    pub score: u32
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Completions {
+    // The part of the script the candidates replace, what has been typed of the name so far
+    pub span: Span,
+    pub items: Vec<Completion>,
+    // Why the library couldn't be read, there are no candidates then
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// What the library knows about the names a script can use
+pub struct Library<'l> {
+    pub components: &'l HashMap<String, Component>,
+    pub types: &'l HashMap<String, Type>,
+    pub options: &'l HashMap<String, Vec<String>>
+}
+
+// The components that can be given a value by SET, DEFAULT, ERASE and the like
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Names {
+    Any,
+    Dialogs,
+    // Dialogs first, then the variables they could be asked for
+    Askable,
+    Variables,
+    Writable(Option<Type>)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Context {
+    Statement,
+    Names(Names),
+    Expression(Type),
+    Keywords(Vec<&'static str>),
+    Operators(Type),
+    Options(Vec<String>, bool),
+    Nothing
+}
+
+// Operators in the order the precedence climbing parser tries them, so <= is found before <
+const OPERATORS: [&str; 15] = [
+    " AND ", " OR ", " CONTAINS ", " STARTS WITH ", " ENDS WITH ", "<=", ">=", "!=", "=", "<", ">", "+", "-", "*", "/"
+];
+
+fn operators(operand: Type) -> Vec<&'static str> {
+    match operand {
+        Type::TrueFalse => vec!["AND", "OR", "=", "!="],
+        Type::Number => vec!["=", "!=", "<", ">", "<=", ">=", "+", "-", "*", "/"],
+        Type::Date => vec!["=", "!=", "<", ">", "<=", ">=", "+", "-"],
+        Type::Text => vec!["=", "!=", "+", "CONTAINS", "STARTS WITH", "ENDS WITH"],
+        Type::MultipleChoice => vec!["=", "!=", "CONTAINS"],
+        _ => vec!["=", "!=", "AND", "OR", "+", "-"]
+    }
+}
+
+fn is_writable(component: &Component) -> bool {
+    !matches!(component, Component::Computation(_) | Component::Dialog { .. } | Component::DialogElement(_) | Component::Image(_))
+}
+
+// Whether the statement has ended in a comment, and whether it has an open text literal
+fn literal_state(text: &str) -> (bool, bool) {
+    let mut in_string = false;
+    for (i, c) in text.char_indices() {
+        if c == '"' {
+            in_string = !in_string;
+        } else if !in_string && text[i..].starts_with("//") {
+            return (true, false);
+        }
+    }
+    (false, in_string)
+}
+
+// The END of each IF, WHILE and REPEAT still open before the cursor, innermost last
+fn open_blocks(text: &str) -> Vec<&'static str> {
+    let mut blocks = vec![];
+    for line in text.lines().map(|l| l.trim()) {
+        if line.starts_with("END IF") || line.starts_with("END WHILE") || line.starts_with("END REPEAT") {
+            blocks.pop();
+        } else if line.starts_with("IF ") {
+            blocks.push("END IF");
+        } else if line.starts_with("WHILE ") {
+            blocks.push("END WHILE");
+        } else if line.starts_with("REPEAT ") {
+            blocks.push("END REPEAT");
+        }
+    }
+    blocks
+}
+
+// An open bracket with where its current argument starts and the last operator in it
+struct Level {
+    bracket: Option<(usize, char)>,
+    start: usize,
+    operator: Option<(usize, &'static str)>
+}
+
+// The operand written last in an expression, where the parser put it in the tree
+fn last_operand<'e, 'a>(expression: &'e Expression<'a>) -> &'e Expression<'a> {
+    match expression {
+        Expression::Binary(b) => b.right.last().map_or(&b.left, |(_, e)| last_operand(e)),
+        Expression::Unary(u) => last_operand(&u.expression),
+        e => e
+    }
+}
+
+impl<'l> Library<'l> {
+    fn type_of(&self, name: &str) -> Type {
+        builtins::constant(name).or_else(|| self.types.get(name).copied()).unwrap_or(Type::Unknown)
+    }
+
+    // The type of the operand before an operator, read by parsing the expression written so far
+    fn operand_type(&self, text: &str) -> (Type, Option<String>) {
+        let expression = match hd_script_parser::parse_entry_expression(text.trim()) {
+            Ok((remaining, expression)) if remaining.trim().is_empty() => expression,
+            _ => return (Type::Unknown, None)
+        };
+        match last_operand(&expression) {
+            Expression::Variable(v) => (self.type_of(v.name.trim()), Some(v.name.trim().to_owned())),
+            operand => (self.value_type(operand), None)
+        }
+    }
+
+    // The type of a whole operand such as a group in brackets, which is true/false if it compares anything
+    fn value_type(&self, expression: &Expression) -> Type {
+        match expression {
+            Expression::Binary(b) => {
+                let arithmetic = b.right.iter().all(|(op, _)| matches!(op, BinaryOperation::Add | BinaryOperation::Subtract | BinaryOperation::Multiply | BinaryOperation::Divide));
+                if arithmetic { self.value_type(&b.left) } else { Type::TrueFalse }
+            },
+            Expression::Unary(u) if u.operation == UnaryOperation::Not => Type::TrueFalse,
+            Expression::Unary(u) => self.value_type(&u.expression),
+            Expression::Variable(v) => self.type_of(v.name.trim()),
+            Expression::FunctionCall(call) => builtins::function_named(call.name.trim()).map_or(Type::Unknown, |f| f.result),
+            Expression::Literal(LiteralExpression::Number(_)) => Type::Number,
+            Expression::Literal(LiteralExpression::Text(_)) => Type::Text,
+            Expression::Literal(LiteralExpression::Boolean(_)) => Type::TrueFalse,
+            _ => Type::Unknown
+        }
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        self.components.contains_key(name) || builtins::constant(name).is_some()
+    }
+
+    // What can come next in an expression, returning the context and what has been typed of the operand
+    fn expression<'t>(&self, text: &'t str, expected: Type) -> (Context, &'t str) {
+        let mut in_string = false;
+        let mut levels = vec![Level { bracket: None, start: 0, operator: None }];
+        for (i, c) in text.char_indices() {
+            if c == '"' {
+                in_string = !in_string;
+            }
+            if in_string || c == '"' {
+                continue;
+            }
+            match c {
+                '(' | '[' => levels.push(Level { bracket: Some((i, c)), start: i + 1, operator: None }),
+                ')' | ']' if levels.len() > 1 => {
+                    levels.pop();
+                },
+                ',' => {
+                    if let Some(level) = levels.last_mut() {
+                        level.start = i + 1;
+                        level.operator = None;
+                    }
+                },
+                _ => {
+                    // Operators follow a space or the end of an operand, as names can have symbols in them
+                    let follows_operand = text[..i].ends_with(|c: char| c.is_whitespace() || c == ')' || c == ']' || c == '"');
+                    let found = OPERATORS.iter().find(|op| text[i..].starts_with(*op) && (op.starts_with(' ') || follows_operand));
+                    if let (Some(op), Some(level)) = (found, levels.last_mut()) {
+                        level.operator = Some((i, op));
+                    }
+                }
+            }
+        }
+
+        // Arguments take the type the function expects, indexes are numbers
+        let Level { bracket, start, operator } = levels.pop().unwrap_or(Level { bracket: None, start: 0, operator: None });
+        let expected = match bracket {
+            Some((_, '[')) => Type::Number,
+            Some((open, _)) => {
+                let name = text[..open].trim_end();
+                let function = builtins::FUNCTIONS.iter().filter(|f| name.ends_with(f.name)).max_by_key(|f| f.name.len());
+                let index = text[open + 1..start].matches(',').count();
+                let parameter = function.and_then(|f| f.parameters.get(index).copied());
+                parameter.unwrap_or(Type::Unknown)
+            },
+            None => expected
+        };
+
+        let (typed, expected) = match operator {
+            Some((i, op)) => {
+                let typed = text[i + op.len()..].trim_start();
+                let (left, name) = self.operand_type(&text[start..i]);
+                match op.trim() {
+                    "AND" | "OR" => (typed, Type::TrueFalse),
+                    "=" | "!=" | "CONTAINS" => {
+                        if let Some(options) = name.and_then(|n| self.options.get(&n)).filter(|_| left == Type::MultipleChoice) {
+                            return (Context::Options(options.clone(), true), typed);
+                        }
+                        (typed, if left == Type::MultipleChoice { Type::Text } else { left })
+                    },
+                    "+" | "-" if left == Type::Date => (typed, Type::Number),
+                    "+" if left == Type::Text => (typed, Type::Text),
+                    "+" | "-" | "*" | "/" => (typed, Type::Number),
+                    _ => (typed, left)
+                }
+            },
+            None => {
+                let typed = text[start..].trim_start();
+                match typed.strip_prefix("NOT ") {
+                    Some(rest) => (rest.trim_start(), Type::TrueFalse),
+                    None => (typed, expected)
+                }
+            }
+        };
+
+        // An operand followed by a space is finished, so an operator comes next
+        if typed.ends_with(' ') {
+            let operand = typed.trim();
+            if self.is_name(operand) || operand.parse::<f64>().is_ok() || (operand.len() > 1 && operand.starts_with('"') && operand.ends_with('"')) {
+                let (operand_type, _) = self.operand_type(operand);
+                return (Context::Operators(operand_type), "");
+            }
+            if operand.ends_with(')') || operand.ends_with(']') {
+                if let Ok((remaining, expression)) = hd_script_parser::parse_entry_expression(operand) {
+                    if remaining.trim().is_empty() {
+                        return (Context::Operators(self.value_type(&expression)), "");
+                    }
+                }
+            }
+        }
+        (Context::Expression(expected), typed)
+    }
+
+    // Works out what fits at the end of the statement, returning it with what has been typed so far
+    fn context<'t>(&self, statement: &'t str) -> (Context, &'t str) {
+        let (comment, in_string) = literal_state(statement);
+        if comment {
+            return (Context::Nothing, "");
+        }
+        if in_string {
+            // Inside the quotes of an option being compared to a multiple choice variable
+            let quote = statement.rfind('"').unwrap_or_default();
+            return match self.context(&statement[..quote]) {
+                (Context::Options(options, _), "") => (Context::Options(options, false), &statement[quote + 1..]),
+                _ => (Context::Nothing, "")
+            };
+        }
+
+        let after = |keyword: &str| statement.strip_prefix(keyword);
+        if let Some(rest) = after("ASK ") {
+            (Context::Names(Names::Askable), rest)
+        } else if let Some(rest) = after("REPEAT ") {
+            (Context::Names(Names::Dialogs), rest)
+        } else if let Some(rest) = ["GRAY ", "UNGRAY ", "SHOW ", "HIDE ", "REQUIRE "].iter().find_map(|k| after(k)) {
+            (Context::Names(Names::Variables), rest)
+        } else if let Some(rest) = after("INCREMENT ").or_else(|| after("DECREMENT ")) {
+            (Context::Names(Names::Writable(Some(Type::Number))), rest)
+        } else if let Some(rest) = after("ERASE ") {
+            (Context::Names(Names::Writable(None)), rest)
+        } else if let Some(rest) = after("ASSEMBLE ") {
+            (Context::Names(Names::Any), rest)
+        } else if let Some(rest) = after("LIMIT ") {
+            self.expression(rest, Type::Number)
+        } else if let Some(rest) = after("SET ").or_else(|| after("DEFAULT ")) {
+            self.assignment(rest)
+        } else if let Some(rest) = after("ADD ") {
+            match rest.find(" TO ") {
+                Some(i) => (Context::Names(Names::Writable(Some(Type::MultipleChoice))), rest[i + 4..].trim_start()),
+                None => self.expression(rest, Type::Text)
+            }
+        } else if let Some(rest) = after("IF ").or_else(|| after("ELSE IF ")).or_else(|| after("WHILE ")) {
+            self.expression(rest, Type::TrueFalse)
+        } else if statement.is_empty() || statement.chars().all(|c| c.is_ascii_uppercase() || c == ' ' || c == '/') {
+            (Context::Statement, statement)
+        } else {
+            self.expression(statement, Type::Unknown)
+        }
+    }
+
+    // SET and DEFAULT name a variable, then TO, then a value of the variable's type
+    fn assignment<'t>(&self, rest: &'t str) -> (Context, &'t str) {
+        if let Some(i) = rest.find(" TO ") {
+            let (target, _) = self.operand_type(&rest[..i]);
+            return self.expression(&rest[i + 4..], target);
+        }
+        let variable = self
+            .components
+            .iter()
+            .filter(|(_, c)| is_writable(c))
+            .map(|(name, _)| name)
+            .filter(|name| rest.starts_with(name.as_str()) && rest[name.len()..].starts_with(' '))
+            .max_by_key(|name| name.len());
+        match variable {
+            Some(name) if "TO".starts_with(rest[name.len()..].trim()) => (Context::Keywords(vec!["TO"]), rest[name.len()..].trim_start()),
+            _ => (Context::Names(Names::Writable(None)), rest)
+        }
+    }
+
+    fn component_detail(&self, name: &str, component: &Component) -> (CompletionKind, String) {
+        let kind = match component {
+            Component::Computation(_) => return (CompletionKind::Computation, format!("computation returning {}", self.type_of(name))),
+            Component::Dialog { .. } => return (CompletionKind::Dialog, String::from("dialog")),
+            Component::DialogElement(_) => return (CompletionKind::Variable, String::from("dialog element")),
+            Component::Image(_) => return (CompletionKind::Variable, String::from("image")),
+            _ => self.type_of(name)
+        };
+        (CompletionKind::Variable, format!("{} variable", kind))
+    }
+
+    // Components that fit the names wanted, those whose type matches exactly are scored highest
+    fn names(&self, names: Names, expected: Option<Type>, items: &mut Vec<Completion>) {
+        for (name, component) in self.components.iter() {
+            let component_type = self.type_of(name);
+            let score = match names {
+                Names::Any if is_writable(component) || matches!(component, Component::Computation(_) | Component::Dialog { .. }) => 50,
+                Names::Any => continue,
+                Names::Dialogs | Names::Askable if component_type == Type::Dialog => 90,
+                Names::Dialogs => continue,
+                Names::Askable if is_writable(component) => 40,
+                Names::Askable => continue,
+                Names::Variables if is_writable(component) => 80,
+                Names::Variables => continue,
+                Names::Writable(wanted) if is_writable(component) => match wanted {
+                    Some(t) if t == component_type => 90,
+                    Some(_) => continue,
+                    None => 80
+                },
+                Names::Writable(_) => continue
+            };
+            let score = match expected {
+                Some(Type::Dialog) if component_type == Type::Dialog => score + 20,
+                _ if component_type == Type::Dialog && names == Names::Any => continue,
+                Some(Type::Unknown) | None => score,
+                Some(t) if t == component_type => score + 20,
+                Some(t) if t.accepts(component_type) => score,
+                // Any value can be compared to give a true or false one, e.g. IF Age > 5
+                Some(Type::TrueFalse) => score - 20,
+                Some(_) => continue
+            };
+            let (kind, detail) = self.component_detail(name, component);
+            items.push(Completion { label: name.to_owned(), kind, detail: Some(detail), insert: name.to_owned(), score });
+        }
+    }
+
+    fn functions(&self, expected: Type, items: &mut Vec<Completion>) {
+        for builtins::Function { name, parameters, result } in builtins::FUNCTIONS.iter() {
+            let result = *result;
+            let score = match expected {
+                Type::Unknown => 30,
+                t if t == result => 60,
+                t if t.accepts(result) => 30,
+                Type::TrueFalse => 10,
+                _ => continue
+            };
+            let parameters: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+            let detail = format!("{}({}) returns {}", name, parameters.join(", "), result);
+            items.push(Completion { label: name.to_string(), kind: CompletionKind::Function, detail: Some(detail), insert: format!("{}(", name), score });
+        }
+    }
+
+    fn keyword(label: &str, kind: CompletionKind, score: u32, items: &mut Vec<Completion>) {
+        items.push(Completion { label: label.to_owned(), kind, detail: None, insert: label.to_owned(), score });
+    }
+
+    fn candidates(&self, context: Context, blocks: &[&str], items: &mut Vec<Completion>) {
+        match context {
+            Context::Nothing => (),
+            Context::Statement => {
+                // Closing the innermost block is the likeliest statement, ELSE only fits inside an IF
+                for (i, end) in blocks.iter().rev().enumerate() {
+                    Library::keyword(end, CompletionKind::Keyword, if i == 0 { 100 } else { 60 }, items);
+                }
+                if blocks.contains(&"END IF") {
+                    Library::keyword("ELSE", CompletionKind::Keyword, 85, items);
+                    Library::keyword("ELSE IF", CompletionKind::Keyword, 85, items);
+                }
+                for keyword in builtins::STATEMENTS.iter().chain(["//"].iter()) {
+                    Library::keyword(keyword, CompletionKind::Keyword, 80, items);
+                }
+                Library::keyword("GRAY ALL", CompletionKind::Keyword, 70, items);
+                self.names(Names::Any, None, items);
+                self.functions(Type::Unknown, items);
+            },
+            Context::Names(names) => {
+                if names == Names::Variables {
+                    Library::keyword("ALL", CompletionKind::Keyword, 95, items);
+                }
+                self.names(names, None, items);
+            },
+            Context::Expression(expected) => {
+                self.names(Names::Any, Some(expected), items);
+                self.functions(expected, items);
+                if expected == Type::TrueFalse || expected == Type::Unknown {
+                    for keyword in ["TRUE", "FALSE", "NOT"].iter() {
+                        Library::keyword(keyword, CompletionKind::Keyword, 65, items);
+                    }
+                }
+                if expected == Type::Date || expected == Type::Unknown {
+                    Library::keyword("TODAY", CompletionKind::Keyword, 65, items);
+                }
+                if blocks.contains(&"END REPEAT") && (expected == Type::Number || expected == Type::Unknown) {
+                    Library::keyword("COUNTER", CompletionKind::Keyword, 65, items);
+                }
+            },
+            Context::Keywords(keywords) => {
+                for keyword in keywords {
+                    Library::keyword(keyword, CompletionKind::Keyword, 100, items);
+                }
+            },
+            Context::Operators(operand) => {
+                for (i, operator) in operators(operand).into_iter().enumerate() {
+                    Library::keyword(operator, CompletionKind::Operator, 100 - i as u32, items);
+                }
+            },
+            Context::Options(options, quoted) => {
+                for option in options {
+                    let insert = if quoted { format!("\"{}\"", option.replace('"', "\"\"")) } else { option.clone() };
+                    items.push(Completion { label: option, kind: CompletionKind::Option, detail: None, insert, score: 100 });
+                }
+            }
+        }
+    }
+}
+
+// How well what has been typed matches the label, None when it doesn't. Names are matched from the start or from the
+// start of any word in them, ignoring case.
+fn matches(label: &str, typed: &str) -> Option<u32> {
+    if typed.is_empty() || label.starts_with(typed) {
+        return Some(20);
+    }
+    let (label, typed) = (label.to_lowercase(), typed.to_lowercase());
+    if label.starts_with(&typed) {
+        Some(15)
+    } else if label.match_indices(' ').any(|(i, _)| label[i + 1..].starts_with(&typed)) {
+        Some(5)
+    } else {
+        None
+    }
+}
+
+// The candidates that fit the position of the cursor in the script, best first
+pub fn complete(script: &str, offset: usize, library: &Library) -> Completions {
+    let mut offset = offset.min(script.len());
+    while !script.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &script[..offset];
+    let line = &before[before.rfind('\n').map_or(0, |i| i + 1)..];
+    let statement = line.trim_start();
+
+    let (context, typed) = library.context(statement);
+    let mut items = vec![];
+    library.candidates(context, &open_blocks(&before[..before.len() - line.len()]), &mut items);
+
+    let typed_text = typed.trim_end();
+    let mut items: Vec<Completion> = items
+        .into_iter()
+        .filter_map(|mut item| {
+            item.score += matches(&item.label, typed_text)?;
+            Some(item)
+        })
+        .collect();
+    items.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.label.cmp(&b.label)));
+    items.dedup_by(|a, b| a.label == b.label && a.kind == b.kind);
+
+    Completions { span: Span { start: offset - typed.len(), end: offset }, items, diagnostics: vec![] }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Utf16Index;
+    use std::collections::HashSet;
+
+    fn labels(completions: &Completions) -> Vec<&str> {
+        completions.items.iter().map(|c| c.label.as_str()).collect()
+    }
+
+    fn complete_with(script: &str, offset: usize) -> Completions {
+        let components: HashMap<String, Component> = vec![
+            (String::from("Married"), Component::TrueFalse(HashSet::new())),
+            (String::from("Age"), Component::Number(HashSet::new())),
+            (String::from("Client Name"), Component::Text(HashSet::new()))
+        ].into_iter().collect();
+        let types: HashMap<String, Type> = vec![
+            (String::from("Married"), Type::TrueFalse),
+            (String::from("Age"), Type::Number),
+            (String::from("Client Name"), Type::Text)
+        ].into_iter().collect();
+        let options = HashMap::new();
+        complete(script, offset, &Library { components: &components, types: &types, options: &options })
+    }
+
+    #[test]
+    fn conditions_rank_true_false_values_first_and_still_offer_the_rest() {
+        let completions = complete_with("IF ", 3);
+        let ranked = labels(&completions);
+        assert_eq!(ranked[0], "Married");
+        let position = |label: &str| ranked.iter().position(|l| *l == label).unwrap();
+        assert!(position("Age") > position("Married"));
+        assert!(position("LENGTH") > position("ANSWERED"));
+
+        let completions = complete_with("IF Ag", 5);
+        assert_eq!(labels(&completions)[0], "Age");
+        assert_eq!(completions.span, Span { start: 3, end: 5 });
+    }
+
+    #[test]
+    fn offsets_convert_between_bytes_and_utf16() {
+        let script = "SET Client Name TO \"«A»\"\nIF Ag";
+        let index = Utf16Index::new(script);
+        let utf16_length = script.encode_utf16().count();
+        assert_eq!(index.to_byte(utf16_length), script.len());
+        assert_eq!(index.to_utf16(script.len()), utf16_length);
+
+        let completions = complete_with(script, index.to_byte(utf16_length));
+        let span = index.span(completions.span);
+        assert_eq!(labels(&completions)[0], "Age");
+        assert_eq!(span, Span { start: utf16_length - 2, end: utf16_length });
+
+        let emoji = "😀x";
+        let index = Utf16Index::new(emoji);
+        assert_eq!(index.to_byte(1), 0);
+        assert_eq!(index.to_byte(2), 4);
+        assert_eq!(index.to_utf16(4), 2);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::xml_parser::{ Node, XmlElement };
+use crate::diagnostics::Diagnostic;
+use crate::preferences::{ self, LibraryPreferences };
+
+use std::collections::{ HashMap, HashSet };
+
+// Every version of the component library schema lives under this URI, e.g. .../component_library/2009
+pub const HOTDOCS_NAMESPACE: &str = "http://www.hotdocs.com/schemas/component_library/";
+
+// The first version of the schema with a namespace of its own
+const FIRST_SCHEMA_YEAR: u32 = 2009;
+
+// Whether the URI is the namespace of the 2009 component library schema or a later one
+pub fn is_hotdocs_namespace(uri: &str) -> bool {
+    match uri.strip_prefix(HOTDOCS_NAMESPACE) {
+        Some(version) => version.trim_end_matches('/').parse::<u32>().is_ok_and(|year| year >= FIRST_SCHEMA_YEAR),
+        None => false
+    }
+}
+
+pub struct ComponentElement<'a, 'b> {
+    pub kind: &'a str,
+    pub name: &'b str,
+    pub element: &'b XmlElement<'a>
+}
+
+pub struct ComponentLibrary<'a, 'b> {
+    // Whether the root binds its prefix to a HotDocs namespace, older files that don't are matched on the hd prefix
+    namespaced: bool,
+    pub preferences: LibraryPreferences,
+    pub sections: HashMap<&'a str, &'b XmlElement<'a>>,
+    pub components: Vec<ComponentElement<'a, 'b>>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl<'a, 'b> ComponentLibrary<'a, 'b> {
+    // Returns the name of the element without its prefix if it belongs to the HotDocs namespace
+    pub fn local_name(&self, element: &XmlElement<'a>) -> Option<&'a str> {
+        match &element.namespace {
+            Some(uri) if is_hotdocs_namespace(uri) => Some(element.local_name()),
+            None if !self.namespaced => element.name.strip_prefix("hd:"),
+            _ => None
+        }
+    }
+
+    pub fn is(&self, element: &XmlElement<'a>, local_name: &str) -> bool {
+        self.local_name(element) == Some(local_name)
+    }
+
+    // Finds the first child element with the given HotDocs name, e.g. hd:contents or hd:script
+    pub fn child(&self, element: &'b XmlElement<'a>, local_name: &str) -> Option<&'b XmlElement<'a>> {
+        self.children(element).find(|e| self.is(e, local_name))
+    }
+
+    // The kind and name of a component, with a warning when an earlier component has the same name, or why the element
+    // was ignored
+    pub fn identify<'e>(&self, element: &'e XmlElement<'a>, seen: &mut HashSet<String>) -> Result<(&'a str, &'e str, Option<Diagnostic>), Box<Diagnostic>> {
+        let kind = match self.local_name(element) {
+            Some(kind) => kind,
+            None => return Err(Box::new(Diagnostic::warning(format!("<{}> is not a HotDocs component and was ignored", element.name)).located(Some(element.location))))
+        };
+
+        match element.attributes.get("name") {
+            Some(name) if !name.is_empty() => {
+                let duplicate = if seen.insert(name.to_owned()) {
+                    None
+                } else {
+                    Some(Diagnostic::warning(String::from("The component name is used by more than one component")).for_component(name).located(element.attribute("name").map(|a| a.location)))
+                };
+                Ok((kind, name, duplicate))
+            },
+            _ => Err(Box::new(Diagnostic::error(format!("<{}> has no name attribute and was ignored", element.name)).located(Some(element.location))))
+        }
+    }
+
+    pub fn children(&self, element: &'b XmlElement<'a>) -> impl Iterator<Item = &'b XmlElement<'a>> {
+        element.children.iter().filter_map(|n| match n {
+            Node::XmlElement(e) => Some(e),
+            _ => None
+        })
+    }
+}
+
+fn is_blank(node: &Node) -> bool {
+    match node {
+        Node::Value(text) => text.value.trim().is_empty(),
+        Node::Comment(_) | Node::ProcessingInstruction(..) => true,
+        Node::XmlElement(_) => false
+    }
+}
+
+// A library with nothing loaded into it that matches elements the way the root element names them, for reading
+// components as they stream by before the rest of the library has been read
+pub fn named<'a, 'b>(root: &XmlElement<'a>) -> ComponentLibrary<'a, 'b> {
+    ComponentLibrary {
+        namespaced: root.namespace.as_deref().is_some_and(is_hotdocs_namespace),
+        preferences: LibraryPreferences::default(),
+        sections: HashMap::new(),
+        components: vec![],
+        diagnostics: vec![]
+    }
+}
+
+pub fn load<'a, 'b>(root: &'b XmlElement<'a>) -> ComponentLibrary<'a, 'b> {
+    let mut library = named(root);
+    if !library.namespaced {
+        library.diagnostics.push(Diagnostic::warning(format!("<{}> is not in a HotDocs component library namespace, assuming the hd prefix", root.name)));
+    }
+
+    if !library.is(root, "componentLibrary") {
+        library.diagnostics.push(Diagnostic::error(format!("The root element is <{}>, expected a HotDocs componentLibrary", root.name)).located(Some(root.location)));
+        return library;
+    }
+
+    for node in root.children.iter() {
+        match node {
+            Node::XmlElement(section) => match library.local_name(section) {
+                Some(name) if library.sections.contains_key(name) => {
+                    library.diagnostics.push(Diagnostic::warning(format!("Duplicate <{}> section ignored", section.name)).located(Some(section.location)));
+                },
+                Some(name) => {
+                    library.sections.insert(name, section);
+                },
+                None => {
+                    library.diagnostics.push(Diagnostic::warning(format!("<{}> is not part of the HotDocs namespace and was ignored", section.name)).located(Some(section.location)));
+                }
+            },
+            n if is_blank(n) => (),
+            _ => {
+                library.diagnostics.push(Diagnostic::warning(String::from("Unexpected text in the component library was ignored")));
+            }
+        }
+    }
+
+    match library.sections.get("preferences").copied() {
+        Some(element) => {
+            let mut diagnostics = vec![];
+            library.preferences = preferences::parse(&library, element, &mut diagnostics);
+            library.diagnostics.extend(diagnostics);
+        },
+        None => {
+            library.diagnostics.push(Diagnostic::warning(String::from("The component library has no preferences section")));
+        }
+    }
+
+    let components = match library.sections.get("components") {
+        Some(components) => *components,
+        None => {
+            library.diagnostics.push(Diagnostic::error(String::from("The component library has no components section")));
+            return library;
+        }
+    };
+
+    let mut seen = HashSet::new();
+    for element in library.children(components).collect::<Vec<_>>() {
+        match library.identify(element, &mut seen) {
+            Ok((kind, name, duplicate)) => {
+                library.diagnostics.extend(duplicate);
+                library.components.push(ComponentElement { kind, name, element });
+            },
+            Err(diagnostic) => library.diagnostics.push(*diagnostic)
+        }
+    }
+
+    library
+}
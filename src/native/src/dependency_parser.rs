@@ -1,5 +1,6 @@
 use std::collections::HashSet;
 use crate::hd_script_parser;
+use crate::merge_field_parser;
 
 use hd_script_parser::*;
 
@@ -9,9 +10,9 @@ pub fn parse(script: &Script) -> HashSet<String> {
     result
 }
 
-fn output_statements(statements: &Vec<Statement>, uses: &mut HashSet<String>) {
-    for statement in statements.iter().filter(|s| match s { Statement::Comment(_) => false, _ => true }) {
-        output_statement(statement, uses);
+fn output_statements(statements: &Vec<Spanned<Statement>>, uses: &mut HashSet<String>) {
+    for statement in statements.iter().filter(|s| match s.node { Statement::Comment(_) => false, _ => true }) {
+        output_statement(&statement.node, uses);
     }
 }
 
@@ -96,8 +97,9 @@ fn output_expression(expression: &Expression, uses: &mut HashSet<String>) {
         Expression::Variable(variable) => output_variable_reference(variable, uses),
         
         Expression::Literal(LiteralExpression::Text(t)) => {
-            // process dot codes
-            ()
+            for field in merge_field_parser::parse(t) {
+                uses.insert(field.name.to_owned());
+            }
         },
         Expression::Binary(ex) => output_binary_expression(ex, uses),
         Expression::Unary(ex) => output_unary_expression(ex, uses),
@@ -141,7 +143,7 @@ fn output_if_statement(if_statement: &IfStatement, uses: &mut HashSet<String>) {
     match &if_statement.else_body[..] {
         [] => (),
         [x] => {
-            output_statement(&x, uses);
+            output_statement(&x.node, uses);
         },
         _ => {
             output_statements(&if_statement.else_body, uses);
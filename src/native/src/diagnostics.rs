@@ -0,0 +1,160 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+// Byte offsets into the script of the component a diagnostic is for
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    // Finds where a slice taken from the source starts and ends, slices from elsewhere have no span
+    pub fn of(source: &str, slice: &str) -> Option<Span> {
+        let start = (slice.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        let end = start + slice.len();
+        if end <= source.len() {
+            Some(Span { start, end })
+        } else {
+            None
+        }
+    }
+
+    pub fn join(self, other: Span) -> Span {
+        Span { start: self.start.min(other.start), end: self.end.max(other.end) }
+    }
+}
+
+// A place in the component file, lines and columns count from 1 and columns count characters
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default, Hash)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default, Hash)]
+pub struct Location {
+    pub start: Position,
+    pub end: Position
+}
+
+// Where each line of a document starts, for turning byte offsets into lines and columns
+pub struct LineIndex<'a> {
+    document: &'a str,
+    starts: Vec<usize>
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(document: &'a str) -> LineIndex<'a> {
+        let starts = std::iter::once(0).chain(document.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        LineIndex { document, starts }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let line = match self.starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1
+        };
+        let column = self.document.get(self.starts[line]..offset).map_or(0, |text| text.chars().count()) + 1;
+        Position { offset, line: line + 1, column }
+    }
+
+    pub fn location(&self, span: Span) -> Location {
+        Location { start: self.position(span.start), end: self.position(span.end) }
+    }
+
+    // Locates a slice taken from the document, slices from elsewhere have no location
+    pub fn locate(&self, slice: &str) -> Option<Location> {
+        Span::of(self.document, slice).map(|span| self.location(span))
+    }
+}
+
+// Converts between byte offsets and the UTF-16 code unit offsets JavaScript strings are indexed with
+pub struct Utf16Index {
+    // The byte and code unit offset of each character, and of the end of the text
+    offsets: Vec<(usize, usize)>
+}
+
+impl Utf16Index {
+    pub fn new(text: &str) -> Utf16Index {
+        let mut units = 0;
+        let mut offsets: Vec<(usize, usize)> = text
+            .char_indices()
+            .map(|(i, c)| {
+                let offset = (i, units);
+                units += c.len_utf16();
+                offset
+            })
+            .collect();
+        offsets.push((text.len(), units));
+        Utf16Index { offsets }
+    }
+
+    // Offsets inside a character are moved to its start
+    pub fn to_utf16(&self, offset: usize) -> usize {
+        match self.offsets.binary_search_by_key(&offset, |(byte, _)| *byte) {
+            Ok(i) => self.offsets[i].1,
+            Err(next) => self.offsets[next.saturating_sub(1)].1
+        }
+    }
+
+    // Offsets inside a surrogate pair are moved to the start of the character
+    pub fn to_byte(&self, offset: usize) -> usize {
+        match self.offsets.binary_search_by_key(&offset, |(_, unit)| *unit) {
+            Ok(i) => self.offsets[i].0,
+            Err(next) => self.offsets[next.saturating_sub(1)].0
+        }
+    }
+
+    pub fn span(&self, span: Span) -> Span {
+        Span { start: self.to_utf16(span.start), end: self.to_utf16(span.end) }
+    }
+
+    pub fn location(&self, location: Location) -> Location {
+        let mut location = location;
+        location.start.offset = self.to_utf16(location.start.offset);
+        location.end.offset = self.to_utf16(location.end.offset);
+        location
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub component: Option<String>,
+    pub span: Option<Span>,
+    // Where the diagnostic points to in the component file
+    pub location: Option<Location>
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, component: None, span: None, location: None }
+    }
+
+    pub fn warning(message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message, component: None, span: None, location: None }
+    }
+
+    pub fn for_component(mut self, component: &str) -> Diagnostic {
+        self.component = Some(component.to_owned());
+        self
+    }
+
+    pub fn at(mut self, span: Option<Span>) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+    pub fn located(mut self, location: Option<Location>) -> Diagnostic {
+        self.location = location;
+        self
+    }
+}
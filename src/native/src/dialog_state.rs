@@ -0,0 +1,147 @@
+use crate::hd_script_parser::DisplayInstruction;
+use crate::interpreter::DialogRun;
+use crate::answers::AnswerSet;
+use crate::diagnostics::Diagnostic;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ControlState {
+    pub name: String,
+    pub visible: bool,
+    pub grayed: bool,
+    pub required: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DialogState {
+    pub dialog: String,
+    pub controls: Vec<ControlState>,
+    // The most times a repeated dialog can be repeated, set with LIMIT
+    pub limit: Option<u32>,
+    // The answers as they are after the script has run, as dialog scripts can SET variables
+    pub answers: AnswerSet,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+fn apply(controls: &mut [ControlState], instruction: &DisplayInstruction, diagnostics: &mut Vec<Diagnostic>) {
+    let (target, change): (Option<&str>, fn(&mut ControlState)) = match instruction {
+        DisplayInstruction::Gray(name) => (Some(name), |c| c.grayed = true),
+        DisplayInstruction::GrayAll => (None, |c| c.grayed = true),
+        DisplayInstruction::Ungray(name) => (Some(name), |c| c.grayed = false),
+        DisplayInstruction::UngrayAll => (None, |c| c.grayed = false),
+        DisplayInstruction::Hide(name) => (Some(name), |c| c.visible = false),
+        DisplayInstruction::HideAll => (None, |c| c.visible = false),
+        DisplayInstruction::Show(name) => (Some(name), |c| c.visible = true),
+        DisplayInstruction::ShowAll => (None, |c| c.visible = true),
+        DisplayInstruction::Require(name) => (Some(name), |c| c.required = true),
+        DisplayInstruction::RequireAll => (None, |c| c.required = true),
+        DisplayInstruction::Ask(_) | DisplayInstruction::Limit(_) => return
+    };
+
+    match target.map(str::trim) {
+        None => controls.iter_mut().for_each(change),
+        Some(name) => match controls.iter_mut().find(|c| c.name == name) {
+            Some(control) => change(control),
+            None => diagnostics.push(Diagnostic::warning(format!("{} is not on the dialog", name)))
+        }
+    }
+}
+
+// Applies what the dialog script did to its controls, which start out visible, not grayed and not required
+pub fn build(dialog: &str, children: &[String], run: DialogRun) -> DialogState {
+    let mut controls: Vec<ControlState> = children
+        .iter()
+        .map(|name| ControlState { name: name.to_owned(), visible: true, grayed: false, required: false })
+        .collect();
+
+    let mut diagnostics = run.diagnostics;
+    let mut script_diagnostics = vec![];
+    for instruction in run.displayed.iter() {
+        apply(&mut controls, instruction, &mut script_diagnostics);
+    }
+
+    // A required control the user can't see or change can never be answered
+    for control in controls.iter().filter(|c| c.required && (!c.visible || c.grayed)) {
+        let state = if control.visible { "grayed" } else { "hidden" };
+        script_diagnostics.push(Diagnostic::warning(format!("{} is required but {}", control.name, state)));
+    }
+    diagnostics.extend(script_diagnostics.into_iter().map(|d| d.for_component(dialog)));
+
+    DialogState { dialog: dialog.to_owned(), controls, limit: run.limit, answers: run.answers, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::answers::{ Date, Value };
+    use crate::simulate_dialog;
+
+    const LIBRARY: &str = r#"<hd:componentLibrary xmlns:hd="http://www.hotdocs.com/schemas/component_library/2009">
+<hd:components>
+<hd:trueFalse name="Married"/>
+<hd:text name="Spouse Name"/>
+<hd:number name="Child Count"/>
+<hd:text name="Child Name"/>
+<hd:dialog name="Client Details"><hd:contents><hd:item name="Married"/><hd:item name="Spouse Name"/></hd:contents><hd:script>
+IF Married
+SHOW Spouse Name
+REQUIRE Spouse Name
+ELSE
+HIDE Spouse Name
+END IF
+GRAY Married
+</hd:script></hd:dialog>
+<hd:dialog name="Children"><hd:contents><hd:item name="Child Name"/></hd:contents><hd:script>LIMIT Child Count</hd:script></hd:dialog>
+<hd:dialog name="Hidden"><hd:contents><hd:item name="Spouse Name"/></hd:contents><hd:script>REQUIRE ALL
+HIDE ALL
+SHOW Missing</hd:script></hd:dialog>
+</hd:components>
+</hd:componentLibrary>"#;
+
+    fn simulated(dialog: &str, answers: &[(&str, Value)]) -> DialogState {
+        let mut set = AnswerSet::new();
+        for (name, value) in answers.iter() {
+            set.set(name, vec![], value.clone());
+        }
+        simulate_dialog(LIBRARY, dialog, set, Date::new(2024, 1, 1).unwrap())
+    }
+
+    fn control(name: &str, visible: bool, grayed: bool, required: bool) -> ControlState {
+        ControlState { name: name.to_owned(), visible, grayed, required }
+    }
+
+    #[test]
+    fn the_script_shows_and_hides_controls_from_the_answers() {
+        let married = simulated("Client Details", &[("Married", Value::TrueFalse(true))]);
+        assert!(married.diagnostics.is_empty(), "{:?}", married.diagnostics);
+        assert_eq!(married.controls, vec![control("Married", true, true, false), control("Spouse Name", true, false, true)]);
+
+        let single = simulated("Client Details", &[("Married", Value::TrueFalse(false))]);
+        assert_eq!(single.controls, vec![control("Married", true, true, false), control("Spouse Name", false, false, false)]);
+    }
+
+    #[test]
+    fn controls_that_are_required_but_hidden_are_reported() {
+        let hidden = simulated("Hidden", &[]);
+        assert_eq!(hidden.controls, vec![control("Spouse Name", false, false, true)]);
+
+        let messages: Vec<(&str, Option<&str>)> = hidden.diagnostics.iter().map(|d| (d.message.as_str(), d.component.as_deref())).collect();
+        assert_eq!(messages, vec![
+            ("Missing is not on the dialog", Some("Hidden")),
+            ("Spouse Name is required but hidden", Some("Hidden"))
+        ]);
+    }
+
+    #[test]
+    fn the_repeat_limit_comes_from_the_script() {
+        assert_eq!(simulated("Children", &[("Child Count", Value::Number(3.0))]).limit, Some(3));
+        // Without an answer there is no limit
+        assert_eq!(simulated("Children", &[]).limit, None);
+
+        let negative = simulated("Children", &[("Child Count", Value::Number(-1.0))]);
+        assert_eq!(negative.limit, None);
+        assert_eq!(negative.diagnostics.len(), 1);
+        assert!(negative.diagnostics[0].message.contains("can't be used as the LIMIT of a repeated dialog"), "{:?}", negative.diagnostics);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Windows1252
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Windows1252 => "Windows-1252"
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct DecodeError {
+    pub encoding: Encoding,
+    // Byte offset of the first sequence that couldn't be decoded
+    pub offset: usize
+}
+
+// The characters for bytes 0x80 to 0x9F, the rest of Windows-1252 is the same as Latin-1. Bytes the code page leaves
+// undefined are kept as the control character with the same value, as browsers do.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}'
+];
+
+// The encoding named in the XML declaration, which is read as ASCII since it comes before anything else
+fn declared_name(bytes: &[u8]) -> Option<String> {
+    if !bytes.starts_with(b"<?xml") {
+        return None;
+    }
+    let end = bytes.windows(2).take(1024).position(|w| w == b"?>")?;
+    let declaration: String = bytes[..end].iter().map(|b| *b as char).collect();
+
+    let rest = &declaration[declaration.find("encoding")? + "encoding".len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let name = &rest[1..];
+    Some(name[..name.find(quote)?].to_owned())
+}
+
+fn named(name: &str) -> Option<Encoding> {
+    match name.to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" | "us-ascii" | "ascii" => Some(Encoding::Utf8),
+        // Without a byte order mark UTF-16 is little endian, as Windows writes it
+        "utf-16" | "utf-16le" | "unicode" => Some(Encoding::Utf16Le),
+        "utf-16be" => Some(Encoding::Utf16Be),
+        "windows-1252" | "cp1252" | "iso-8859-1" | "latin1" | "l1" => Some(Encoding::Windows1252),
+        _ => None
+    }
+}
+
+// Works out the encoding from the byte order mark, then the way the first "<" is written, then the XML declaration,
+// falling back to UTF-8. Returns the encoding and how many bytes of byte order mark to skip.
+pub fn detect(bytes: &[u8]) -> (Encoding, usize) {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        (Encoding::Utf8, 3)
+    } else if bytes.starts_with(&[0xff, 0xfe]) {
+        (Encoding::Utf16Le, 2)
+    } else if bytes.starts_with(&[0xfe, 0xff]) {
+        (Encoding::Utf16Be, 2)
+    } else if bytes.starts_with(&[b'<', 0]) {
+        (Encoding::Utf16Le, 0)
+    } else if bytes.starts_with(&[0, b'<']) {
+        (Encoding::Utf16Be, 0)
+    } else {
+        match declared_name(bytes).and_then(|name| named(&name)) {
+            // A declaration readable as ASCII can't be in UTF-16
+            Some(Encoding::Utf16Le) | Some(Encoding::Utf16Be) | None => (Encoding::Utf8, 0),
+            Some(encoding) => (encoding, 0)
+        }
+    }
+}
+
+// The encoding the XML declaration names when it isn't one that can be decoded, such files are read as UTF-8
+pub fn unsupported(bytes: &[u8]) -> Option<String> {
+    match detect(bytes) {
+        (Encoding::Utf8, 0) => declared_name(bytes).filter(|name| named(name).is_none()),
+        _ => None
+    }
+}
+
+fn utf16_units(bytes: &[u8], encoding: Encoding) -> impl Iterator<Item = u16> + '_ {
+    bytes.chunks_exact(2).map(move |pair| match encoding {
+        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+        _ => u16::from_be_bytes([pair[0], pair[1]])
+    })
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding, skipped: usize) -> Result<String, DecodeError> {
+    let units = utf16_units(bytes, encoding);
+    let mut text = String::with_capacity(bytes.len() / 2);
+    let mut offset = skipped;
+    for c in std::char::decode_utf16(units) {
+        match c {
+            Ok(c) => {
+                offset += c.len_utf16() * 2;
+                text.push(c);
+            },
+            Err(_) => return Err(DecodeError { encoding, offset })
+        }
+    }
+    // An odd byte at the end can't be part of a character
+    if bytes.len() % 2 == 1 {
+        return Err(DecodeError { encoding, offset });
+    }
+    Ok(text)
+}
+
+// Decodes a component file in whichever encoding it was saved in, without its byte order mark
+pub fn decode(bytes: &[u8]) -> Result<String, DecodeError> {
+    let (encoding, skipped) = detect(bytes);
+    let content = &bytes[skipped..];
+
+    match encoding {
+        Encoding::Utf8 => String::from_utf8(content.to_vec()).map_err(|e| DecodeError { encoding, offset: skipped + e.utf8_error().valid_up_to() }),
+        Encoding::Utf16Le | Encoding::Utf16Be => decode_utf16(content, encoding, skipped),
+        Encoding::Windows1252 => Ok(content
+            .iter()
+            .map(|b| match b {
+                0x80..=0x9f => WINDOWS_1252[(b - 0x80) as usize],
+                _ => *b as char
+            })
+            .collect())
+    }
+}
+
+// Decodes as much as can be, replacing what can't be decoded
+pub fn decode_lossy(bytes: &[u8]) -> String {
+    let (encoding, skipped) = detect(bytes);
+    match decode(bytes) {
+        Ok(text) => text,
+        Err(_) if encoding == Encoding::Utf8 => String::from_utf8_lossy(&bytes[skipped..]).into_owned(),
+        Err(_) => {
+            let content = &bytes[skipped..];
+            let mut text: String = std::char::decode_utf16(utf16_units(content, encoding))
+                .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+                .collect();
+            if content.len() % 2 == 1 {
+                text.push(std::char::REPLACEMENT_CHARACTER);
+            }
+            text
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XML: &str = "<?xml version=\"1.0\"?><a>é€</a>";
+
+    fn utf16(text: &str, little_endian: bool) -> Vec<u8> {
+        text.encode_utf16().flat_map(|u| if little_endian { u.to_le_bytes() } else { u.to_be_bytes() }).collect()
+    }
+
+    #[test]
+    fn byte_order_marks_are_detected_and_skipped() {
+        let utf8 = [&[0xef, 0xbb, 0xbf][..], XML.as_bytes()].concat();
+        let le = [&[0xff, 0xfe][..], &utf16(XML, true)].concat();
+        let be = [&[0xfe, 0xff][..], &utf16(XML, false)].concat();
+
+        assert_eq!(detect(&utf8), (Encoding::Utf8, 3));
+        assert_eq!(detect(&le), (Encoding::Utf16Le, 2));
+        assert_eq!(detect(&be), (Encoding::Utf16Be, 2));
+        for bytes in [utf8, le, be].iter() {
+            assert_eq!(decode(bytes).unwrap(), XML);
+        }
+    }
+
+    #[test]
+    fn utf16_without_a_byte_order_mark_is_detected_from_the_first_tag() {
+        for text in [XML, "<a>é€</a>"].iter() {
+            assert_eq!(detect(&utf16(text, true)), (Encoding::Utf16Le, 0));
+            assert_eq!(detect(&utf16(text, false)), (Encoding::Utf16Be, 0));
+            assert_eq!(decode(&utf16(text, true)).unwrap(), *text);
+            assert_eq!(decode(&utf16(text, false)).unwrap(), *text);
+        }
+    }
+
+    #[test]
+    fn windows_1252_is_read_from_the_declaration() {
+        let bytes = b"<?xml version=\"1.0\" encoding='Windows-1252'?><a>\xe9\x80\x9d</a>";
+        assert_eq!(detect(bytes), (Encoding::Windows1252, 0));
+        assert_eq!(decode(bytes).unwrap(), "<?xml version=\"1.0\" encoding='Windows-1252'?><a>é€\u{9d}</a>");
+        assert_eq!(unsupported(bytes), None);
+    }
+
+    #[test]
+    fn unsupported_declarations_are_named() {
+        let bytes = b"<?xml version=\"1.0\" encoding=\"Shift_JIS\"?><a/>";
+        assert_eq!(detect(bytes), (Encoding::Utf8, 0));
+        assert_eq!(unsupported(bytes), Some(String::from("Shift_JIS")));
+        assert_eq!(unsupported(b"<?xml version=\"1.0\" encoding=\"utf-8\"?><a/>"), None);
+        assert_eq!(unsupported(b"<a/>"), None);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_where_it_starts() {
+        assert_eq!(decode(b"<a>\xff</a>"), Err(DecodeError { encoding: Encoding::Utf8, offset: 3 }));
+    }
+}
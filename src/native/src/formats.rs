@@ -0,0 +1,169 @@
+use crate::answers::{ Date, Value };
+use crate::preferences::LibraryPreferences;
+
+// HotDocs formats are written as an example of the output, using June 3, 1990 for dates and 9,999.99 for numbers
+pub const DEFAULT_DATE_FORMAT: &str = "June 3, 1990";
+pub const DEFAULT_NUMBER_FORMAT: &str = "9,999.99";
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"
+];
+
+const DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+fn ordinal_suffix(n: u32) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th"
+    }
+}
+
+fn with_case(text: &str, example: &str) -> String {
+    if example.len() > 1 && example.chars().all(|c| !c.is_alphabetic() || c.is_uppercase()) {
+        text.to_uppercase()
+    } else if example.chars().next().is_some_and(|c| c.is_lowercase()) {
+        text.to_lowercase()
+    } else {
+        text.to_owned()
+    }
+}
+
+fn name_token(names: &[&str], token: &str, value: usize) -> Option<String> {
+    names.iter().find_map(|name| {
+        if name.eq_ignore_ascii_case(token) {
+            Some(with_case(names[value], token))
+        } else if token.len() == 3 && name[..3].eq_ignore_ascii_case(token) {
+            Some(with_case(&names[value][..3], token))
+        } else {
+            None
+        }
+    })
+}
+
+fn date_token(date: Date, token: &str) -> String {
+    if let Some(month) = name_token(&MONTHS, token, date.month as usize - 1) {
+        return month;
+    }
+    if let Some(day) = name_token(&DAYS, token, date.day_of_week() as usize - 1) {
+        return day;
+    }
+
+    match token {
+        "3" | "D" | "d" => date.day.to_string(),
+        "03" | "DD" | "dd" => format!("{:02}", date.day),
+        "3rd" | "3RD" => format!("{}{}", date.day, with_case(ordinal_suffix(date.day), token)),
+        "6" | "M" | "m" => date.month.to_string(),
+        "06" | "MM" | "mm" => format!("{:02}", date.month),
+        "1990" | "YYYY" | "yyyy" => date.year.to_string(),
+        "90" | "YY" | "yy" => format!("{:02}", date.year.rem_euclid(100)),
+        _ => token.to_owned()
+    }
+}
+
+pub fn format_date(date: Date, example: &str) -> String {
+    let mut result = String::new();
+    let mut token = String::new();
+
+    for c in example.chars() {
+        if c.is_alphanumeric() {
+            token.push(c);
+        } else {
+            if !token.is_empty() {
+                result.push_str(&date_token(date, &token));
+                token.clear();
+            }
+            result.push(c);
+        }
+    }
+    if !token.is_empty() {
+        result.push_str(&date_token(date, &token));
+    }
+
+    result
+}
+
+pub fn format_number(number: f64, example: &str) -> String {
+    let (whole_part, decimal_part) = match example.rfind('.') {
+        Some(i) => (&example[..i], &example[i + 1..]),
+        None => (example, "")
+    };
+    let decimals = decimal_part.chars().take_while(|c| c.is_ascii_digit()).count();
+    let separate_thousands = whole_part.contains(',');
+
+    let formatted = format!("{:.*}", decimals, number.abs());
+    let (whole, fraction) = match formatted.find('.') {
+        Some(i) => (&formatted[..i], &formatted[i..]),
+        None => (formatted.as_str(), "")
+    };
+
+    let mut result = String::new();
+    if number < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        result.push('-');
+    }
+    for (i, c) in whole.chars().enumerate() {
+        if separate_thousands && i > 0 && (whole.len() - i) % 3 == 0 {
+            result.push(',');
+        }
+        result.push(c);
+    }
+    result.push_str(fraction);
+    result
+}
+
+pub fn format_text(text: &str, example: &str) -> String {
+    match example {
+        "LIKE THIS" => text.to_uppercase(),
+        "like this" => text.to_lowercase(),
+        "Like this" => {
+            let mut chars = text.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new()
+            }
+        },
+        "Like This" => text
+            .split(' ')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
+        _ => text.to_owned()
+    }
+}
+
+// Formats a value the way it would be merged, using the given example or else the library's default format for its type
+pub fn format_value(value: &Value, example: Option<&str>, preferences: &LibraryPreferences) -> String {
+    match value {
+        Value::Unanswered => String::new(),
+        Value::Text(t) => format_text(t, example.unwrap_or("")),
+        Value::Number(n) => match example.or(preferences.number_format.as_deref()) {
+            Some(example) => format_number(*n, example),
+            None if n.fract() == 0.0 => format_number(*n, "9,999"),
+            None => format_number(*n, DEFAULT_NUMBER_FORMAT)
+        },
+        Value::Date(d) => format_date(*d, example.or(preferences.date_format.as_deref()).unwrap_or(DEFAULT_DATE_FORMAT)),
+        Value::TrueFalse(b) => match (b, example) {
+            (_, Some(example)) if example.contains('/') => {
+                let mut options = example.splitn(2, '/');
+                let yes = options.next().unwrap_or_default();
+                let no = options.next().unwrap_or_default();
+                (if *b { yes } else { no }).to_owned()
+            },
+            (true, _) => String::from("true"),
+            (false, _) => String::from("false")
+        },
+        Value::MultipleChoice(selections) => match selections.len() {
+            0 => String::new(),
+            1 => selections[0].clone(),
+            n => format!("{} and {}", selections[..n - 1].join(", "), selections[n - 1])
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use nom::{
     branch::alt,
-    number::complete::float,
+    number::complete::double,
     bytes::complete::tag,
     character::complete::{ anychar, char, line_ending, multispace0, multispace1, not_line_ending, one_of, none_of },
-    combinator::{ map, opt, peek, recognize, value, rest },
+    combinator::{ map, not, opt, peek, recognize, value, verify },
     error::{ context, make_error, ErrorKind },
     multi::{ many0, many_till, separated_list },
     sequence::{ delimited, preceded, separated_pair, tuple, terminated },
@@ -11,11 +11,12 @@ use nom::{
     IResult
 };
 
+use std::fmt;
 use std::iter::FromIterator;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum LimitExpression<'a> {
-    Number(f32),
+    Number(f64),
     Variable(&'a str),
     Function(FunctionCall<'a>),
 }
@@ -89,6 +90,64 @@ pub enum BinaryOperation {
     EndsWith,
 }
 
+impl BinaryOperation {
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperation::Or => 1,
+            BinaryOperation::And => 2,
+            BinaryOperation::EQ
+            | BinaryOperation::GT
+            | BinaryOperation::GTE
+            | BinaryOperation::LT
+            | BinaryOperation::LTE
+            | BinaryOperation::NE => 3,
+            BinaryOperation::Add | BinaryOperation::Subtract => 4,
+            BinaryOperation::Multiply | BinaryOperation::Divide => 5,
+            _ => 6
+        }
+    }
+
+    // Whether the operator compares the order of its operands, e.g. = or <=
+    pub fn is_comparison(&self) -> bool {
+        self.flipped().is_some()
+    }
+
+    // The comparison that gives the same result with its operands swapped
+    pub fn flipped(&self) -> Option<BinaryOperation> {
+        match self {
+            BinaryOperation::EQ | BinaryOperation::NE => Some(self.clone()),
+            BinaryOperation::GT => Some(BinaryOperation::LT),
+            BinaryOperation::GTE => Some(BinaryOperation::LTE),
+            BinaryOperation::LT => Some(BinaryOperation::GT),
+            BinaryOperation::LTE => Some(BinaryOperation::GTE),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for BinaryOperation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self {
+            BinaryOperation::And => "AND",
+            BinaryOperation::Or => "OR",
+            BinaryOperation::Subtract => "-",
+            BinaryOperation::Add => "+",
+            BinaryOperation::Multiply => "*",
+            BinaryOperation::Divide => "/",
+            BinaryOperation::GT => ">",
+            BinaryOperation::LT => "<",
+            BinaryOperation::EQ => "=",
+            BinaryOperation::NE => "!=",
+            BinaryOperation::LTE => "<=",
+            BinaryOperation::GTE => ">=",
+            BinaryOperation::Contains => "CONTAINS",
+            BinaryOperation::StartsWith => "STARTS WITH",
+            BinaryOperation::EndsWith => "ENDS WITH",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum UnaryOperation {
     Not,
@@ -109,7 +168,7 @@ pub struct UnaryExpression<'a> {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum LiteralExpression {
-    Number(f32),
+    Number(f64),
     Text(String),
     Boolean(bool),
     List,
@@ -134,11 +193,84 @@ pub enum Expression<'a> {
     Years(Box<Expression<'a>>)
 }
 
+fn write_arguments(f: &mut fmt::Formatter, args: &[Expression]) -> fmt::Result {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for VariableReference<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(indexer) = &self.indexer {
+            write!(f, "[")?;
+            write_arguments(f, &indexer.args)?;
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FunctionCall<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        write_arguments(f, &self.args)?;
+        write!(f, ")")
+    }
+}
+
+// Writes an operand of a binary expression, adding the parentheses that the parser would have needed to build it.
+// The operators of a binary expression are applied left to right, so its loosest operator decides if it needs them.
+fn write_operand(f: &mut fmt::Formatter, operand: &Expression, needs_parentheses: impl Fn(u8) -> bool) -> fmt::Result {
+    match operand {
+        Expression::Binary(b) if b.right.iter().map(|(op, _)| op.precedence()).min().is_some_and(needs_parentheses) => write!(f, "({})", operand),
+        _ => write!(f, "{}", operand)
+    }
+}
+
+impl fmt::Display for Expression<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Variable(v) => write!(f, "{}", v),
+            Expression::Literal(LiteralExpression::Number(n)) => write!(f, "{}", n),
+            Expression::Literal(LiteralExpression::Text(t)) => write!(f, "\"{}\"", t.replace('"', "\"\"")),
+            Expression::Literal(LiteralExpression::Boolean(true)) => write!(f, "TRUE"),
+            Expression::Literal(LiteralExpression::Boolean(false)) => write!(f, "FALSE"),
+            Expression::Literal(LiteralExpression::List) => write!(f, "LIST"),
+            Expression::Literal(LiteralExpression::Record) => write!(f, "RECORD"),
+            Expression::Binary(b) => {
+                // The left side is always an atom, so a binary expression there must have been parenthesized
+                write_operand(f, &b.left, |_| true)?;
+                for (op, right) in b.right.iter() {
+                    write!(f, " {} ", op)?;
+                    write_operand(f, right, |p| p <= op.precedence())?;
+                }
+                Ok(())
+            },
+            Expression::Unary(u) => match u.operation {
+                UnaryOperation::Not => write!(f, "NOT {}", u.expression),
+                UnaryOperation::Negate => match u.expression {
+                    Expression::Binary(_) => write!(f, "-({})", u.expression),
+                    _ => write!(f, "-{}", u.expression)
+                }
+            },
+            Expression::FunctionCall(call) => write!(f, "{}", call),
+            Expression::Days(e) => write!(f, "{} DAYS", e),
+            Expression::Months(e) => write!(f, "{} MONTHS", e),
+            Expression::Years(e) => write!(f, "{} YEARS", e)
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct IfStatement<'a> {
     pub condition: Expression<'a>,
-    pub then_body: Vec<Statement<'a>>,
-    pub else_body: Vec<Statement<'a>>,
+    pub then_body: Vec<Spanned<'a, Statement<'a>>>,
+    pub else_body: Vec<Spanned<'a, Statement<'a>>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -149,13 +281,13 @@ pub struct CommentStatement<'a> {
 #[derive(Debug, PartialEq, Clone)]
 pub struct WhileStatement<'a> {
     pub condition: Expression<'a>,
-    pub body: Vec<Statement<'a>>,
+    pub body: Vec<Spanned<'a, Statement<'a>>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct RepeatStatement<'a> {
     pub dialog: VariableReference<'a>,
-    pub body: Vec<Statement<'a>>,
+    pub body: Vec<Spanned<'a, Statement<'a>>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -173,9 +305,38 @@ pub enum Statement<'a> {
     Return(Expression<'a>),
 }
 
+// A node along with the text of the script it was parsed from, without surrounding whitespace
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned<'a, T> {
+    pub node: T,
+    pub source: &'a str,
+}
+
+impl<'a, T> Spanned<'a, T> {
+    // The line a statement starts on, e.g. an IF with its condition without the rest of the block
+    pub fn first_line(&self) -> &'a str {
+        self.source.lines().next().unwrap_or(self.source).trim_end()
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Script<'a> {
-    pub body: Vec<Statement<'a>>,
+    pub source: &'a str,
+    pub body: Vec<Spanned<'a, Statement<'a>>>,
+}
+
+fn spanned<'a, T, P>(parser: P) -> impl Fn(&'a str) -> IResult<&'a str, Spanned<'a, T>>
+where P: Fn(&'a str) -> IResult<&'a str, T> {
+    move |input: &'a str| {
+        let (remaining, node) = parser(input)?;
+        let source = input[..input.len() - remaining.len()].trim();
+        Ok((remaining, Spanned { node, source }))
+    }
+}
+
+// Numbers start with a digit, otherwise names like Infinite Loop would be read as numbers
+fn parse_number(input: &str) -> IResult<&str, f64> {
+    preceded(peek(one_of("0123456789.")), double)(input)
 }
 
 fn parse_boolean(input: &str) -> IResult<&str, bool> {
@@ -237,19 +398,7 @@ fn parse_operator_precedence(current_level: u8, input: &str) -> IResult<&str, (B
     // Look ahead to see if the next token is a binary operation.
     let (input, op) = peek(parse_binary_operator)(input)?;
 
-    let next_precedence_level = match op {
-        BinaryOperation::Or => 1,
-        BinaryOperation::And => 2,
-        BinaryOperation::EQ
-        | BinaryOperation::GT
-        | BinaryOperation::GTE
-        | BinaryOperation::LT
-        | BinaryOperation::LTE
-        | BinaryOperation::NE => 3,
-        BinaryOperation::Add | BinaryOperation::Subtract => 4,
-        BinaryOperation::Multiply | BinaryOperation::Divide => 5,
-        _ => 6
-    };
+    let next_precedence_level = op.precedence();
 
     if next_precedence_level < current_level {
         Err(Err::Error(make_error(input, ErrorKind::IsA)))
@@ -286,6 +435,7 @@ fn parse_variable_reference(input: &str) -> IResult<&str, VariableReference> {
         peek(
             alt((
                 value((), peek(line_ending)),
+                value((), not(anychar)),
                 value((), one_of("[](),\"")),
                 value((), preceded(multispace1, parse_boolean)),
                 value((), preceded(multispace1, parse_binary_operator)),
@@ -301,7 +451,7 @@ fn parse_variable_reference(input: &str) -> IResult<&str, VariableReference> {
         )(input)
     }
 
-    let (input, reference) = recognize(many_till(anychar, reserved_word))(input)?;
+    let (input, reference) = verify(recognize(many_till(anychar, reserved_word)), |name: &str| !name.is_empty())(input)?;
 
     let (input, indexer) = opt(parse_indexer)(input)?;
 
@@ -312,7 +462,7 @@ pub fn parse_expression_atom(input: &str) -> IResult<&str, Expression> {
     let (input, expression) = terminated(
         alt((
             parenthesized(parse_entry_expression, '(', ')'),
-            map(float, |d| Expression::Literal(LiteralExpression::Number(d))),
+            map(parse_number, |d| Expression::Literal(LiteralExpression::Number(d))),
             map(parse_boolean, |b| Expression::Literal(LiteralExpression::Boolean(b))),
             // String literal
             map(parse_string_literal, |s| Expression::Literal(LiteralExpression::Text(s))),
@@ -365,7 +515,7 @@ pub fn parse_entry_expression(input: &str) -> IResult<&str, Expression> {
 
 fn parse_limit_expression(input: &str) -> IResult<&str, LimitExpression> {
     alt((
-        map(float, |n| LimitExpression::Number(n)),
+        map(parse_number, |n| LimitExpression::Number(n)),
         map(not_line_ending, |v| LimitExpression::Variable(v)),
     ))(input)
 }
@@ -413,7 +563,7 @@ pub fn parse_if_statement(input: &str) -> IResult<&str, IfStatement> {
             "END IF" => (input, vec![]),
             _ => {
                 let (input, (else_block, _)) = many_till(
-                    alt((else_if, preceded(multispace0, parse_statement))),
+                    alt((spanned(else_if), parse_statement)),
                     preceded(multispace0, peek(tag("END IF"))),
                 )(input)?;
                 (input, else_block)
@@ -454,7 +604,7 @@ pub fn parse_if_statement(input: &str) -> IResult<&str, IfStatement> {
         "END IF" => (input, vec![]),
         _ => {
             let (input, (else_block, _)) = many_till(
-                alt((else_if, preceded(multispace0, parse_statement))),
+                alt((spanned(else_if), parse_statement)),
                 preceded(multispace0, tag("END IF")),
             )(input)?;
             (input, else_block)
@@ -530,22 +680,23 @@ fn parse_instruction(input: &str) -> IResult<&str, Instruction> {
     ))(input)
 }
 
-pub fn parse_statement(input: &str) -> IResult<&str, Statement> {
+pub fn parse_statement(input: &str) -> IResult<&str, Spanned<Statement>> {
     preceded(
         multispace0,
-        alt((
+        spanned(alt((
             map(context("Comment", parse_comment_statement), Statement::Comment),
             map(context("If statement", parse_if_statement), Statement::If),
             map(context("While statement", parse_while_statement), Statement::Loop),
             map(context("Repeat statement", parse_repeat_statement), Statement::Loop),
             map(context("Instruction", parse_instruction), Statement::Instruction),
             map(context("Return statment", parse_return_statement), Statement::Return),
-        )),
+        ))),
     )(input)
 }
 
 pub fn parse_quit_instruction(input: &str) -> IResult<&str, ScriptInstruction> {
-    map(preceded(tag("QUIT"), rest), |_| ScriptInstruction::Quit)(input)
+    // QUIT stops the script but not the parse, anything after it is still part of the script
+    map(terminated(tag("QUIT"), not(none_of(" \t\r\n"))), |_| ScriptInstruction::Quit)(input)
 }
 
 
@@ -646,6 +797,7 @@ pub fn parse_indexer(input: &str) -> IResult<&str, FunctionCall> {
 }
 
 pub fn parse(input: &str) -> IResult<&str, Script> {
+    let source = input;
     let (input, body) = many0(parse_statement)(input)?;
-    Ok((input, Script { body: body }))
+    Ok((input, Script { source, body: body }))
 }
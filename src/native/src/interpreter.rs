@@ -0,0 +1,856 @@
+use crate::hd_script_parser::{ self, * };
+use crate::answers::{ AnswerSet, Date, Value };
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::preferences::LibraryPreferences;
+use crate::builtins;
+use crate::formats;
+use crate::merge_field_parser;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+const DEFAULT_MAX_LOOP_COUNT: u32 = 1000;
+const DEFAULT_MAX_REPEAT_COUNT: u32 = 1000;
+const MAX_CALL_DEPTH: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Evaluation {
+    pub result: Value,
+    pub answers: AnswerSet,
+    // The dialogs and variables that the script asked, in order
+    pub asked: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// What a dialog script did to the controls of its dialog, in the order it did it
+pub struct DialogRun<'a> {
+    pub displayed: Vec<DisplayInstruction<'a>>,
+    pub limit: Option<u32>,
+    pub answers: AnswerSet,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+enum Flow {
+    Continue,
+    Quit
+}
+
+type EvalResult<T> = Result<T, String>;
+
+pub struct Interpreter<'s, 'a> {
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    preferences: &'s LibraryPreferences,
+    max_loop_count: u32,
+    max_repeat_count: u32,
+    today: Date,
+    answers: AnswerSet,
+    asked: Vec<String>,
+    displayed: Vec<DisplayInstruction<'a>>,
+    limit: Option<u32>,
+    // The IF statements that ran and whether their condition held
+    branches: Vec<(&'a str, bool)>,
+    // The zero based iteration of every REPEAT that is currently running, outermost first, along with the dialogs
+    // being repeated
+    context: Vec<usize>,
+    repeating: Vec<String>,
+    depth: usize
+}
+
+fn mismatch(op: &BinaryOperation, left: &Value, right: &Value) -> String {
+    format!("{} can't be used with {} and {} values", op, left.type_name(), right.type_name())
+}
+
+fn as_bool(value: &Value) -> EvalResult<Option<bool>> {
+    match value {
+        Value::TrueFalse(b) => Ok(Some(*b)),
+        Value::Unanswered => Ok(None),
+        v => Err(format!("Expected a true/false value but found a {} value", v.type_name()))
+    }
+}
+
+fn text_eq(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+fn compare(op: &BinaryOperation, ordering: std::cmp::Ordering) -> bool {
+    use std::cmp::Ordering::*;
+    match op {
+        BinaryOperation::EQ => ordering == Equal,
+        BinaryOperation::NE => ordering != Equal,
+        BinaryOperation::GT => ordering == Greater,
+        BinaryOperation::GTE => ordering != Less,
+        BinaryOperation::LT => ordering == Less,
+        BinaryOperation::LTE => ordering != Greater,
+        _ => false
+    }
+}
+
+// HotDocs compares text without regard to case
+fn apply_text(op: &BinaryOperation, a: &str, b: &str) -> Option<Value> {
+    let (a_lower, b_lower) = (a.to_lowercase(), b.to_lowercase());
+    match op {
+        BinaryOperation::Add => Some(Value::Text(format!("{}{}", a, b))),
+        BinaryOperation::Contains => Some(Value::TrueFalse(a_lower.contains(&b_lower))),
+        BinaryOperation::StartsWith => Some(Value::TrueFalse(a_lower.starts_with(&b_lower))),
+        BinaryOperation::EndsWith => Some(Value::TrueFalse(a_lower.ends_with(&b_lower))),
+        op if op.is_comparison() => Some(Value::TrueFalse(compare(op, a_lower.cmp(&b_lower)))),
+        _ => None
+    }
+}
+
+fn apply(op: &BinaryOperation, left: Value, right: Value) -> EvalResult<Value> {
+    match (op, &left, &right) {
+        // AND and OR only need both sides when the first doesn't decide the result
+        (BinaryOperation::And, _, _) => match (as_bool(&left)?, as_bool(&right)?) {
+            (Some(false), _) | (_, Some(false)) => Ok(Value::TrueFalse(false)),
+            (Some(true), Some(true)) => Ok(Value::TrueFalse(true)),
+            _ => Ok(Value::Unanswered)
+        },
+        (BinaryOperation::Or, _, _) => match (as_bool(&left)?, as_bool(&right)?) {
+            (Some(true), _) | (_, Some(true)) => Ok(Value::TrueFalse(true)),
+            (Some(false), Some(false)) => Ok(Value::TrueFalse(false)),
+            _ => Ok(Value::Unanswered)
+        },
+        (_, Value::Unanswered, _) | (_, _, Value::Unanswered) => Ok(Value::Unanswered),
+        (_, Value::Number(a), Value::Number(b)) => match op {
+            BinaryOperation::Add => Ok(Value::Number(a + b)),
+            BinaryOperation::Subtract => Ok(Value::Number(a - b)),
+            BinaryOperation::Multiply => Ok(Value::Number(a * b)),
+            BinaryOperation::Divide if *b == 0.0 => Err(String::from("Division by zero")),
+            BinaryOperation::Divide => Ok(Value::Number(a / b)),
+            op if op.is_comparison() => Ok(Value::TrueFalse(compare(op, a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)))),
+            _ => Err(mismatch(op, &left, &right))
+        },
+        (_, Value::Date(a), Value::Date(b)) if op.is_comparison() => Ok(Value::TrueFalse(compare(op, a.cmp(b)))),
+        (_, Value::TrueFalse(a), Value::TrueFalse(b)) if matches!(op, BinaryOperation::EQ | BinaryOperation::NE) => Ok(Value::TrueFalse(compare(op, a.cmp(b)))),
+        (_, Value::Text(a), Value::Text(b)) => apply_text(op, a, b).ok_or_else(|| mismatch(op, &left, &right)),
+        // A multiple choice variable equals an option when that option is selected
+        (_, Value::MultipleChoice(selections), Value::Text(option)) => {
+            let selected = selections.iter().any(|s| text_eq(s, option));
+            match op {
+                BinaryOperation::EQ | BinaryOperation::Contains => Ok(Value::TrueFalse(selected)),
+                BinaryOperation::NE => Ok(Value::TrueFalse(!selected)),
+                _ => Err(mismatch(op, &left, &right))
+            }
+        },
+        (_, Value::MultipleChoice(a), Value::MultipleChoice(b)) if matches!(op, BinaryOperation::EQ | BinaryOperation::NE) => {
+            let same = a.len() == b.len() && a.iter().all(|s| b.iter().any(|o| text_eq(s, o)));
+            Ok(Value::TrueFalse(if *op == BinaryOperation::EQ { same } else { !same }))
+        },
+        _ => Err(mismatch(op, &left, &right))
+    }
+}
+
+fn expect_arguments(name: &str, args: &[Value], count: usize) -> EvalResult<()> {
+    if args.len() == count {
+        Ok(())
+    } else {
+        Err(format!("{} expects {} argument(s) but was given {}", name, count, args.len()))
+    }
+}
+
+fn number_argument(name: &str, value: &Value) -> EvalResult<f64> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        v => Err(format!("{} expects a number but was given a {} value", name, v.type_name()))
+    }
+}
+
+fn text_argument<'v>(name: &str, value: &'v Value) -> EvalResult<&'v str> {
+    match value {
+        Value::Text(t) => Ok(t),
+        v => Err(format!("{} expects text but was given a {} value", name, v.type_name()))
+    }
+}
+
+fn date_argument(name: &str, value: &Value) -> EvalResult<Date> {
+    match value {
+        Value::Date(d) => Ok(*d),
+        v => Err(format!("{} expects a date but was given a {} value", name, v.type_name()))
+    }
+}
+
+fn round_to(n: f64, places: f64, round: fn(f64) -> f64) -> f64 {
+    let factor = 10f64.powi(places as i32);
+    round(n * factor) / factor
+}
+
+fn years_between(from: Date, to: Date) -> f64 {
+    (from.months_until(to) / 12) as f64
+}
+
+// Built in functions that only need the values of their arguments, ANSWERED and COUNT look at the answers themselves
+fn builtin(name: &str, args: &[Value], today: Date, preferences: &LibraryPreferences) -> EvalResult<Value> {
+    // Most functions are unanswered when any of their arguments are unanswered
+    if name != "ZERO" && name != "FORMAT" && args.iter().any(|a| !a.is_answered()) {
+        return Ok(Value::Unanswered);
+    }
+
+    match name {
+        "ZERO" => {
+            expect_arguments(name, args, 1)?;
+            match &args[0] {
+                Value::Unanswered => Ok(Value::Number(0.0)),
+                v => Ok(Value::Number(number_argument(name, v)?))
+            }
+        },
+        "LENGTH" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Number(text_argument(name, &args[0])?.chars().count() as f64))
+        },
+        "FIRST" | "LAST" => {
+            expect_arguments(name, args, 2)?;
+            let text: Vec<char> = text_argument(name, &args[0])?.chars().collect();
+            let count = (number_argument(name, &args[1])?.max(0.0) as usize).min(text.len());
+            let chars = if name == "FIRST" { &text[..count] } else { &text[text.len() - count..] };
+            Ok(Value::Text(chars.iter().collect()))
+        },
+        "MID" => {
+            expect_arguments(name, args, 3)?;
+            let text: Vec<char> = text_argument(name, &args[0])?.chars().collect();
+            let start = ((number_argument(name, &args[1])?.max(1.0) as usize) - 1).min(text.len());
+            let count = (number_argument(name, &args[2])?.max(0.0) as usize).min(text.len() - start);
+            Ok(Value::Text(text[start..start + count].iter().collect()))
+        },
+        "POSITION" => {
+            expect_arguments(name, args, 2)?;
+            let text = text_argument(name, &args[0])?.to_lowercase();
+            let search = text_argument(name, &args[1])?.to_lowercase();
+            Ok(Value::Number(text.find(&search).map_or(0, |i| text[..i].chars().count() + 1) as f64))
+        },
+        "CAPITALIZE" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Text(formats::format_text(text_argument(name, &args[0])?, "Like this")))
+        },
+        "STRIP" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Text(text_argument(name, &args[0])?.trim().to_owned()))
+        },
+        "INTEGER" => {
+            expect_arguments(name, args, 1)?;
+            let text = text_argument(name, &args[0])?.replace(',', "");
+            text.trim().parse::<f64>()
+                .map(|n| Value::Number(n.trunc()))
+                .map_err(|_| format!("\"{}\" is not a number", text))
+        },
+        "FORMAT" => {
+            expect_arguments(name, args, 2)?;
+            Ok(Value::Text(formats::format_value(&args[0], Some(text_argument(name, &args[1])?), preferences)))
+        },
+        "ROUND" | "TRUNCATE" => {
+            expect_arguments(name, args, 2)?;
+            let round = if name == "ROUND" { f64::round } else { f64::trunc };
+            Ok(Value::Number(round_to(number_argument(name, &args[0])?, number_argument(name, &args[1])?, round)))
+        },
+        "ABSOLUTE VALUE" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Number(number_argument(name, &args[0])?.abs()))
+        },
+        "SQUARE ROOT" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Number(number_argument(name, &args[0])?.sqrt()))
+        },
+        "REMAINDER" | "POWER" | "MAX" | "MIN" => {
+            expect_arguments(name, args, 2)?;
+            let (a, b) = (number_argument(name, &args[0])?, number_argument(name, &args[1])?);
+            match name {
+                "REMAINDER" if b == 0.0 => Err(String::from("Division by zero")),
+                "REMAINDER" => Ok(Value::Number(a % b)),
+                "POWER" => Ok(Value::Number(a.powf(b))),
+                "MAX" => Ok(Value::Number(a.max(b))),
+                _ => Ok(Value::Number(a.min(b)))
+            }
+        },
+        "DATE OF" => {
+            expect_arguments(name, args, 3)?;
+            let (day, month, year) = (number_argument(name, &args[0])?, number_argument(name, &args[1])?, number_argument(name, &args[2])?);
+            Date::new(year as i32, month as u32, day as u32)
+                .map(Value::Date)
+                .ok_or_else(|| format!("{}/{}/{} is not a valid date", day, month, year))
+        },
+        "DAY OF" | "MONTH OF" | "YEAR OF" | "DAY OF WEEK" => {
+            expect_arguments(name, args, 1)?;
+            let date = date_argument(name, &args[0])?;
+            Ok(Value::Number(match name {
+                "DAY OF" => date.day as f64,
+                "MONTH OF" => date.month as f64,
+                "YEAR OF" => date.year as f64,
+                _ => date.day_of_week() as f64
+            }))
+        },
+        "AGE" => {
+            expect_arguments(name, args, 1)?;
+            Ok(Value::Number(years_between(date_argument(name, &args[0])?, today)))
+        },
+        "DAYS FROM" | "MONTHS FROM" | "YEARS FROM" => {
+            expect_arguments(name, args, 2)?;
+            let (from, to) = (date_argument(name, &args[0])?, date_argument(name, &args[1])?);
+            Ok(Value::Number(match name {
+                "DAYS FROM" => (to.to_days() - from.to_days()) as f64,
+                "MONTHS FROM" => from.months_until(to) as f64,
+                _ => years_between(from, to)
+            }))
+        },
+        "SELECTION" => {
+            expect_arguments(name, args, 2)?;
+            match &args[0] {
+                Value::MultipleChoice(selections) => {
+                    let n = number_argument(name, &args[1])? as usize;
+                    Ok(n.checked_sub(1).and_then(|i| selections.get(i)).map_or(Value::Unanswered, |s| Value::Text(s.clone())))
+                },
+                v => Err(format!("{} expects a multiple choice value but was given a {} value", name, v.type_name()))
+            }
+        },
+        _ => Err(format!("{} is not a function", name))
+    }
+}
+
+impl<'s, 'a> Interpreter<'s, 'a> {
+    pub fn new(components: &'s HashMap<String, Component>, scripts: &'s HashMap<&'s str, Script<'a>>, preferences: &'s LibraryPreferences, answers: AnswerSet, today: Date) -> Self {
+        Interpreter {
+            components,
+            scripts,
+            preferences,
+            max_loop_count: preferences.max_loop_count.unwrap_or(DEFAULT_MAX_LOOP_COUNT),
+            max_repeat_count: preferences.max_repeat_count.unwrap_or(DEFAULT_MAX_REPEAT_COUNT),
+            today,
+            answers,
+            asked: vec![],
+            displayed: vec![],
+            limit: None,
+            branches: vec![],
+            context: vec![],
+            repeating: vec![],
+            depth: 0
+        }
+    }
+
+    // Runs the computation and returns its result along with the answers as they are once it has finished
+    pub fn run(self, computation: &str) -> Evaluation {
+        self.run_traced(computation).0
+    }
+
+    // Runs the computation and also returns the source of every IF statement that ran, with whether its condition held
+    pub fn run_traced(mut self, computation: &str) -> (Evaluation, Vec<(&'a str, bool)>) {
+        let (result, diagnostics) = match self.call(computation) {
+            Ok(result) => (result, vec![]),
+            Err(e) => (Value::Unanswered, vec![Diagnostic::error(e).for_component(computation)])
+        };
+
+        (Evaluation { result, answers: self.answers, asked: self.asked, diagnostics }, self.branches)
+    }
+
+    // Evaluates an expression outside of any script
+    pub fn value_of(&mut self, expression: &Expression) -> EvalResult<Value> {
+        self.eval(expression)
+    }
+
+    // Runs a dialog script, recording the GRAY, HIDE, SHOW, REQUIRE and LIMIT instructions it reaches
+    pub fn run_dialog(mut self, dialog: &str, script: &Script<'a>) -> DialogRun<'a> {
+        let diagnostics = match self.execute(&script.body, &mut Value::Unanswered) {
+            Ok(_) => vec![],
+            Err(e) => vec![Diagnostic::error(e).for_component(dialog)]
+        };
+
+        DialogRun { displayed: self.displayed, limit: self.limit, answers: self.answers, diagnostics }
+    }
+
+    fn call(&mut self, computation: &str) -> EvalResult<Value> {
+        let scripts = self.scripts;
+        let script = match scripts.get(computation) {
+            Some(script) => script,
+            None => return Err(format!("{} is not a computation with a script", computation))
+        };
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(format!("{} computations were called inside each other, {} is probably recursive", MAX_CALL_DEPTH, computation));
+        }
+
+        self.depth += 1;
+        let mut result = Value::Unanswered;
+        let outcome = self.execute(&script.body, &mut result);
+        self.depth -= 1;
+
+        outcome.map(|_| result)
+    }
+
+    fn is_dialog(&self, name: &str) -> bool {
+        matches!(self.components.get(name), Some(Component::Dialog { .. }))
+    }
+
+    fn dialog_children(&self, name: &str) -> Vec<String> {
+        match self.components.get(name) {
+            Some(Component::Dialog { children, .. }) => children.clone(),
+            _ => vec![]
+        }
+    }
+
+    fn execute(&mut self, statements: &[Spanned<'a, Statement<'a>>], result: &mut Value) -> EvalResult<Flow> {
+        for statement in statements.iter() {
+            if let Flow::Quit = self.statement(&statement.node, statement.source, result)? {
+                return Ok(Flow::Quit);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn statement(&mut self, statement: &Statement<'a>, source: &'a str, result: &mut Value) -> EvalResult<Flow> {
+        match statement {
+            Statement::Comment(_) => Ok(Flow::Continue),
+            Statement::If(if_statement) => {
+                // An unanswered condition is treated as false
+                let condition = as_bool(&self.eval(&if_statement.condition)?)?.unwrap_or(false);
+                self.branches.push((source, condition));
+                if condition {
+                    self.execute(&if_statement.then_body, result)
+                } else {
+                    self.execute(&if_statement.else_body, result)
+                }
+            },
+            Statement::Loop(LoopStatement::While(while_loop)) => {
+                let mut count = 0;
+                while as_bool(&self.eval(&while_loop.condition)?)?.unwrap_or(false) {
+                    count += 1;
+                    if count > self.max_loop_count {
+                        return Err(format!("The WHILE loop ran more than {} times", self.max_loop_count));
+                    }
+                    if let Flow::Quit = self.execute(&while_loop.body, result)? {
+                        return Ok(Flow::Quit);
+                    }
+                }
+                Ok(Flow::Continue)
+            },
+            Statement::Loop(LoopStatement::Repeat(repeat)) => self.repeat(repeat, result),
+            Statement::Instruction(Instruction::Display(DisplayInstruction::Ask(name))) => {
+                self.asked.push(name.trim().to_owned());
+                Ok(Flow::Continue)
+            },
+            Statement::Instruction(Instruction::Display(DisplayInstruction::Limit(limit))) => {
+                self.limit = self.limit_count(limit)?;
+                Ok(Flow::Continue)
+            },
+            Statement::Instruction(Instruction::Display(instruction)) => {
+                self.displayed.push(instruction.clone());
+                Ok(Flow::Continue)
+            },
+            Statement::Instruction(Instruction::Script(instruction)) => self.instruction(instruction),
+            // A dialog name on its own in a computation doesn't produce a value
+            Statement::Return(Expression::Variable(v)) if self.is_dialog(v.name.trim()) => Ok(Flow::Continue),
+            Statement::Return(expression) => {
+                *result = self.eval(expression)?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    fn limit_count(&mut self, limit: &LimitExpression) -> EvalResult<Option<u32>> {
+        let value = match limit {
+            LimitExpression::Number(n) => Value::Number(*n),
+            LimitExpression::Variable(name) => self.variable(&VariableReference { name: name.trim(), indexer: None })?,
+            LimitExpression::Function(call) => self.function(call)?
+        };
+
+        match value {
+            Value::Number(n) if n >= 0.0 => Ok(Some(n as u32)),
+            Value::Unanswered => Ok(None),
+            v => Err(format!("{} can't be used as the LIMIT of a repeated dialog", formats::format_value(&v, None, self.preferences)))
+        }
+    }
+
+    fn repeat(&mut self, repeat: &RepeatStatement<'a>, result: &mut Value) -> EvalResult<Flow> {
+        let dialog = repeat.dialog.name.trim();
+        let iterations = self
+            .dialog_children(dialog)
+            .iter()
+            .map(|child| self.answers.iterations(child, &self.context))
+            .max()
+            .unwrap_or(0);
+
+        if iterations > self.max_repeat_count as usize {
+            return Err(format!("{} is repeated {} times, more than the maximum of {}", dialog, iterations, self.max_repeat_count));
+        }
+
+        self.repeating.push(dialog.to_owned());
+        let mut flow = Ok(Flow::Continue);
+        for i in 0..iterations {
+            self.context.push(i);
+            flow = self.execute(&repeat.body, result);
+            self.context.pop();
+
+            if !matches!(flow, Ok(Flow::Continue)) {
+                break;
+            }
+        }
+        self.repeating.pop();
+        flow
+    }
+
+    fn instruction(&mut self, instruction: &ScriptInstruction) -> EvalResult<Flow> {
+        match instruction {
+            ScriptInstruction::Set(variable, expression) => {
+                let value = self.eval(expression)?;
+                self.write(variable, value)?;
+            },
+            ScriptInstruction::Default(variable, expression) => {
+                let answered = self.read(variable)?.is_answered();
+                if !answered {
+                    let value = self.eval(expression)?;
+                    self.write(variable, value)?;
+                }
+            },
+            ScriptInstruction::Add(variable, expression) => {
+                let value = match (self.read(variable)?, self.eval(expression)?) {
+                    (Value::Unanswered, v) => v,
+                    (Value::Text(current), Value::Text(added)) => Value::Text(current + &added),
+                    (Value::MultipleChoice(mut current), Value::Text(added)) => {
+                        current.push(added);
+                        Value::MultipleChoice(current)
+                    },
+                    (current, added) => return Err(format!("A {} value can't be added to a {} value", added.type_name(), current.type_name()))
+                };
+                self.write(variable, value)?;
+            },
+            ScriptInstruction::Increment(variable) | ScriptInstruction::Decrement(variable) => {
+                let step = if let ScriptInstruction::Increment(_) = instruction { 1.0 } else { -1.0 };
+                let value = match self.read(variable)? {
+                    Value::Unanswered => step,
+                    Value::Number(n) => n + step,
+                    v => return Err(format!("{} can't be incremented or decremented as it has a {} value", variable.name.trim(), v.type_name()))
+                };
+                self.write(variable, Value::Number(value))?;
+            },
+            ScriptInstruction::Erase(variable) => {
+                let name = variable.name.trim();
+                if variable.indexer.is_some() {
+                    self.write(variable, Value::Unanswered)?;
+                } else if self.is_dialog(name) {
+                    for child in self.dialog_children(name) {
+                        self.answers.erase(&child);
+                    }
+                } else {
+                    self.answers.erase(name);
+                }
+            },
+            ScriptInstruction::Quit => return Ok(Flow::Quit),
+            _ => ()
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn index(&mut self, variable: &VariableReference) -> EvalResult<Option<Vec<usize>>> {
+        match &variable.indexer {
+            Some(indexer) => {
+                let mut index = vec![];
+                for arg in indexer.args.iter() {
+                    match self.eval(arg)? {
+                        Value::Number(n) if n >= 1.0 => index.push(n as usize - 1),
+                        v => return Err(format!("{} can't be used as an index of {}", formats::format_value(&v, None, self.preferences), variable.name.trim()))
+                    }
+                }
+                Ok(Some(index))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn read(&mut self, variable: &VariableReference) -> EvalResult<Value> {
+        let name = variable.name.trim();
+        match self.index(variable)? {
+            Some(index) => Ok(self.answers.get(name, &index)),
+            None => Ok(self.answers
+                .resolve(name, &self.context)
+                .map_or(Value::Unanswered, |index| self.answers.get(name, &index)))
+        }
+    }
+
+    fn check_type(&self, name: &str, value: Value) -> EvalResult<Value> {
+        let expected = match self.components.get(name) {
+            Some(Component::Text(_)) => "text",
+            Some(Component::Number(_)) => "number",
+            Some(Component::Date(_)) => "date",
+            Some(Component::TrueFalse(_)) => "true/false",
+            Some(Component::MultipleChoice(_)) => "multiple choice",
+            Some(Component::Computation(_)) | Some(Component::Dialog { .. }) => return Err(format!("{} can't be given a value", name)),
+            _ => return Ok(value)
+        };
+
+        match value {
+            Value::Text(option) if expected == "multiple choice" => Ok(Value::MultipleChoice(vec![option])),
+            v if !v.is_answered() || v.type_name() == expected => Ok(v),
+            v => Err(format!("{} is a {} variable and can't be set to a {} value", name, expected, v.type_name()))
+        }
+    }
+
+    fn write(&mut self, variable: &VariableReference, value: Value) -> EvalResult<()> {
+        let name = variable.name.trim();
+        let value = self.check_type(name, value)?;
+        // Without an explicit index the answer is written where it would be read from. A new answer is only
+        // repeated as deeply as the innermost running REPEAT whose dialog contains the variable.
+        let index = match self.index(variable)? {
+            Some(index) => index,
+            None => match self.answers.resolve(name, &self.context) {
+                Some(index) => index,
+                None => {
+                    let depth = self
+                        .repeating
+                        .iter()
+                        .rposition(|dialog| self.dialog_children(dialog).iter().any(|child| child == name))
+                        .map_or(0, |i| i + 1);
+                    self.context[..depth].to_vec()
+                }
+            }
+        };
+
+        self.answers.set(name, index, value);
+        Ok(())
+    }
+
+    fn eval(&mut self, expression: &Expression) -> EvalResult<Value> {
+        match expression {
+            Expression::Literal(LiteralExpression::Number(n)) => Ok(Value::Number(*n)),
+            Expression::Literal(LiteralExpression::Text(t)) => self.merge(t),
+            Expression::Literal(LiteralExpression::Boolean(b)) => Ok(Value::TrueFalse(*b)),
+            Expression::Literal(_) => Err(String::from("Lists and records are not supported")),
+            Expression::Variable(v) => self.variable(v),
+            Expression::Unary(u) => match (&u.operation, self.eval(&u.expression)?) {
+                (_, Value::Unanswered) => Ok(Value::Unanswered),
+                (UnaryOperation::Not, Value::TrueFalse(b)) => Ok(Value::TrueFalse(!b)),
+                (UnaryOperation::Negate, Value::Number(n)) => Ok(Value::Number(-n)),
+                (UnaryOperation::Not, v) => Err(format!("NOT can't be used with a {} value", v.type_name())),
+                (UnaryOperation::Negate, v) => Err(format!("A {} value can't be negated", v.type_name()))
+            },
+            Expression::Binary(b) => self.binary(b),
+            Expression::FunctionCall(call) => self.function(call),
+            Expression::Days(_) | Expression::Months(_) | Expression::Years(_) => Err(String::from("DAYS, MONTHS and YEARS can only be added to or subtracted from a date"))
+        }
+    }
+
+    // Merges the answers into the fields of a text literal, e.g. "Dear «Client Name»" or "«Birth Date:3 June 1990»"
+    fn merge(&mut self, text: &str) -> EvalResult<Value> {
+        let mut merged = String::with_capacity(text.len());
+        let mut copied = 0;
+        for field in merge_field_parser::parse(text) {
+            let name = match Span::of(text, field.name) {
+                Some(span) => span,
+                None => continue
+            };
+            let start = text[..name.start].rfind('«').unwrap_or(name.start);
+            let end = text[name.end..].find('»').map_or(text.len(), |i| name.end + i + '»'.len_utf8());
+
+            // The field is read as a variable reference so an index, «Child Name[2]», is kept
+            let reference = text[name.start..end].split([':', '»']).next().unwrap_or(field.name).trim();
+            let value = match hd_script_parser::parse_entry_expression(reference) {
+                Ok((remaining, expression @ Expression::Variable(_))) if remaining.trim().is_empty() => self.eval(&expression)?,
+                _ => self.variable(&VariableReference { name: field.name, indexer: None })?
+            };
+
+            merged.push_str(&text[copied..start]);
+            merged.push_str(&formats::format_value(&value, field.format, self.preferences));
+            copied = end;
+        }
+        merged.push_str(&text[copied..]);
+        Ok(Value::Text(merged))
+    }
+
+    fn variable(&mut self, variable: &VariableReference) -> EvalResult<Value> {
+        let name = variable.name.trim();
+        match name {
+            "TODAY" => Ok(Value::Date(self.today)),
+            "UNANSWERED" => Ok(Value::Unanswered),
+            "COUNTER" => match self.context.last() {
+                Some(i) => Ok(Value::Number((i + 1) as f64)),
+                None => Err(String::from("COUNTER can only be used inside a REPEAT"))
+            },
+            n if self.scripts.contains_key(n) => self.call(n),
+            n if self.is_dialog(n) => Err(format!("The dialog {} doesn't have a value", n)),
+            _ => self.read(variable)
+        }
+    }
+
+    fn interval(&mut self, date: Date, op: &BinaryOperation, expression: &Expression) -> EvalResult<Value> {
+        let (amount, unit) = match expression {
+            Expression::Days(e) => (e, "DAYS"),
+            Expression::Months(e) => (e, "MONTHS"),
+            Expression::Years(e) => (e, "YEARS"),
+            _ => return Err(String::from("Only DAYS, MONTHS or YEARS can be added to or subtracted from a date"))
+        };
+
+        let n = match self.eval(amount)? {
+            Value::Number(n) => n as i64,
+            Value::Unanswered => return Ok(Value::Unanswered),
+            v => return Err(format!("A {} value can't be used as a number of {}", v.type_name(), unit))
+        };
+        let n = if *op == BinaryOperation::Subtract { -n } else { n };
+
+        Ok(Value::Date(match unit {
+            "DAYS" => date.add_days(n),
+            "MONTHS" => date.add_months(n),
+            _ => date.add_months(n * 12)
+        }))
+    }
+
+    fn binary(&mut self, binary: &BinaryExpression) -> EvalResult<Value> {
+        let mut acc = self.eval(&binary.left)?;
+
+        for (op, expression) in binary.right.iter() {
+            acc = match (op, &acc, expression) {
+                (BinaryOperation::Add, Value::Date(d), _) | (BinaryOperation::Subtract, Value::Date(d), _)
+                    if matches!(expression, Expression::Days(_) | Expression::Months(_) | Expression::Years(_)) => {
+                    let date = *d;
+                    self.interval(date, op, expression)?
+                },
+                (BinaryOperation::And, Value::TrueFalse(false), _) => Value::TrueFalse(false),
+                (BinaryOperation::Or, Value::TrueFalse(true), _) => Value::TrueFalse(true),
+                _ => {
+                    let right = self.eval(expression)?;
+                    apply(op, acc, right)?
+                }
+            };
+        }
+
+        Ok(acc)
+    }
+
+    fn function(&mut self, call: &FunctionCall) -> EvalResult<Value> {
+        let name = call.name.trim();
+        match name {
+            "ANSWERED" => {
+                if call.args.len() != 1 {
+                    return Err(format!("ANSWERED expects 1 argument(s) but was given {}", call.args.len()));
+                }
+                match &call.args[0] {
+                    Expression::Variable(v) if self.is_dialog(v.name.trim()) => {
+                        let answered = self
+                            .dialog_children(v.name.trim())
+                            .iter()
+                            .any(|child| self.answers.resolve(child, &self.context).is_some() || self.answers.iterations(child, &self.context) > 0);
+                        Ok(Value::TrueFalse(answered))
+                    },
+                    e => Ok(Value::TrueFalse(self.eval(e)?.is_answered()))
+                }
+            },
+            "COUNT" => match call.args.first() {
+                Some(Expression::Variable(v)) if call.args.len() == 1 && self.is_dialog(v.name.trim()) => {
+                    let count = self
+                        .dialog_children(v.name.trim())
+                        .iter()
+                        .map(|child| self.answers.iterations(child, &self.context))
+                        .max()
+                        .unwrap_or(0);
+                    Ok(Value::Number(count as f64))
+                },
+                _ => Err(String::from("COUNT expects the name of a repeated dialog"))
+            },
+            _ => {
+                let mut args = vec![];
+                for arg in call.args.iter() {
+                    args.push(self.eval(arg)?);
+                }
+
+                if builtins::is_function(name) {
+                    builtin(name, &args, self.today, self.preferences)
+                } else if self.scripts.contains_key(name) {
+                    self.call(name)
+                } else {
+                    Err(format!("{} is not a function", name))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn today() -> Date {
+        Date::new(2020, 6, 15).unwrap()
+    }
+
+    fn evaluate(script: &str, answers: AnswerSet, preferences: &LibraryPreferences) -> Evaluation {
+        let (remaining, ast) = hd_script_parser::parse(script).unwrap();
+        assert!(remaining.trim().is_empty(), "unparsed: {}", remaining);
+        let components = HashMap::new();
+        let scripts: HashMap<&str, Script> = vec![("Test", ast)].into_iter().collect();
+        Interpreter::new(&components, &scripts, preferences, answers, today()).run("Test")
+    }
+
+    fn answers(values: Vec<(&str, Vec<usize>, Value)>) -> AnswerSet {
+        let mut answers = AnswerSet::new();
+        for (name, index, value) in values {
+            answers.set(name, index, value);
+        }
+        answers
+    }
+
+    #[test]
+    fn merges_answers_into_text_literals() {
+        let answers = answers(vec![
+            ("Client Name", vec![], Value::Text(String::from("ann smith"))),
+            ("Birth Date", vec![], Value::Date(Date::new(1980, 2, 1).unwrap())),
+            ("Child Name", vec![1], Value::Text(String::from("Bo")))
+        ]);
+        let script = r#""Dear «Client Name:Like This», born «Birth Date:06/03/1990», «Child Name[2]» «IF Married»""#;
+        let evaluation = evaluate(script, answers, &LibraryPreferences::default());
+        assert_eq!(evaluation.result, Value::Text(String::from("Dear Ann Smith, born 02/01/1980, Bo «IF Married»")));
+    }
+
+    #[test]
+    fn merge_fields_without_a_format_use_the_library_defaults() {
+        let answers = answers(vec![("Birth Date", vec![], Value::Date(Date::new(1980, 2, 1).unwrap()))]);
+        let preferences = LibraryPreferences { date_format: Some(String::from("3 June 1990")), ..Default::default() };
+        assert_eq!(evaluate(r#""«Birth Date»""#, answers.clone(), &preferences).result, Value::Text(String::from("1 February 1980")));
+        assert_eq!(evaluate(r#""«Birth Date»""#, answers, &LibraryPreferences::default()).result, Value::Text(String::from("February 1, 1980")));
+    }
+
+    fn value(script: &str) -> Value {
+        evaluate(script, AnswerSet::new(), &LibraryPreferences::default()).result
+    }
+
+    fn text(t: &str) -> Value {
+        Value::Text(String::from(t))
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> Value {
+        Value::Date(Date::new(year, month, day).unwrap())
+    }
+
+    #[test]
+    fn text_builtins() {
+        assert_eq!(value(r#"FIRST("Hello", 2)"#), text("He"));
+        assert_eq!(value(r#"LAST("Hello", 3)"#), text("llo"));
+        assert_eq!(value(r#"MID("Hello", 2, 3)"#), text("ell"));
+        assert_eq!(value(r#"POSITION("Hello", "l")"#), Value::Number(3.0));
+        assert_eq!(value(r#"LENGTH("Hello")"#), Value::Number(5.0));
+    }
+
+    #[test]
+    fn number_builtins() {
+        assert_eq!(value("ROUND(2.456, 2)"), Value::Number(2.46));
+        assert_eq!(value("TRUNCATE(2.456, 1)"), Value::Number(2.4));
+        assert_eq!(value("REMAINDER(7, 3)"), Value::Number(1.0));
+        assert_eq!(value("MAX(2, 5)"), Value::Number(5.0));
+        let evaluation = evaluate("REMAINDER(7, 0)", AnswerSet::new(), &LibraryPreferences::default());
+        assert_eq!(evaluation.diagnostics[0].message, "Division by zero");
+    }
+
+    #[test]
+    fn adding_months_and_years_keeps_the_day_where_possible() {
+        assert_eq!(value("DATE OF(31, 1, 2020) + 1 MONTHS"), date(2020, 2, 29));
+        assert_eq!(value("DATE OF(29, 2, 2020) + 1 YEARS"), date(2021, 2, 28));
+        assert_eq!(value("DATE OF(1, 3, 2020) - 1 DAYS"), date(2020, 2, 29));
+        assert_eq!(value("DATE OF(15, 6, 2020) - 13 MONTHS"), date(2019, 5, 15));
+    }
+
+    #[test]
+    fn date_differences_count_whole_units() {
+        assert_eq!(value("DAYS FROM(DATE OF(1, 1, 2020), DATE OF(1, 3, 2020))"), Value::Number(60.0));
+        assert_eq!(value("MONTHS FROM(DATE OF(31, 1, 2020), DATE OF(29, 2, 2020))"), Value::Number(0.0));
+        assert_eq!(value("YEARS FROM(DATE OF(16, 6, 2000), DATE OF(15, 6, 2020))"), Value::Number(19.0));
+        assert_eq!(value("AGE(DATE OF(15, 6, 1990))"), Value::Number(30.0));
+        assert_eq!(value("AGE(DATE OF(16, 6, 1990))"), Value::Number(29.0));
+        assert_eq!(value("DAY OF WEEK(DATE OF(15, 6, 2020))"), Value::Number(2.0));
+    }
+}
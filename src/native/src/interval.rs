@@ -0,0 +1,56 @@
+use crate::hd_script_parser::BinaryOperation;
+
+// A range of numbers, with whether each bound is excluded
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Interval {
+    pub lower: Option<(f64, bool)>,
+    pub upper: Option<(f64, bool)>
+}
+
+impl Interval {
+    // The numbers a comparison with a number allows, e.g. Age > 18. Not equal allows two ranges, so it has no interval.
+    pub fn of(op: &BinaryOperation, n: f64) -> Option<Interval> {
+        let (lower, upper) = match op {
+            BinaryOperation::EQ => (Some((n, false)), Some((n, false))),
+            BinaryOperation::GT => (Some((n, true)), None),
+            BinaryOperation::GTE => (Some((n, false)), None),
+            BinaryOperation::LT => (None, Some((n, true))),
+            BinaryOperation::LTE => (None, Some((n, false))),
+            _ => return None
+        };
+        Some(Interval { lower, upper })
+    }
+
+    pub fn contains(&self, n: f64) -> bool {
+        self.lower.is_none_or(|(l, strict)| if strict { n > l } else { n >= l })
+            && self.upper.is_none_or(|(u, strict)| if strict { n < u } else { n <= u })
+    }
+
+    // Whether every number in the interval is in the other one as well
+    pub fn within(&self, other: &Interval) -> bool {
+        let lower = match (self.lower, other.lower) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some((a, a_strict)), Some((b, b_strict))) => a > b || (a == b && (a_strict || !b_strict))
+        };
+        let upper = match (self.upper, other.upper) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some((a, a_strict)), Some((b, b_strict))) => a < b || (a == b && (a_strict || !b_strict))
+        };
+        lower && upper
+    }
+
+    // The numbers both intervals allow
+    pub fn and(&self, other: &Interval) -> Interval {
+        let lower = match (self.lower, other.lower) {
+            (Some((a, a_strict)), Some((b, b_strict))) => Some(if b > a || (b == a && b_strict) { (b, b_strict) } else { (a, a_strict) }),
+            (a, b) => a.or(b)
+        };
+        let upper = match (self.upper, other.upper) {
+            (Some((a, a_strict)), Some((b, b_strict))) => Some(if b < a || (b == a && b_strict) { (b, b_strict) } else { (a, a_strict) }),
+            (a, b) => a.or(b)
+        };
+        Interval { lower, upper }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::hd_script_parser::*;
+use crate::diagnostics::Diagnostic;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum OutlineNode {
+    // A dialog that is asked, along with any dialogs inserted into it
+    Ask { dialog: String, inserted: Vec<OutlineNode> },
+    Condition { condition: String, then_body: Vec<OutlineNode>, else_body: Vec<OutlineNode> },
+    Repeat { dialog: String, body: Vec<OutlineNode> },
+    While { condition: String, body: Vec<OutlineNode> },
+    // Another computation that is run from the interview, with the steps it contributes
+    Computation { name: String, body: Vec<OutlineNode> },
+    // A computation or dialog that is already being expanded higher up the outline
+    Recursion { name: String }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Outline {
+    pub root: Option<String>,
+    pub steps: Vec<OutlineNode>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+struct OutlineBuilder<'s, 'a> {
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    stack: Vec<String>
+}
+
+impl OutlineBuilder<'_, '_> {
+    fn statements(&mut self, statements: &[Spanned<Statement>]) -> Vec<OutlineNode> {
+        statements.iter().flat_map(|s| self.statement(&s.node)).collect()
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Option<OutlineNode> {
+        match statement {
+            Statement::Instruction(Instruction::Display(DisplayInstruction::Ask(name))) => Some(self.ask(name.trim())),
+            Statement::If(if_statement) => self.condition(if_statement),
+            Statement::Loop(LoopStatement::Repeat(repeat)) => Some(OutlineNode::Repeat {
+                dialog: repeat.dialog.to_string(),
+                body: self.statements(&repeat.body)
+            }),
+            Statement::Loop(LoopStatement::While(while_loop)) => Some(OutlineNode::While {
+                condition: while_loop.condition.to_string(),
+                body: self.statements(&while_loop.body)
+            }),
+            // A computation name on its own runs that computation
+            Statement::Return(Expression::Variable(v)) if self.scripts.contains_key(v.name.trim()) => Some(self.computation(v.name.trim())),
+            _ => None
+        }
+    }
+
+    fn condition(&mut self, if_statement: &IfStatement) -> Option<OutlineNode> {
+        let then_body = self.statements(&if_statement.then_body);
+        let else_body = self.statements(&if_statement.else_body);
+
+        // Conditions that don't ask anything don't change the shape of the interview
+        if then_body.is_empty() && else_body.is_empty() {
+            return None;
+        }
+
+        Some(OutlineNode::Condition { condition: if_statement.condition.to_string(), then_body, else_body })
+    }
+
+    fn ask(&mut self, dialog: &str) -> OutlineNode {
+        if self.stack.iter().any(|n| n == dialog) {
+            return OutlineNode::Recursion { name: dialog.to_owned() };
+        }
+
+        let components = self.components;
+        let inserted = match components.get(dialog) {
+            Some(Component::Dialog { children, .. }) => {
+                self.stack.push(dialog.to_owned());
+                let inserted = children
+                    .iter()
+                    .filter(|c| matches!(components.get(c.as_str()), Some(Component::Dialog { .. })))
+                    .map(|c| self.ask(c))
+                    .collect();
+                self.stack.pop();
+                inserted
+            },
+            _ => vec![]
+        };
+
+        OutlineNode::Ask { dialog: dialog.to_owned(), inserted }
+    }
+
+    fn computation(&mut self, name: &str) -> OutlineNode {
+        if self.stack.iter().any(|n| n == name) {
+            return OutlineNode::Recursion { name: name.to_owned() };
+        }
+
+        self.stack.push(name.to_owned());
+        let body = match self.scripts.get(name) {
+            Some(script) => self.statements(&script.body),
+            None => vec![]
+        };
+        self.stack.pop();
+
+        OutlineNode::Computation { name: name.to_owned(), body }
+    }
+}
+
+// Walks the script of the root computation, following the computations it runs, to find the order that dialogs are asked in
+pub fn build(root: &str, components: &HashMap<String, Component>, scripts: &HashMap<&str, Script>) -> Outline {
+    let mut diagnostics = vec![];
+
+    let steps = match scripts.get(root) {
+        Some(script) => {
+            let mut builder = OutlineBuilder { components, scripts, stack: vec![root.to_owned()] };
+            builder.statements(&script.body)
+        },
+        None => {
+            diagnostics.push(Diagnostic::error(format!("{} is not a computation with a script", root)));
+            vec![]
+        }
+    };
+
+    Outline { root: Some(root.to_owned()), steps, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+    use std::collections::HashSet;
+
+    fn dialog(children: &[&str]) -> Component {
+        Component::Dialog { children: children.iter().map(|c| c.to_string()).collect(), script: HashSet::new(), fields: HashSet::new() }
+    }
+
+    fn ask(dialog: &str) -> OutlineNode {
+        OutlineNode::Ask { dialog: dialog.to_owned(), inserted: vec![] }
+    }
+
+    // The outline from the Interview computation, given the scripts of every computation
+    fn outline(computations: &[(&'static str, &'static str)]) -> Outline {
+        let scripts: HashMap<&str, Script> = computations.iter().map(|(name, script)| (*name, hd_script_parser::parse(script).unwrap().1)).collect();
+        let mut components: HashMap<String, Component> = vec![
+            (String::from("Married"), Component::TrueFalse(HashSet::new())),
+            (String::from("Client Details"), dialog(&["Married", "Address"])),
+            (String::from("Address"), dialog(&[])),
+            (String::from("Spouse Details"), dialog(&[])),
+            (String::from("Single Details"), dialog(&[])),
+            (String::from("Children"), dialog(&[])),
+            (String::from("Nested"), dialog(&["Nested"]))
+        ].into_iter().collect();
+        components.extend(computations.iter().map(|(name, _)| (name.to_string(), Component::Computation(HashSet::new()))));
+        build("Interview", &components, &scripts)
+    }
+
+    #[test]
+    fn asked_dialogs_list_the_dialogs_inserted_into_them() {
+        assert_eq!(outline(&[("Interview", "ASK Client Details\nASK Spouse Details")]).steps, vec![
+            OutlineNode::Ask { dialog: String::from("Client Details"), inserted: vec![ask("Address")] },
+            ask("Spouse Details")
+        ]);
+    }
+
+    #[test]
+    fn conditions_keep_the_dialogs_each_branch_asks() {
+        let script = "IF Married\nASK Spouse Details\nELSE\nASK Single Details\nEND IF\nIF Married\nSET Married TO TRUE\nEND IF";
+        assert_eq!(outline(&[("Interview", script)]).steps, vec![
+            OutlineNode::Condition { condition: String::from("Married"), then_body: vec![ask("Spouse Details")], else_body: vec![ask("Single Details")] }
+        ]);
+    }
+
+    #[test]
+    fn loops_list_the_dialogs_they_ask() {
+        let script = "REPEAT Children\nASK Address\nEND REPEAT\nWHILE NOT Married\nASK Client Details\nEND WHILE";
+        assert_eq!(outline(&[("Interview", script)]).steps, vec![
+            OutlineNode::Repeat { dialog: String::from("Children"), body: vec![ask("Address")] },
+            OutlineNode::While {
+                condition: String::from("NOT Married"),
+                body: vec![OutlineNode::Ask { dialog: String::from("Client Details"), inserted: vec![ask("Address")] }]
+            }
+        ]);
+    }
+
+    #[test]
+    fn computations_the_interview_runs_are_expanded() {
+        assert_eq!(outline(&[("Interview", "Ask Spouse\nASK Address"), ("Ask Spouse", "ASK Spouse Details")]).steps, vec![
+            OutlineNode::Computation { name: String::from("Ask Spouse"), body: vec![ask("Spouse Details")] },
+            ask("Address")
+        ]);
+    }
+
+    #[test]
+    fn recursion_stops_at_what_is_already_being_expanded() {
+        assert_eq!(outline(&[("Interview", "First"), ("First", "Second"), ("Second", "First\nInterview")]).steps, vec![
+            OutlineNode::Computation {
+                name: String::from("First"),
+                body: vec![OutlineNode::Computation {
+                    name: String::from("Second"),
+                    body: vec![OutlineNode::Recursion { name: String::from("First") }, OutlineNode::Recursion { name: String::from("Interview") }]
+                }]
+            }
+        ]);
+        assert_eq!(outline(&[("Interview", "ASK Nested")]).steps, vec![
+            OutlineNode::Ask { dialog: String::from("Nested"), inserted: vec![OutlineNode::Recursion { name: String::from("Nested") }] }
+        ]);
+    }
+
+    #[test]
+    fn an_interview_without_a_script_is_reported() {
+        let outline = build("Interview", &HashMap::new(), &HashMap::new());
+        assert_eq!(outline.steps, vec![]);
+        assert_eq!(outline.diagnostics[0].message, "Interview is not a computation with a script");
+    }
+}
@@ -1,128 +1,779 @@
 extern crate wasm_bindgen;
 
 mod hd_script_parser;
-mod xml_parser;
+pub mod xml_parser;
 mod dependency_parser;
+mod merge_field_parser;
+mod component_library;
+mod diagnostics;
+mod preferences;
+mod interview_outline;
+mod answers;
+mod formats;
+mod interpreter;
+mod answer_file;
+mod repeats;
+mod type_checker;
+mod dialog_state;
+mod branch_coverage;
+mod reachability;
+mod interval;
+mod loops;
+mod metrics;
+mod clones;
+mod xml_writer;
+pub mod xml_reader;
+mod encoding;
+mod tokens;
+mod builtins;
+mod completion;
+mod where_used;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
+mod rename;
+mod patch;
 
-use xml_parser::{Node, XmlElement};
+use xml_parser::{Node, Text, XmlElement};
+use component_library::ComponentLibrary;
+use diagnostics::{Diagnostic, LineIndex, Location, Span, Utf16Index};
+use xml_reader::ReadError;
+use preferences::LibraryPreferences;
+use interview_outline::Outline;
+use hd_script_parser::Script;
+use answers::{AnswerSet, Date};
+use interpreter::{Evaluation, Interpreter};
+use answer_file::AnswerFile;
+use type_checker::Type;
+use dialog_state::DialogState;
+use branch_coverage::Coverage;
+use metrics::{MetricsReport, Thresholds};
+use clones::{CloneOptions, CloneReport};
+use patch::{Patch, Patched};
+use tokens::Tokens;
+use completion::Completions;
 use std::collections::{HashSet, HashMap};
 
 use wasm_bindgen::prelude::*;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
-extern crate web_sys;
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Component {
+    Text(HashSet<String>),
+    Number(HashSet<String>),
+    TrueFalse(HashSet<String>),
+    Date(HashSet<String>),
+    Image(HashSet<String>),
+    MultipleChoice(HashSet<String>),
+    DialogElement(HashSet<String>),
+    Computation(HashSet<String>),
+    Dialog { children: Vec<String>, script: HashSet<String>, fields: HashSet<String> }
+}
 
-// A macro to provide `println!(..)`-style syntax for `console.log` logging.
-macro_rules! log {
-    ( $( $t:tt )* ) => {
-        web_sys::console::log_1(&format!( $( $t )* ).into());
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Analysis {
+    pub components: HashMap<String, Component>,
+    pub preferences: LibraryPreferences,
+    // The interview outline, when the library names an interview computation
+    pub outline: Option<Outline>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// Collects the variables merged into the prompts, resources, dialog element text and option merge text of a component.
+// Scripts are skipped as their text literals are handled by the dependency parser.
+fn parse_merge_fields(library: &ComponentLibrary, element: &XmlElement, fields: &mut HashSet<String>) {
+    for child in element.children.iter() {
+        match child {
+            Node::XmlElement(xe) if library.is(xe, "script") => (),
+            Node::XmlElement(xe) => parse_merge_fields(library, xe, fields),
+            Node::Value(text) => {
+                for field in merge_field_parser::parse(&text.value) {
+                    fields.insert(field.name.to_owned());
+                }
+            },
+            Node::Comment(_) | Node::ProcessingInstruction(..) => ()
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum Component {
-    Text,
-    Number,
-    TrueFalse,
-    Date,
-    Image,
-    MultipleChoice,
-    Computation(HashSet<String>),
-    Dialog { children: Vec<String>, script: HashSet<String> }
+fn merge_fields(library: &ComponentLibrary, element: &XmlElement) -> HashSet<String> {
+    let mut fields = HashSet::new();
+    parse_merge_fields(library, element, &mut fields);
+    fields
 }
 
-fn parse_script_node(element: &XmlElement) -> HashSet<String> {
-    let script = 
-        element
-        .children
-        .iter()
-        .filter(|e| match e {
-            Node::XmlElement(xe) if xe.name == "hd:script" => true,
-            _ => false
-        })
-        .nth(0);
-
-    
-    match script {
-        Some(Node::XmlElement(xe)) if xe.children.len() > 0 => {
-            let script_body = xe.children[0].as_value();
-            let (_, ast) = hd_script_parser::parse(script_body).unwrap();
-            dependency_parser::parse(&ast)
+// The text node holding the component's script, comments written next to the script aren't part of it
+fn script_text<'a, 'b>(library: &ComponentLibrary<'a, 'b>, element: &'b XmlElement<'a>) -> Option<&'b Text<'a>> {
+    let script = library.child(element, "script")?;
+    let mut texts = script.children.iter().filter_map(|n| match n {
+        Node::Value(text) => Some(text),
+        _ => None
+    });
+    texts.clone().find(|t| !t.value.trim().is_empty()).or_else(|| texts.next())
+}
+
+fn parse_script<'a, 'b>(library: &ComponentLibrary<'a, 'b>, element: &'b XmlElement<'a>, diagnostics: &mut Vec<Diagnostic>) -> Option<Script<'b>> {
+    parse_script_text(&script_text(library, element)?.value, diagnostics)
+}
+
+fn parse_script_text<'s>(script_body: &'s str, diagnostics: &mut Vec<Diagnostic>) -> Option<Script<'s>> {
+    let unparsed = |remaining: &str| {
+        let line = remaining.trim_start().lines().next().unwrap_or_default();
+        (format!("The script could not be parsed from \"{}\"", line), Span::of(script_body, line))
+    };
+    match hd_script_parser::parse(script_body) {
+        Ok((remaining, ast)) => {
+            if !remaining.trim().is_empty() {
+                let (message, span) = unparsed(remaining);
+                diagnostics.push(Diagnostic::warning(message).at(span));
+            }
+            Some(ast)
         },
-        _ => HashSet::new()
+        Err(nom::Err::Error((remaining, _))) | Err(nom::Err::Failure((remaining, _))) => {
+            let (message, span) = unparsed(remaining);
+            diagnostics.push(Diagnostic::error(message).at(span));
+            None
+        },
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(format!("The script could not be parsed: {:?}", e)));
+            None
+        }
     }
 }
 
-#[wasm_bindgen]
-pub fn analyze(component: &str) -> JsValue {
-    let parsed_cmp = 
-        match xml_parser::parse(component) {
-            Ok((_, cmp)) => cmp,
-            Err(msg) => {
-                log!("{}", msg);
-                panic!("AARRGHHH");
+fn parse_script_node<'a, 'b>(library: &ComponentLibrary<'a, 'b>, element: &'b XmlElement<'a>, diagnostics: &mut Vec<Diagnostic>) -> HashSet<String> {
+    match parse_script(library, element, diagnostics) {
+        Some(ast) => dependency_parser::parse(&ast),
+        None => HashSet::new()
+    }
+}
+
+fn dialog_children(library: &ComponentLibrary, element: &XmlElement, diagnostics: &mut Vec<Diagnostic>) -> Vec<String> {
+    let contents = match library.child(element, "contents") {
+        Some(contents) => contents,
+        None => return vec![]
+    };
+
+    library
+        .children(contents)
+        .filter_map(|item| match item.attributes.get("name") {
+            Some(name) => Some(name.to_owned()),
+            None => {
+                diagnostics.push(Diagnostic::warning(format!("<{}> in the dialog contents has no name attribute and was ignored", item.name)));
+                None
             }
+        })
+        .collect()
+}
+
+fn multiple_choice_name(name: &str) -> String {
+    match name {
+        x if x.ends_with("_SelectionVariable") => x.replace("_SelectionVariable", ""),
+        x if x.ends_with("_MultiSelectVariable") => x.replace("_MultiSelectVariable", ""),
+        x => x.to_owned()
+    }
+}
+
+// The options a multiple choice variable lists, variables that take their options from a table have none
+fn option_names<'a, 'b>(library: &ComponentLibrary<'a, 'b>, element: &'b XmlElement<'a>) -> Option<Vec<String>> {
+    let options = library.child(element, "options")?;
+    let names = library
+        .children(options)
+        .filter(|option| library.is(option, "option"))
+        .filter_map(|option| option.attributes.get("name").cloned())
+        .collect();
+    Some(names)
+}
+
+fn to_component(library: &ComponentLibrary, kind: &str, name: &str, element: &XmlElement, diagnostics: &mut Vec<Diagnostic>) -> Option<(String, Component)> {
+    match kind {
+        "text" => Some((name.to_owned(), Component::Text(merge_fields(library, element)))),
+        "number" => Some((name.to_owned(), Component::Number(merge_fields(library, element)))),
+        "date" => Some((name.to_owned(), Component::Date(merge_fields(library, element)))),
+        "trueFalse" => Some((name.to_owned(), Component::TrueFalse(merge_fields(library, element)))),
+        "image" => Some((name.to_owned(), Component::Image(merge_fields(library, element)))),
+        "dialogElement" => Some((name.to_owned(), Component::DialogElement(merge_fields(library, element)))),
+        "multipleChoice" => Some((multiple_choice_name(name), Component::MultipleChoice(merge_fields(library, element)))),
+        "dialog" => {
+            let children = dialog_children(library, element, diagnostics);
+            let script = parse_script_node(library, element, diagnostics);
+
+            Some((name.to_owned(), Component::Dialog { children, script, fields: merge_fields(library, element) }))
+        },
+        "computation" => {
+            match name {
+                x if x.ends_with("_OptionTable") => None,
+                x if x.ends_with("_TableVariable") => None,
+                x => {
+                    let mut uses = parse_script_node(library, element, diagnostics);
+                    uses.extend(merge_fields(library, element));
+                    Some((x.to_owned(), Component::Computation(uses)))
+                }
+            }
+        },
+        _ => None,
+    }
+}
+
+fn invalid_xml(component: &str, e: ReadError) -> Diagnostic {
+    let location = LineIndex::new(component).location(Span { start: e.offset, end: e.offset });
+    let message = format!("The component file is not valid XML at line {}, column {}: {}", location.start.line, location.start.column, e.message);
+    Diagnostic::error(message).located(Some(location))
+}
+
+// The names scripts use for the components, multiple choice variables are used without their suffix
+// Maps a span of a text node's value to where it was written in the component file, through the escaping of the text
+fn text_span(component: &str, text: &Text, span: Span) -> Option<Span> {
+    let (_, offsets) = xml_parser::unescape_with_offsets(text.source);
+    let start = Span::of(component, text.source)?.start;
+    Some(Span { start: start + offsets.get(span.start)?, end: start + offsets.get(span.end)? })
+}
+
+// Finds where in the component file each diagnostic points to. Spans are mapped through the escaping of the script
+// they are in, diagnostics for a whole component point to its name.
+fn locate_diagnostics(component: &str, library: &ComponentLibrary, diagnostics: &mut [Diagnostic]) {
+    let places = library.components.iter().map(|c| (c.name, script_text(library, c.element), name_location(c.element)));
+    locate(component, places, diagnostics)
+}
+
+// Where diagnostics for a whole component point to
+fn name_location(element: &XmlElement) -> Location {
+    element.attribute("name").map_or(element.location, |a| a.location)
+}
+
+// Locates the diagnostics given the name, script and location of each component
+fn locate<'n, 't: 'n>(component: &str, places: impl Iterator<Item = (&'n str, Option<&'n Text<'t>>, Location)>, diagnostics: &mut [Diagnostic]) {
+    let lines = LineIndex::new(component);
+    let places: HashMap<String, (Option<&Text>, Location)> = places
+        .flat_map(|(name, script, location)| vec![(name.to_owned(), (script, location)), (multiple_choice_name(name), (script, location))])
+        .collect();
+
+    for diagnostic in diagnostics.iter_mut().filter(|d| d.location.is_none()) {
+        let (script, location) = match diagnostic.component.as_ref().and_then(|name| places.get(name)) {
+            Some(place) => *place,
+            None => continue
         };
-    let components = parsed_cmp.children[1].as_element();
-
-    let component_map = components
-        .children
-        .iter()
-        .map(|e| match e {
-            Node::XmlElement(xe) if xe.name == "hd:text" => Some((xe.attributes["name"].to_owned(), Component::Text)),
-            Node::XmlElement(xe) if xe.name == "hd:number" => Some((xe.attributes["name"].to_owned(), Component::Number)),
-            Node::XmlElement(xe) if xe.name == "hd:date" => Some((xe.attributes["name"].to_owned(), Component::Date)),
-            Node::XmlElement(xe) if xe.name == "hd:trueFalse" => Some((xe.attributes["name"].to_owned(), Component::TrueFalse)),
-            Node::XmlElement(xe) if xe.name == "hd:image" => Some((xe.attributes["name"].to_owned(), Component::Image)),
-            Node::XmlElement(xe) if xe.name == "hd:multipleChoice" => {
-                let name = 
-                    match &xe.attributes["name"] {
-                        x if x.ends_with("_SelectionVariable") => x.replace("_SelectionVariable", ""),
-                        x if x.ends_with("_MultiSelectVariable") => x.replace("_MultiSelectVariable", ""),
-                        x => x.to_owned()
-                    };
-
-                Some((name, Component::MultipleChoice))
-            },
-            Node::XmlElement(xe) if xe.name == "hd:dialog" => {
-                let contents = 
-                    xe
-                    .children
-                    .iter()
-                    .filter(|e| match e {
-                        Node::XmlElement(xe) if xe.name == "hd:contents" => true,
-                        _ => false
-                    })
-                    .nth(0);
-                    
-                let children =
-                    match contents {
-                        Some(Node::XmlElement(xe)) => {
-                            xe.children.iter().map(|e| e.as_element().attributes["name"].to_owned()).collect()
-                        },
-                        _ => vec![]
-                    };
-                
-                Some((xe.attributes["name"].to_owned(), Component::Dialog { children: children, script: parse_script_node(xe) }))
-            },
-            Node::XmlElement(xe) if xe.name == "hd:computation" => {
-                match &xe.attributes["name"] {
-                    x if x.ends_with("_OptionTable") => None,
-                    x if x.ends_with("_TableVariable") => None,
-                    x => Some((x.to_owned(), Component::Computation(parse_script_node(xe))))
+
+        let in_script = diagnostic.span.and_then(|span| text_span(component, script?, span)).map(|span| lines.location(span));
+        diagnostic.location = in_script.or(Some(location));
+    }
+}
+
+// What the analysis keeps of a component once it has been read, so the component's tree can be dropped
+struct Summary<'a> {
+    kind: &'a str,
+    name: String,
+    component: Option<(String, Component)>,
+    script: Option<Text<'a>>,
+    result_type: Option<Type>,
+    options: Option<Vec<String>>,
+    location: Location
+}
+
+fn summarize<'a>(library: &ComponentLibrary<'a, '_>, kind: &'a str, name: &str, element: &XmlElement<'a>, diagnostics: &mut Vec<Diagnostic>) -> Summary<'a> {
+    let mut component_diagnostics = vec![];
+    let component = to_component(library, kind, name, element, &mut component_diagnostics);
+    diagnostics.extend(component_diagnostics.into_iter().map(|d| d.for_component(name)));
+
+    Summary {
+        kind,
+        name: name.to_owned(),
+        component,
+        script: script_text(library, element).cloned(),
+        result_type: element.attributes.get("resultType").and_then(|t| Type::parse(t)),
+        options: if kind == "multipleChoice" { option_names(library, element) } else { None },
+        location: name_location(element)
+    }
+}
+
+// The library as the analysis and the tools read it, one component at a time
+struct Loaded<'a> {
+    preferences: LibraryPreferences,
+    components: HashMap<String, Component>,
+    summaries: Vec<Summary<'a>>,
+    // The problems with the library as a whole, then with each of its components
+    diagnostics: Vec<Diagnostic>
+}
+
+impl Loaded<'_> {
+    fn scripts(&self, kind: &str) -> HashMap<&str, Script<'_>> {
+        self.summaries
+            .iter()
+            .filter(|s| s.kind == kind)
+            .filter_map(|s| parse_script_text(&s.script.as_ref()?.value, &mut vec![]).map(|script| (s.name.as_str(), script)))
+            .collect()
+    }
+
+    fn computations(&self) -> HashMap<&str, Script<'_>> {
+        self.scripts("computation")
+    }
+
+    // The result types computations declare with their resultType attribute
+    fn result_types(&self) -> HashMap<String, Type> {
+        self.summaries
+            .iter()
+            .filter(|s| s.kind == "computation")
+            .filter_map(|s| s.result_type.map(|t| (s.name.clone(), t)))
+            .collect()
+    }
+
+    // The options of each multiple choice variable that lists them
+    fn options(&self) -> HashMap<String, Vec<String>> {
+        self.summaries
+            .iter()
+            .filter_map(|s| s.options.clone().map(|options| (multiple_choice_name(&s.name), options)))
+            .collect()
+    }
+
+    // The names scripts use for the components, multiple choice variables are used without their suffix
+    fn names(&self) -> HashSet<String> {
+        self.summaries.iter().map(|s| multiple_choice_name(&s.name)).collect()
+    }
+
+    fn locate(&self, component: &str, diagnostics: &mut [Diagnostic]) {
+        locate(component, self.summaries.iter().map(|s| (s.name.as_str(), s.script.as_ref(), s.location)), diagnostics)
+    }
+}
+
+// Reads the library in one pass, summarizing each component as it streams by instead of building the tree of the
+// whole library, and adding the uses in each component to the index when there is one
+fn load_library<'a>(component: &'a str, mut uses: Option<&mut where_used::Builder>) -> Result<Loaded<'a>, Box<Diagnostic>> {
+    let mut names = None;
+    let mut seen = HashSet::new();
+    let mut read_diagnostics = vec![];
+    let mut diagnostics = vec![];
+    let mut summaries = vec![];
+
+    let parsed_cmp = xml_reader::read_library(component, |element, root| {
+        let library = names.get_or_insert_with(|| component_library::named(root));
+        // The library is loaded without components when the root isn't a component library
+        if !library.is(root, "componentLibrary") {
+            return;
+        }
+        match library.identify(&element, &mut seen) {
+            Ok((kind, name, duplicate)) => {
+                read_diagnostics.extend(duplicate);
+                if let Some(uses) = uses.as_mut() {
+                    uses.add_component(library, name, &element);
                 }
+                summaries.push(summarize(library, kind, name, &element, &mut diagnostics));
             },
-            _ => None,
+            Err(diagnostic) => read_diagnostics.push(*diagnostic)
+        }
+    });
+    let parsed_cmp = parsed_cmp.map_err(|e| Box::new(invalid_xml(component, e)))?;
+
+    let library = component_library::load(&parsed_cmp);
+    let components = summaries.iter_mut().filter_map(|s| s.component.take()).collect();
+    let mut all_diagnostics = library.diagnostics;
+    all_diagnostics.extend(read_diagnostics);
+    all_diagnostics.extend(diagnostics);
+
+    Ok(Loaded { preferences: library.preferences, components, summaries, diagnostics: all_diagnostics })
+}
+
+pub fn analyze_component_file(component: &str) -> Analysis {
+    analyze_library(component, None)
+}
+
+// Analyzes the component file and indexes where each component is used in the same pass over it
+pub fn analyze_and_index(component: &str) -> (Analysis, where_used::Index) {
+    let mut uses = where_used::Builder::new(component);
+    let analysis = analyze_library(component, Some(&mut uses));
+    (analysis, uses.finish())
+}
+
+fn analyze_library(component: &str, uses: Option<&mut where_used::Builder>) -> Analysis {
+    let mut library = match load_library(component, uses) {
+        Ok(library) => library,
+        Err(diagnostic) => return unreadable(*diagnostic)
+    };
+    let mut all_diagnostics = std::mem::take(&mut library.diagnostics);
+    let components = &library.components;
+    let mut diagnostics = vec![];
+
+    if let Some(interview) = &library.preferences.interview_computation {
+        match components.get(interview) {
+            Some(Component::Computation(_)) => (),
+            _ => diagnostics.push(Diagnostic::warning(format!("The interview computation {} is not a computation in this library", interview)))
+        }
+    }
+
+    let computations = library.computations();
+    let dialogs = library.scripts("dialog");
+    diagnostics.extend(type_checker::check(components, &computations, &dialogs, &library.result_types()));
+    diagnostics.extend(reachability::check(components, &computations, &dialogs, &library.preferences));
+    diagnostics.extend(loops::check(components, &computations, &dialogs));
+    diagnostics.extend(repeats::check(components, &computations, &dialogs));
+
+    let outline = library.preferences.interview_computation.as_ref().map(|root| interview_outline::build(root, components, &computations));
+
+    all_diagnostics.extend(diagnostics);
+    library.locate(component, &mut all_diagnostics);
+
+    Analysis { components: library.components, preferences: library.preferences, outline, diagnostics: all_diagnostics }
+}
+
+fn unreadable(diagnostic: Diagnostic) -> Analysis {
+    Analysis { components: HashMap::new(), preferences: LibraryPreferences::default(), outline: None, diagnostics: vec![diagnostic] }
+}
+
+// Builds the interview outline from the given computation, or the library's interview computation when no root is given
+pub fn outline_component_file(component: &str, root: Option<&str>) -> Outline {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => return Outline { root: None, steps: vec![], diagnostics: vec![*diagnostic] }
+    };
+
+    let root = match root.or(library.preferences.interview_computation.as_deref()) {
+        Some(root) => root,
+        None => {
+            let diagnostic = Diagnostic::error(String::from("No interview computation is set in the component library preferences"));
+            return Outline { root: None, steps: vec![], diagnostics: vec![diagnostic] };
+        }
+    };
+
+    interview_outline::build(root, &library.components, &library.computations())
+}
+
+// Runs a computation against the given answers and returns its result along with the answers it changed
+pub fn evaluate_computation(component: &str, computation: &str, answers: AnswerSet, today: Date) -> Evaluation {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => return Evaluation { result: answers::Value::Unanswered, answers, asked: vec![], diagnostics: vec![*diagnostic] }
+    };
+    let scripts = library.computations();
+
+    Interpreter::new(&library.components, &scripts, &library.preferences, answers, today).run(computation)
+}
+
+// Runs the dialog's script against the given answers and returns whether each control on the dialog is visible, grayed
+// and required
+pub fn simulate_dialog(component: &str, dialog: &str, answers: AnswerSet, today: Date) -> DialogState {
+    let failed = |answers, diagnostic| DialogState { dialog: dialog.to_owned(), controls: vec![], limit: None, answers, diagnostics: vec![diagnostic] };
+
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => return failed(answers, *diagnostic)
+    };
+    let children = match library.components.get(dialog) {
+        Some(Component::Dialog { children, .. }) => children,
+        _ => return failed(answers, Diagnostic::error(format!("{} is not a dialog in this library", dialog)))
+    };
+
+    let scripts = library.computations();
+    let mut dialogs = library.scripts("dialog");
+    let interpreter = Interpreter::new(&library.components, &scripts, &library.preferences, answers, today);
+    let run = match dialogs.remove(dialog) {
+        Some(script) => interpreter.run_dialog(dialog, &script),
+        None => interpreter.run_dialog(dialog, &Script { source: "", body: vec![] })
+    };
+
+    dialog_state::build(dialog, children, run)
+}
+
+// Reads a HotDocs answer file and checks its answers against the component library
+pub fn check_answer_file(component: &str, answer_file: &str) -> AnswerFile {
+    let parsed_answers =
+        match xml_reader::read_tree(answer_file) {
+            Ok(answers) => answers,
+            Err(e) => {
+                let position = LineIndex::new(answer_file).position(e.offset);
+                let diagnostic = Diagnostic::error(format!("The answer file is not valid XML at line {}, column {}: {}", position.line, position.column, e.message));
+                return AnswerFile { title: None, answers: AnswerSet::new(), diagnostics: vec![diagnostic] };
+            }
+        };
+
+    let mut file = answer_file::parse(&parsed_answers);
+
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => {
+            file.diagnostics.push(*diagnostic);
+            return file;
+        }
+    };
+
+    let diagnostics = answer_file::validate(&file.answers, &library.components, &library.options(), &library.computations());
+    file.diagnostics.extend(diagnostics);
+    file
+}
+
+// Finds answers that run every branch of the given computation, or of every computation when none is given
+pub fn generate_coverage(component: &str, computation: Option<&str>, today: Date) -> Vec<Coverage> {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => {
+            let failed = Coverage { computation: computation.unwrap_or_default().to_owned(), branches: vec![], cases: vec![], diagnostics: vec![*diagnostic] };
+            return vec![failed];
+        }
+    };
+    let scripts = library.computations();
+    let options = library.options();
+
+    let mut names: Vec<&str> = match computation {
+        Some(computation) => vec![computation],
+        None => scripts.keys().copied().collect()
+    };
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let mut coverage = branch_coverage::generate(name, &library.components, &scripts, &options, &library.preferences, today);
+            library.locate(component, &mut coverage.diagnostics);
+            coverage
         })
-        .fold(
-            HashMap::new(), 
-            |mut acc, e| match e {
-                Some((name, cmp)) => {
-                    acc.insert(name, cmp);
-                    acc
-                },
-                _ => acc
-            });
-
-    JsValue::from_serde(&component_map).unwrap()
+        .collect()
+}
+
+// Measures the scripts in the library and reports the components past the thresholds
+pub fn measure_component_file(component: &str, thresholds: &Thresholds) -> MetricsReport {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => {
+            let empty = metrics::measure(&HashMap::new(), &HashMap::new(), &HashMap::new(), thresholds);
+            return MetricsReport { diagnostics: vec![*diagnostic], ..empty };
+        }
+    };
+
+    let mut report = metrics::measure(&library.components, &library.computations(), &library.scripts("dialog"), thresholds);
+    library.locate(component, &mut report.diagnostics);
+    report
+}
+
+// Finds computation and dialog scripts that are copies of each other
+pub fn find_clones(component: &str, options: &CloneOptions) -> CloneReport {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => return CloneReport { groups: vec![], diagnostics: vec![*diagnostic] }
+    };
+    let mut scripts = library.computations();
+    scripts.extend(library.scripts("dialog"));
+
+    let mut report = clones::find(&scripts, options);
+    library.locate(component, &mut report.diagnostics);
+    report
+}
+
+// Analyzes a component file as it was read from disk, decoding it from the encoding it was saved in
+pub fn analyze_component_bytes(component: &[u8]) -> Analysis {
+    decode_and_analyze(component, |text| (analyze_component_file(text), ())).0
+}
+
+pub fn analyze_and_index_bytes(component: &[u8]) -> (Analysis, where_used::Index) {
+    decode_and_analyze(component, analyze_and_index)
+}
+
+fn decode_and_analyze<T: Default>(component: &[u8], analyze: impl FnOnce(&str) -> (Analysis, T)) -> (Analysis, T) {
+    let text = match encoding::decode(component) {
+        Ok(text) => text,
+        Err(e) => return (unreadable(Diagnostic::error(format!("The component file is not valid {} at byte {}", e.encoding, e.offset))), T::default())
+    };
+
+    let (mut analysis, extra) = analyze(&text);
+    if let Some(name) = encoding::unsupported(component) {
+        let location = text.find(&name).map(|start| LineIndex::new(&text).location(Span { start, end: start + name.len() }));
+        let message = format!("The {} encoding the XML declaration names isn't supported, the file was read as UTF-8", name);
+        analysis.diagnostics.insert(0, Diagnostic::warning(message).located(location));
+    }
+    (analysis, extra)
+}
+
+// Reads the whole tree of the library for the entry points that edit it. Renames and patches edit components anywhere
+// in the library at once, so unlike the checks they can't read it one component at a time.
+fn edit_library(component: &str, edit: impl FnOnce(&ComponentLibrary) -> Patched) -> Patched {
+    match xml_reader::read_tree(component) {
+        Ok(root) => edit(&component_library::load(&root)),
+        Err(e) => Patched::unchanged(component, vec![invalid_xml(component, e)])
+    }
+}
+
+// Renames a component throughout the library, returning the updated component file
+pub fn rename_component(component: &str, old: &str, new: &str) -> Patched {
+    edit_library(component, |library| {
+        let mut renamed = rename::rename(component, library, old, new);
+        locate_diagnostics(component, library, &mut renamed.diagnostics);
+        renamed
+    })
+}
+
+// Applies the patches to the component file, changing nothing outside the patched spans
+pub fn patch_component_file(component: &str, patches: &[Patch]) -> Patched {
+    edit_library(component, |library| patch::apply(component, library, patches))
+}
+
+// Splits a script into tokens for highlighting, resolving the names it uses against the component library
+pub fn tokenize_script(component: &str, script: &str) -> Tokens {
+    match load_library(component, None) {
+        Ok(library) => tokens::tokenize(script, &library.names()),
+        Err(diagnostic) => {
+            let mut tokens = tokens::tokenize(script, &HashSet::new());
+            tokens.diagnostics.push(*diagnostic);
+            tokens
+        }
+    }
+}
+
+// The completions that fit the cursor at the byte offset into the script, best first
+pub fn complete_script(component: &str, script: &str, offset: usize) -> Completions {
+    let library = match load_library(component, None) {
+        Ok(library) => library,
+        Err(diagnostic) => return Completions { span: Span { start: offset, end: offset }, items: vec![], diagnostics: vec![*diagnostic] }
+    };
+    let types = type_checker::types(&library.components, &library.computations(), &library.result_types());
+    let options = library.options();
+    completion::complete(script, offset, &completion::Library { components: &library.components, types: &types, options: &options })
+}
+
+// Finds every use of every component, to be queried for one component at a time
+pub fn index_uses(component: &str) -> where_used::Index {
+    let mut uses = where_used::Builder::new(component);
+    match load_library(component, Some(&mut uses)) {
+        Ok(_) => uses.finish(),
+        Err(diagnostic) => where_used::Index::unreadable(*diagnostic)
+    }
+}
+
+// Reads an argument passed from JavaScript, failing with an error naming the argument rather than using a default
+fn from_js<T: DeserializeOwned>(value: &JsValue, argument: &str) -> Result<T, JsValue> {
+    value.into_serde().map_err(|e| JsValue::from_str(&format!("The {} could not be read: {}", argument, e)))
+}
+
+fn today_from_js(today: &str) -> Result<Date, JsValue> {
+    Date::parse_iso(today).ok_or_else(|| JsValue::from_str(&format!("Today's date \"{}\" is not written as YYYY-MM-DD", today)))
+}
+
+#[wasm_bindgen]
+pub fn analyze(component: &str) -> Analyzed {
+    let (analysis, uses) = analyze_and_index(component);
+    Analyzed { analysis, uses }
+}
+
+#[wasm_bindgen]
+pub fn analyze_bytes(component: &[u8]) -> Analyzed {
+    let (analysis, uses) = analyze_and_index_bytes(component);
+    Analyzed { analysis, uses }
+}
+
+#[wasm_bindgen]
+pub fn decode(bytes: &[u8]) -> String {
+    encoding::decode_lossy(bytes)
+}
+
+#[wasm_bindgen]
+pub fn outline(component: &str, root: Option<String>) -> JsValue {
+    JsValue::from_serde(&outline_component_file(component, root.as_deref())).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn evaluate(component: &str, computation: &str, answers: &JsValue, today: &str) -> Result<JsValue, JsValue> {
+    let answers = from_js(answers, "answers")?;
+    let today = today_from_js(today)?;
+    Ok(JsValue::from_serde(&evaluate_computation(component, computation, answers, today)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn check_answers(component: &str, answer_file: &str) -> JsValue {
+    JsValue::from_serde(&check_answer_file(component, answer_file)).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn dialog(component: &str, dialog: &str, answers: &JsValue, today: &str) -> Result<JsValue, JsValue> {
+    let answers = from_js(answers, "answers")?;
+    let today = today_from_js(today)?;
+    Ok(JsValue::from_serde(&simulate_dialog(component, dialog, answers, today)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn coverage(component: &str, computation: Option<String>, today: &str) -> Result<JsValue, JsValue> {
+    let today = today_from_js(today)?;
+    Ok(JsValue::from_serde(&generate_coverage(component, computation.as_deref(), today)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn metrics(component: &str, thresholds: &JsValue) -> Result<JsValue, JsValue> {
+    let thresholds = from_js(thresholds, "thresholds")?;
+    Ok(JsValue::from_serde(&measure_component_file(component, &thresholds)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn clones(component: &str, options: &JsValue) -> Result<JsValue, JsValue> {
+    let options = from_js(options, "clone options")?;
+    Ok(JsValue::from_serde(&find_clones(component, &options)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn rename(component: &str, old: &str, new: &str) -> JsValue {
+    JsValue::from_serde(&rename_component(component, old, new)).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn patch(component: &str, patches: &JsValue) -> Result<JsValue, JsValue> {
+    let patches: Vec<Patch> = from_js(patches, "patches")?;
+    Ok(JsValue::from_serde(&patch_component_file(component, &patches)).unwrap())
+}
+
+#[wasm_bindgen]
+pub fn tokens(component: &str, script: &str) -> JsValue {
+    JsValue::from_serde(&tokenize_script(component, script).in_utf16(script)).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn complete(component: &str, script: &str, offset: usize) -> JsValue {
+    // JavaScript indexes the script in UTF-16 code units
+    let index = Utf16Index::new(script);
+    let mut completions = complete_script(component, script, index.to_byte(offset));
+    completions.span = index.span(completions.span);
+    JsValue::from_serde(&completions).unwrap()
+}
+
+// The analysis with the uses of every component, kept by the caller so the component file is only read once however
+// many components are looked up
+#[wasm_bindgen]
+pub struct Analyzed {
+    analysis: Analysis,
+    uses: where_used::Index
+}
+
+#[wasm_bindgen]
+impl Analyzed {
+    pub fn analysis(&self) -> JsValue {
+        JsValue::from_serde(&self.analysis).unwrap()
+    }
+
+    pub fn uses(&self, name: &str) -> JsValue {
+        JsValue::from_serde(&self.uses.uses(name)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNREADABLE: &str = "<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:components>";
+
+    #[test]
+    fn every_entry_point_reports_an_unreadable_library_the_same_way() {
+        let today = Date::new(2020, 6, 15).unwrap();
+        let reports = vec![
+            analyze_component_file(UNREADABLE).diagnostics,
+            outline_component_file(UNREADABLE, Some("Interview")).diagnostics,
+            evaluate_computation(UNREADABLE, "Interview", AnswerSet::new(), today).diagnostics,
+            simulate_dialog(UNREADABLE, "Details", AnswerSet::new(), today).diagnostics,
+            check_answer_file(UNREADABLE, "<AnswerSet/>").diagnostics,
+            generate_coverage(UNREADABLE, None, today).remove(0).diagnostics,
+            measure_component_file(UNREADABLE, &Thresholds::default()).diagnostics,
+            find_clones(UNREADABLE, &CloneOptions::default()).diagnostics,
+            rename_component(UNREADABLE, "A", "B").diagnostics,
+            patch_component_file(UNREADABLE, &[]).diagnostics,
+            tokenize_script(UNREADABLE, "1").diagnostics,
+            complete_script(UNREADABLE, "IF ", 3).diagnostics
+        ];
+
+        for diagnostics in reports {
+            let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+            assert_eq!(messages, vec!["The component file is not valid XML at line 2, column 16: The document ends before </hd:components>"]);
+            assert_eq!(diagnostics[0].location.map(|l| l.start.line), Some(2));
+        }
+    }
 }
@@ -0,0 +1,266 @@
+use crate::hd_script_parser::*;
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::repeats::expression_names;
+use crate::Component;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// The branches of an IF that can run, an IF TRUE never runs its ELSE and an IF FALSE never runs its body
+fn reachable_branches<'s, 'a>(s: &'s IfStatement<'a>) -> Vec<&'s [Spanned<'a, Statement<'a>>]> {
+    match s.condition {
+        Expression::Literal(LiteralExpression::Boolean(true)) => vec![&s.then_body],
+        Expression::Literal(LiteralExpression::Boolean(false)) => vec![&s.else_body],
+        _ => vec![&s.then_body, &s.else_body]
+    }
+}
+
+fn has_quit(statements: &[Spanned<Statement>]) -> bool {
+    statements.iter().any(|statement| match &statement.node {
+        Statement::Instruction(Instruction::Script(ScriptInstruction::Quit)) => true,
+        Statement::If(s) => reachable_branches(s).into_iter().any(has_quit),
+        Statement::Loop(LoopStatement::While(s)) => has_quit(&s.body),
+        Statement::Loop(LoopStatement::Repeat(s)) => has_quit(&s.body),
+        _ => false
+    })
+}
+
+fn statement_expressions<'s, 'a>(statement: &'s Statement<'a>) -> Vec<&'s Expression<'a>> {
+    match statement {
+        Statement::If(s) => vec![&s.condition],
+        Statement::Loop(LoopStatement::While(s)) => vec![&s.condition],
+        Statement::Return(e) => vec![e],
+        Statement::Instruction(Instruction::Script(ScriptInstruction::Set(_, e)))
+        | Statement::Instruction(Instruction::Script(ScriptInstruction::Add(_, e)))
+        | Statement::Instruction(Instruction::Script(ScriptInstruction::Default(_, e))) => vec![e],
+        _ => vec![]
+    }
+}
+
+struct Effects<'s, 'a> {
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    // What each computation reads and writes once the computations it uses are included
+    reads: HashMap<String, BTreeSet<String>>,
+    writes: HashMap<String, BTreeSet<String>>,
+    visiting: HashSet<String>
+}
+
+impl<'s, 'a> Effects<'s, 'a> {
+    // The answers asking a dialog can change
+    fn asked(&self, dialog: &str, writes: &mut BTreeSet<String>) {
+        if let Some(Component::Dialog { children, .. }) = self.components.get(dialog) {
+            writes.extend(children.iter().cloned());
+        }
+    }
+
+    fn computation_reads(&mut self, name: &str) -> BTreeSet<String> {
+        if let Some(reads) = self.reads.get(name) {
+            return reads.clone();
+        }
+        let script = match self.scripts.get(name) {
+            Some(script) if self.visiting.insert(name.to_owned()) => script,
+            _ => return BTreeSet::new()
+        };
+
+        let mut names = vec![];
+        collect_statement_names(&script.body, &mut names);
+        let mut reads = BTreeSet::new();
+        for used in names {
+            reads.extend(self.computation_reads(used));
+            reads.insert(used.to_owned());
+        }
+
+        self.visiting.remove(name);
+        self.reads.insert(name.to_owned(), reads.clone());
+        reads
+    }
+
+    fn expression_reads(&mut self, expression: &Expression) -> BTreeSet<String> {
+        let mut names = vec![];
+        expression_names(expression, &mut names);
+        let mut reads = BTreeSet::new();
+        for name in names {
+            reads.extend(self.computation_reads(name));
+            reads.insert(name.to_owned());
+        }
+        reads
+    }
+
+    fn computation_writes(&mut self, name: &str) -> BTreeSet<String> {
+        if let Some(writes) = self.writes.get(name) {
+            return writes.clone();
+        }
+        let script = match self.scripts.get(name) {
+            Some(script) if self.visiting.insert(name.to_owned()) => script,
+            _ => return BTreeSet::new()
+        };
+
+        let mut writes = BTreeSet::new();
+        self.statement_writes(&script.body, &mut writes);
+
+        self.visiting.remove(name);
+        self.writes.insert(name.to_owned(), writes.clone());
+        writes
+    }
+
+    // The variables running the statements can change, including through the computations they use and the dialogs
+    // they ask
+    fn statement_writes(&mut self, statements: &[Spanned<Statement>], writes: &mut BTreeSet<String>) {
+        for statement in statements.iter() {
+            let mut names = vec![];
+            for expression in statement_expressions(&statement.node) {
+                expression_names(expression, &mut names);
+            }
+            for name in names {
+                writes.extend(self.computation_writes(name));
+            }
+
+            match &statement.node {
+                Statement::If(s) => {
+                    for branch in reachable_branches(s) {
+                        self.statement_writes(branch, writes);
+                    }
+                },
+                Statement::Loop(LoopStatement::While(s)) => self.statement_writes(&s.body, writes),
+                Statement::Loop(LoopStatement::Repeat(s)) => {
+                    self.asked(s.dialog.name.trim(), writes);
+                    self.statement_writes(&s.body, writes);
+                },
+                Statement::Instruction(Instruction::Script(
+                    ScriptInstruction::Set(v, _) | ScriptInstruction::Add(v, _) | ScriptInstruction::Default(v, _)
+                    | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) | ScriptInstruction::Erase(v)
+                )) => {
+                    writes.insert(v.name.trim().to_owned());
+                },
+                Statement::Instruction(Instruction::Display(DisplayInstruction::Ask(name))) => {
+                    writes.extend(self.computation_writes(name.trim()));
+                    self.asked(name.trim(), writes);
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+fn collect_statement_names<'e>(statements: &'e [Spanned<Statement>], names: &mut Vec<&'e str>) {
+    for statement in statements.iter() {
+        for expression in statement_expressions(&statement.node) {
+            expression_names(expression, names);
+        }
+        match &statement.node {
+            Statement::If(s) => {
+                collect_statement_names(&s.then_body, names);
+                collect_statement_names(&s.else_body, names);
+            },
+            Statement::Loop(LoopStatement::While(s)) => collect_statement_names(&s.body, names),
+            Statement::Loop(LoopStatement::Repeat(s)) => collect_statement_names(&s.body, names),
+            _ => ()
+        }
+    }
+}
+
+fn check_loops(source: &str, statements: &[Spanned<Statement>], effects: &mut Effects, diagnostics: &mut Vec<Diagnostic>) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::If(s) => {
+                check_loops(source, &s.then_body, effects, diagnostics);
+                check_loops(source, &s.else_body, effects, diagnostics);
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => check_loops(source, &s.body, effects, diagnostics),
+            Statement::Loop(LoopStatement::While(s)) => {
+                check_loops(source, &s.body, effects, diagnostics);
+
+                // QUIT ends the whole script, which ends the loop as well
+                if has_quit(&s.body) {
+                    continue;
+                }
+
+                let reads = effects.expression_reads(&s.condition);
+                let mut writes = BTreeSet::new();
+                effects.statement_writes(&s.body, &mut writes);
+                if reads.iter().any(|r| writes.contains(r)) {
+                    continue;
+                }
+
+                let variables: Vec<&String> = reads.iter().filter(|r| effects.components.contains_key(r.as_str())).collect();
+                let message = if variables.is_empty() {
+                    format!("WHILE {} never ends once it starts: the condition reads no variables and the loop has no QUIT", s.condition)
+                } else {
+                    let names = variables.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(", ");
+                    format!("WHILE {} never ends once it starts: the loop doesn't change {}, which the condition reads, and has no QUIT", s.condition, names)
+                };
+                diagnostics.push(Diagnostic::warning(message).at(Span::of(source, statement.first_line())));
+            },
+            _ => ()
+        }
+    }
+}
+
+// Finds WHILE loops that can't end, because nothing the loop does can change whether its condition holds
+pub fn check(components: &HashMap<String, Component>, computations: &HashMap<&str, Script>, dialogs: &HashMap<&str, Script>) -> Vec<Diagnostic> {
+    let mut effects = Effects { components, scripts: computations, reads: HashMap::new(), writes: HashMap::new(), visiting: HashSet::new() };
+
+    let mut names: Vec<_> = computations.keys().map(|n| (*n, true)).chain(dialogs.keys().map(|n| (*n, false))).collect();
+    names.sort();
+
+    let mut diagnostics = vec![];
+    for (name, computation) in names {
+        let script = if computation { &computations[name] } else { &dialogs[name] };
+        let mut script_diagnostics = vec![];
+        check_loops(script.source, &script.body, &mut effects, &mut script_diagnostics);
+        diagnostics.extend(script_diagnostics.into_iter().map(|d| d.for_component(name)));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+
+    // The component and message of each warning for a dialog script, given the computations it can use
+    fn warnings(script: &str, computations: &[(&'static str, &'static str)]) -> Vec<(Option<String>, String)> {
+        let computations: HashMap<&str, Script> = computations.iter()
+            .map(|(name, script)| (*name, hd_script_parser::parse(script).unwrap().1))
+            .collect();
+        let dialogs: HashMap<&str, Script> = vec![("Dialog", hd_script_parser::parse(script).unwrap().1)].into_iter().collect();
+        let components = vec![
+            (String::from("Count"), Component::Number(HashSet::new())),
+            (String::from("Step"), Component::Computation(HashSet::new()))
+        ].into_iter().collect();
+        check(&components, &computations, &dialogs).into_iter().map(|d| (d.component, d.message)).collect()
+    }
+
+    #[test]
+    fn a_while_that_changes_nothing_never_ends() {
+        let script = "WHILE Count < 10\n\"a\"\nEND WHILE";
+        assert_eq!(warnings(script, &[]), vec![
+            (Some(String::from("Dialog")), String::from("WHILE Count < 10 never ends once it starts: the loop doesn't change Count, which the condition reads, and has no QUIT"))
+        ]);
+
+        let (_, ast) = hd_script_parser::parse(script).unwrap();
+        let dialogs: HashMap<&str, Script> = vec![("Dialog", ast)].into_iter().collect();
+        let components = vec![(String::from("Count"), Component::Number(HashSet::new()))].into_iter().collect();
+        let span = check(&components, &HashMap::new(), &dialogs)[0].span.unwrap();
+        assert_eq!(&script[span.start..span.end], "WHILE Count < 10");
+    }
+
+    #[test]
+    fn a_while_that_changes_its_condition_can_end() {
+        assert_eq!(warnings("WHILE Count < 10\nINCREMENT Count\nEND WHILE", &[]), vec![]);
+        assert_eq!(warnings("WHILE Count < 10\nSET Count TO Count + 1\nEND WHILE", &[]), vec![]);
+    }
+
+    #[test]
+    fn a_while_that_quits_can_end() {
+        assert_eq!(warnings("WHILE Count < 10\nIF Count = 5\nQUIT\nEND IF\nEND WHILE", &[]), vec![]);
+    }
+
+    #[test]
+    fn a_while_can_change_its_condition_through_a_computation() {
+        let step = [("Step", "SET Count TO Count + 1")];
+        assert_eq!(warnings("WHILE Count < 10\nStep\nEND WHILE", &[("Step", "\"a\"")]).len(), 1);
+        assert_eq!(warnings("WHILE Count < 10\nStep\nEND WHILE", &step), vec![]);
+        assert_eq!(warnings("WHILE Count < 10\nSET Count TO Step\nEND WHILE", &step), vec![]);
+    }
+}
@@ -0,0 +1,705 @@
+use crate::completion::{ self, CompletionKind, Completions };
+use crate::component_library;
+use crate::diagnostics::{ Diagnostic, Location, Severity, Span };
+use crate::encoding;
+use crate::patch::Patch;
+use crate::tokens::{ self, Token, TokenKind };
+use crate::where_used::Index;
+use crate::xml_reader;
+use crate::{ analyze_and_index, analyze_component_file, complete_script, multiple_choice_name, patch_component_file };
+
+use serde_json::{ json, Value };
+use std::collections::{ HashMap, HashSet };
+use std::fs;
+use std::io::{ self, BufRead, Write };
+use std::path::{ Path, PathBuf };
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+// How many directories down from the workspace root to look for a component file
+const SEARCH_DEPTH: usize = 4;
+
+// A component of the library, with what hovers and definitions show of it
+struct Symbol {
+    name: String,
+    kind: String,
+    result_type: Option<String>,
+    prompt: Option<String>,
+    // Byte offsets into the component file of the name attribute's value and of the whole element
+    name_span: Option<Span>,
+    span: Option<Span>
+}
+
+// The component file scripts are checked against, read from disk until it is opened in the editor. It is read and
+// analyzed once each time it changes rather than for every request.
+struct Library {
+    uri: String,
+    text: String,
+    symbols: Vec<Symbol>,
+    // The names scripts use for the components
+    names: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+    uses: Index
+}
+
+impl Library {
+    fn read(uri: String, text: String) -> Library {
+        let symbols = symbols(&text);
+        let names = symbols.iter().map(|s| multiple_choice_name(&s.name)).collect();
+        let (analysis, uses) = analyze_and_index(&text);
+        Library { uri, text, symbols, names, diagnostics: analysis.diagnostics, uses }
+    }
+
+    // The component a script refers to by name, multiple choice variables are referred to without their suffix
+    fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|s| s.name == name)
+            .or_else(|| self.symbols.iter().find(|s| multiple_choice_name(&s.name) == name))
+    }
+}
+
+pub struct Server {
+    library: Option<Library>,
+    // The text of every open document by URI
+    documents: HashMap<String, String>,
+    shutting_down: bool
+}
+
+fn is_component_file(uri: &str) -> bool {
+    uri.to_ascii_lowercase().ends_with(".cmp")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes().fold(String::new(), |mut encoded, byte| {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+        encoded
+    })
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = percent_decode(uri.strip_prefix("file://")?);
+    // Windows paths are written /C:/...
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => Some(PathBuf::from(&path[1..])),
+        _ => Some(PathBuf::from(path))
+    }
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let path = if path.starts_with('/') { path } else { format!("/{}", path) };
+    format!("file://{}", percent_encode(&path))
+}
+
+// The name of the component an extracted script is for, the name of its file without the extension
+fn script_component(uri: &str) -> Option<String> {
+    let name = uri.rsplit('/').next()?;
+    let name = percent_decode(name);
+    Some(match name.rfind('.') {
+        Some(i) if i > 0 => name[..i].to_owned(),
+        _ => name
+    })
+}
+
+// Finds the first component file under the directory, looking in the directory itself before the ones inside it
+fn find_component_file(directory: &Path, depth: usize) -> Option<PathBuf> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory).ok()?.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    entries.sort();
+
+    let file = entries.iter().find(|p| p.is_file() && p.extension().is_some_and(|e| e.eq_ignore_ascii_case("cmp")));
+    if file.is_some() || depth == 0 {
+        return file.cloned();
+    }
+    entries
+        .iter()
+        .filter(|p| p.is_dir())
+        .filter(|p| !p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with('.') || n == "node_modules"))
+        .find_map(|p| find_component_file(p, depth - 1))
+}
+
+// LSP positions count lines from 0 and characters in UTF-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let offset = offset.min(text.len());
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({ "line": before.matches('\n').count(), "character": before[line_start..].encode_utf16().count() })
+}
+
+fn range(text: &str, span: Span) -> Value {
+    json!({ "start": position(text, span.start), "end": position(text, span.end) })
+}
+
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1)?.0 + 1
+    };
+    let line_text = text[line_start..].split(['\r', '\n']).next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line_text.char_indices() {
+        if units >= character {
+            return Some(line_start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(line_start + line_text.len())
+}
+
+fn location_span(location: Location) -> Span {
+    Span { start: location.start.offset, end: location.end.offset }
+}
+
+fn lsp_diagnostic(text: &str, diagnostic: &Diagnostic, span: Option<Span>) -> Value {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2
+    };
+    json!({
+        "range": range(text, span.unwrap_or(Span { start: 0, end: 0 })),
+        "severity": severity,
+        "source": "hotdocs",
+        "message": diagnostic.message
+    })
+}
+
+fn kind_label(kind: &str) -> String {
+    match kind {
+        "trueFalse" => String::from("true/false variable"),
+        "multipleChoice" => String::from("multiple choice variable"),
+        "dialogElement" => String::from("dialog element"),
+        "computation" | "dialog" => kind.to_owned(),
+        _ => format!("{} variable", kind)
+    }
+}
+
+// https://microsoft.github.io/language-server-protocol/specifications/specification-current/#symbolKind
+fn symbol_kind(kind: &str) -> u64 {
+    match kind {
+        "computation" => 12,
+        "dialog" => 5,
+        _ => 13
+    }
+}
+
+// https://microsoft.github.io/language-server-protocol/specifications/specification-current/#completionItemKind
+fn completion_kind(kind: CompletionKind) -> u64 {
+    match kind {
+        CompletionKind::Keyword => 14,
+        CompletionKind::Operator => 24,
+        CompletionKind::Variable => 6,
+        CompletionKind::Computation | CompletionKind::Function => 3,
+        CompletionKind::Dialog => 7,
+        CompletionKind::Option => 20
+    }
+}
+
+// The components of a component file, none when it isn't valid XML
+fn symbols(text: &str) -> Vec<Symbol> {
+    let root = match xml_reader::read_tree(text) {
+        Ok(root) => root,
+        Err(_) => return vec![]
+    };
+    let library = component_library::load(&root);
+    library
+        .components
+        .iter()
+        .map(|c| Symbol {
+            name: c.name.to_owned(),
+            kind: c.kind.to_owned(),
+            result_type: c.element.attributes.get("resultType").cloned(),
+            prompt: library.child(c.element, "prompt").map(|p| p.text().trim().to_owned()).filter(|p| !p.is_empty()),
+            name_span: c.element.attribute("name").and_then(|a| Span::of(text, a.raw_value)),
+            span: Span::of(text, c.element.source)
+        })
+        .collect()
+}
+
+fn token_at(tokens: &[Token], offset: usize) -> Option<&Token> {
+    tokens.iter().find(|t| t.span.start <= offset && offset <= t.span.end)
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server { library: None, documents: HashMap::new(), shutting_down: false }
+    }
+
+    // Uses the component file named in the initialization options, or the first one in the workspace
+    fn initialize(&mut self, params: &Value) -> Value {
+        let configured = params["initializationOptions"]["componentFile"].as_str().map(PathBuf::from);
+        let root = params["rootUri"]
+            .as_str()
+            .or_else(|| params["workspaceFolders"][0]["uri"].as_str())
+            .and_then(uri_to_path);
+        let file = configured.or_else(|| root.and_then(|root| find_component_file(&root, SEARCH_DEPTH)));
+
+        self.library = file.and_then(|path| {
+            let bytes = fs::read(&path).ok()?;
+            Some(Library::read(path_to_uri(&path), encoding::decode_lossy(&bytes)))
+        });
+
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "completionProvider": {},
+                "documentSymbolProvider": true
+            },
+            "serverInfo": { "name": "hotdocs-lsp" }
+        })
+    }
+
+    fn component_diagnostics(&self, uri: &str, text: &str) -> Vec<Value> {
+        let analyzed;
+        let diagnostics = match self.library.as_ref().filter(|l| l.uri == uri) {
+            Some(library) => &library.diagnostics,
+            None => {
+                analyzed = analyze_component_file(text).diagnostics;
+                &analyzed
+            }
+        };
+        diagnostics.iter().map(|d| lsp_diagnostic(text, d, d.location.map(location_span))).collect()
+    }
+
+    // Checks an extracted script by putting it in place of the component's script and analyzing the library
+    fn script_diagnostics(&self, uri: &str, text: &str) -> Vec<Value> {
+        let library = match &self.library {
+            Some(library) => library,
+            None => return tokens::tokenize(text, &Default::default()).diagnostics.iter().map(|d| lsp_diagnostic(text, d, d.span)).collect()
+        };
+        let name = script_component(uri).unwrap_or_default();
+        let has_script = library.symbols.iter().any(|s| s.name == name && (s.kind == "computation" || s.kind == "dialog"));
+
+        let diagnostics = if has_script {
+            let patched = patch_component_file(&library.text, &[Patch::SetScript { component: name.clone(), script: text.to_owned() }]);
+            analyze_component_file(&patched.xml)
+                .diagnostics
+                .into_iter()
+                .filter(|d| d.component.as_deref() == Some(name.as_str()))
+                .collect()
+        } else {
+            tokens::tokenize(text, &library.names).diagnostics
+        };
+        diagnostics.iter().map(|d| lsp_diagnostic(text, d, d.span)).collect()
+    }
+
+    fn publish(&self, uri: &str, out: &mut impl Write) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(text) if is_component_file(uri) => self.component_diagnostics(uri, text),
+            Some(text) => self.script_diagnostics(uri, text),
+            None => vec![]
+        };
+        let notification = json!({ "jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": { "uri": uri, "diagnostics": diagnostics } });
+        write_message(out, &notification)
+    }
+
+    // Scripts are checked against the library, so every open document is checked again when the library changes
+    fn publish_all(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut uris: Vec<&String> = self.documents.keys().collect();
+        uris.sort();
+        for uri in uris {
+            self.publish(uri, out)?;
+        }
+        Ok(())
+    }
+
+    // Only the document that changed is checked again, unless it's the library every script is checked against
+    fn publish_changed(&self, uri: &str, out: &mut impl Write) -> io::Result<()> {
+        if is_component_file(uri) {
+            self.publish_all(out)
+        } else {
+            self.publish(uri, out)
+        }
+    }
+
+    fn open(&mut self, uri: &str, text: String) {
+        if is_component_file(uri) {
+            self.library = Some(Library::read(uri.to_owned(), text.clone()));
+        }
+        self.documents.insert(uri.to_owned(), text);
+    }
+
+    // The token under the cursor in an open document, with the names resolved against the library
+    fn token(&self, params: &Value) -> Option<(String, Token)> {
+        let text = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let offset = offset(text, &params["position"])?;
+        let tokens = match &self.library {
+            Some(library) => tokens::tokenize(text, &library.names).tokens,
+            None => tokens::tokenize(text, &HashSet::new()).tokens
+        };
+        let token = token_at(&tokens, offset)?.clone();
+        Some((text[token.span.start..token.span.end].to_owned(), token))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (name, token) = self.token(params)?;
+        let text = self.documents.get(params["textDocument"]["uri"].as_str()?)?;
+        let contents = match token.kind {
+            TokenKind::Function => format!("**{}** built-in function", name),
+            TokenKind::Variable => {
+                let component = self.library.as_ref()?.find(&name)?;
+                let mut contents = format!("**{}** {}", name, kind_label(&component.kind));
+                if let Some(result) = &component.result_type {
+                    contents.push_str(&format!(" returning {}", result));
+                }
+                if let Some(prompt) = &component.prompt {
+                    contents.push_str(&format!("\n\n{}", prompt));
+                }
+                contents
+            },
+            _ => return None
+        };
+        Some(json!({ "contents": { "kind": "markdown", "value": contents }, "range": range(text, token.span) }))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (name, token) = self.token(params).filter(|(_, t)| t.kind == TokenKind::Variable)?;
+        let library = self.library.as_ref()?;
+        let span = library.find(&name)?.name_span.unwrap_or(token.span);
+        Some(json!({ "uri": library.uri, "range": range(&library.text, span) }))
+    }
+
+    // Every place the component under the cursor is used: open scripts, then everywhere in the library other than the
+    // scripts that are open
+    fn references(&self, params: &Value) -> Option<Value> {
+        let (name, _) = self.token(params).filter(|(_, t)| t.kind == TokenKind::Variable)?;
+        let library = self.library.as_ref()?;
+        let component = library.find(&name)?;
+
+        let mut references = vec![];
+        if params["context"]["includeDeclaration"].as_bool().unwrap_or(true) {
+            if let Some(span) = component.name_span {
+                references.push(json!({ "uri": library.uri, "range": range(&library.text, span) }));
+            }
+        }
+
+        let mut scripts: Vec<(&String, &String)> = self.documents.iter().filter(|(uri, _)| !is_component_file(uri)).collect();
+        scripts.sort();
+        let open: Vec<String> = scripts.iter().filter_map(|(uri, _)| script_component(uri)).collect();
+        for (uri, text) in scripts {
+            for token in tokens::tokenize(text, &library.names).tokens.iter().filter(|t| t.kind == TokenKind::Variable) {
+                if text[token.span.start..token.span.end] == name {
+                    references.push(json!({ "uri": uri, "range": range(text, token.span) }));
+                }
+            }
+        }
+
+        for used in library.uses.uses(&component.name) {
+            // The scripts open in the editor were searched above
+            if used.script_span.is_some() && open.contains(&used.component) {
+                continue;
+            }
+            if let Some(span) = used.span {
+                references.push(json!({ "uri": library.uri, "range": range(&library.text, span) }));
+            }
+        }
+        Some(Value::Array(references))
+    }
+
+    // The candidates that fit the cursor in a script, without a library only keywords and functions fit
+    fn completion(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri).filter(|_| !is_component_file(uri))?;
+        let offset = offset(text, &params["position"])?;
+        let Completions { span, items, .. } = match &self.library {
+            Some(library) => complete_script(&library.text, text, offset),
+            None => {
+                let (components, types, options) = Default::default();
+                completion::complete(text, offset, &completion::Library { components: &components, types: &types, options: &options })
+            }
+        };
+
+        // The editor sorts and filters by its own rules, so the ranking is kept in the sort text
+        let items = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| json!({
+                "label": c.label,
+                "kind": completion_kind(c.kind),
+                "detail": c.detail,
+                "sortText": format!("{:05}", i),
+                "filterText": c.insert,
+                "textEdit": { "range": range(text, span), "newText": c.insert }
+            }))
+            .collect();
+        Some(Value::Array(items))
+    }
+
+    // One symbol for each component of a component file, scripts have none
+    fn document_symbols(&self, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let text = self.documents.get(uri).filter(|_| is_component_file(uri))?;
+        let read;
+        let symbols = match self.library.as_ref().filter(|l| l.uri == uri) {
+            Some(library) => &library.symbols,
+            None => {
+                read = symbols(text);
+                &read
+            }
+        };
+
+        let symbols = symbols
+            .iter()
+            .filter_map(|s| {
+                let whole = s.span?;
+                Some(json!({
+                    "name": s.name,
+                    "detail": kind_label(&s.kind),
+                    "kind": symbol_kind(&s.kind),
+                    "range": range(text, whole),
+                    "selectionRange": range(text, s.name_span.unwrap_or(whole))
+                }))
+            })
+            .collect();
+        Some(Value::Array(symbols))
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let result = match method {
+            "initialize" => self.initialize(params),
+            "shutdown" => {
+                self.shutting_down = true;
+                Value::Null
+            },
+            "textDocument/hover" => self.hover(params).unwrap_or(Value::Null),
+            "textDocument/definition" => self.definition(params).unwrap_or(Value::Null),
+            "textDocument/references" => self.references(params).unwrap_or(Value::Null),
+            "textDocument/completion" => self.completion(params).unwrap_or(Value::Null),
+            "textDocument/documentSymbol" => self.document_symbols(params).unwrap_or(Value::Null),
+            _ => return Err((METHOD_NOT_FOUND, format!("{} is not supported", method)))
+        };
+        Ok(result)
+    }
+
+    fn notification(&mut self, method: &str, params: &Value, out: &mut impl Write) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                self.open(&uri, params["textDocument"]["text"].as_str().unwrap_or_default().to_owned());
+                self.publish_changed(&uri, out)
+            },
+            "textDocument/didChange" => {
+                // Documents are synced in full, so the last change has the whole text
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    Some(text) => {
+                        self.open(&uri, text.to_owned());
+                        self.publish_changed(&uri, out)
+                    },
+                    None => Ok(())
+                }
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish(&uri, out)?;
+                if is_component_file(&uri) {
+                    self.publish_all(out)?;
+                }
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    // Handles one message, returning false once the client has asked the server to exit
+    pub fn handle(&mut self, message: &Value, out: &mut impl Write) -> io::Result<bool> {
+        let method = message["method"].as_str();
+        let params = &message["params"];
+        match (method, message.get("id")) {
+            (Some("exit"), _) => return Ok(false),
+            (Some(method), Some(id)) => {
+                let response = match self.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+                };
+                write_message(out, &response)?;
+            },
+            (Some(method), None) => self.notification(method, params, out)?,
+            // Responses to requests the server never sends
+            (None, Some(_)) if message.get("result").is_some() || message.get("error").is_some() => (),
+            (None, id) => {
+                let error = json!({ "jsonrpc": "2.0", "id": id, "error": { "code": INVALID_REQUEST, "message": "The message has no method" } });
+                write_message(out, &error)?;
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.shutting_down { 0 } else { 1 }
+    }
+}
+
+// Reads one message, returning None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, serde_json::Error>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "A message has no Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+// Serves requests from the input until the client exits, returning the exit code the process should use
+pub fn run(input: &mut impl BufRead, out: &mut impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(input)? {
+        let running = match message {
+            Ok(message) => server.handle(&message, out)?,
+            Err(e) => {
+                let error = json!({ "jsonrpc": "2.0", "id": null, "error": { "code": PARSE_ERROR, "message": e.to_string() } });
+                write_message(out, &error)?;
+                true
+            }
+        };
+        if !running {
+            break;
+        }
+    }
+    Ok(server.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = "<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:components>\n<hd:text name=\"Client\"><hd:prompt>Client name</hd:prompt></hd:text>\n</hd:components>\n</hd:componentLibrary>";
+
+    // The messages the server wrote, without their framing
+    fn written(out: &[u8]) -> Vec<Value> {
+        let mut input = out;
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut input).unwrap() {
+            messages.push(message.unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn messages_are_framed_by_their_content_length() {
+        let mut out = vec![];
+        write_message(&mut out, &json!({ "id": 1, "text": "é" })).unwrap();
+        write_message(&mut out, &json!({ "id": 2 })).unwrap();
+        // The length is in bytes, é takes two
+        assert!(out.starts_with(b"Content-Length: 20\r\n\r\n{"));
+        assert_eq!(written(&out), vec![json!({ "id": 1, "text": "é" }), json!({ "id": 2 })]);
+
+        let mut input: &[u8] = b"content-length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{}";
+        assert_eq!(read_message(&mut input).unwrap().unwrap().unwrap(), json!({}));
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let mut input: &[u8] = b"Content-Type: text/plain\r\n\r\n{}";
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn positions_count_utf16_code_units() {
+        let text = "a😀b\r\nçd";
+        let b = text.find('b').unwrap();
+        let d = text.find('d').unwrap();
+
+        assert_eq!(position(text, b), json!({ "line": 0, "character": 3 }));
+        assert_eq!(position(text, d), json!({ "line": 1, "character": 1 }));
+        assert_eq!(offset(text, &json!({ "line": 0, "character": 3 })), Some(b));
+        assert_eq!(offset(text, &json!({ "line": 1, "character": 1 })), Some(d));
+        // Past the end of a line is the end of the line, before its line break
+        assert_eq!(offset(text, &json!({ "line": 0, "character": 10 })), Some(b + 1));
+        assert_eq!(offset(text, &json!({ "line": 2, "character": 0 })), None);
+    }
+
+    #[test]
+    fn uris_are_percent_decoded() {
+        assert_eq!(percent_decode("a%20b%C3%A9"), "a bé");
+        // Anything that isn't a valid escape is kept as it was written
+        assert_eq!(percent_decode("100%zz%2"), "100%zz%2");
+
+        assert_eq!(uri_to_path("file:///home/me/My%20Library.cmp"), Some(PathBuf::from("/home/me/My Library.cmp")));
+        assert_eq!(uri_to_path("file:///C:/Templates/Library.cmp"), Some(PathBuf::from("C:/Templates/Library.cmp")));
+        assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+        assert_eq!(uri_to_path(&path_to_uri(Path::new("/home/me/a b#c.cmp"))), Some(PathBuf::from("/home/me/a b#c.cmp")));
+    }
+
+    #[test]
+    fn the_library_is_read_again_when_it_changes() {
+        let mut server = Server::new();
+        let mut out = vec![];
+        let notify = |server: &mut Server, method: &str, params: Value, out: &mut Vec<u8>| {
+            server.handle(&json!({ "jsonrpc": "2.0", "method": method, "params": params }), out).unwrap();
+        };
+        let hover = |server: &mut Server| {
+            let params = json!({ "textDocument": { "uri": "file:///Greeting.hdc" }, "position": { "line": 0, "character": 1 } });
+            server.hover(&params).map(|hover| hover["contents"]["value"].clone())
+        };
+
+        notify(&mut server, "textDocument/didOpen", json!({ "textDocument": { "uri": "file:///Library.cmp", "text": LIBRARY } }), &mut out);
+        notify(&mut server, "textDocument/didOpen", json!({ "textDocument": { "uri": "file:///Greeting.hdc", "text": "Client" } }), &mut out);
+        assert_eq!(hover(&mut server), Some(json!("**Client** text variable\n\nClient name")));
+
+        let changed = LIBRARY.replace("Client name", "The client's full name");
+        notify(&mut server, "textDocument/didChange", json!({ "textDocument": { "uri": "file:///Library.cmp" }, "contentChanges": [{ "text": changed }] }), &mut out);
+        assert_eq!(hover(&mut server), Some(json!("**Client** text variable\n\nThe client's full name")));
+
+        let unknown = LIBRARY.replace("\"Client\"", "\"Customer\"");
+        notify(&mut server, "textDocument/didChange", json!({ "textDocument": { "uri": "file:///Library.cmp" }, "contentChanges": [{ "text": unknown }] }), &mut out);
+        assert_eq!(hover(&mut server), None);
+    }
+}
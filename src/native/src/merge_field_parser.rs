@@ -0,0 +1,112 @@
+use nom::{
+    IResult,
+    branch::alt,
+    bytes::complete::take_till,
+    character::complete::{ anychar, char },
+    combinator::{ map, value },
+    multi::fold_many0,
+    sequence::delimited
+};
+
+// Merge fields that start with one of these words are template instructions (e.g. «IF Married», «END REPEAT»)
+// rather than references to a variable.
+const INSTRUCTION_KEYWORDS: [&str; 12] = [
+    "IF", "ELSE", "END", "REPEAT", "ASK", "INSERT", "SPAN", "FILTER", "SORT", "FIRST", "LAST", "KEEP"
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MergeField<'a> {
+    pub name: &'a str,
+    pub format: Option<&'a str>
+}
+
+// Instructions can be written in any case, e.g. «if Married» and «end if»
+fn is_instruction(name: &str) -> bool {
+    INSTRUCTION_KEYWORDS.iter().any(|k| {
+        name.get(..k.len()).is_some_and(|start| start.eq_ignore_ascii_case(k))
+            && (name.len() == k.len() || name[k.len()..].starts_with(' '))
+    })
+}
+
+fn to_merge_field(body: &str) -> Option<MergeField<'_>> {
+    // «Client Name:LIKE THIS» => the name is everything before the first colon, the format example is the rest
+    let (name, format) = match body.find(':') {
+        Some(i) => (&body[..i], Some(body[i + 1..].trim())),
+        None => (body, None)
+    };
+
+    // Repeated variables can be merged with an explicit index, «Child Name[2]»
+    let name = match name.find('[') {
+        Some(i) => &name[..i],
+        None => name
+    }
+    .trim();
+
+    match name {
+        "" => None,
+        n if is_instruction(n) => None,
+        n => Some(MergeField { name: n, format: format.filter(|f| !f.is_empty()) })
+    }
+}
+
+fn parse_merge_field(input: &str) -> IResult<&str, Option<MergeField<'_>>> {
+    map(
+        delimited(char('«'), take_till(|c| c == '«' || c == '»'), char('»')),
+        to_merge_field
+    )(input)
+}
+
+pub fn parse(input: &str) -> Vec<MergeField<'_>> {
+    let result: IResult<&str, Vec<MergeField>> = fold_many0(
+        alt((
+            parse_merge_field,
+            value(None, anychar)
+        )),
+        Vec::new(),
+        |mut fields: Vec<MergeField>, f| {
+            if let Some(field) = f {
+                fields.push(field);
+            }
+            fields
+        }
+    )(input);
+
+    match result {
+        Ok((_, fields)) => fields,
+        Err(_) => vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field<'a>(name: &'a str, format: Option<&'a str>) -> MergeField<'a> {
+        MergeField { name, format }
+    }
+
+    #[test]
+    fn fields_are_read_with_their_format_example() {
+        assert_eq!(parse("Signed on «Date:3 June 1990» by «Client Name : LIKE THIS» and «Child Name[2]»"), vec![
+            field("Date", Some("3 June 1990")),
+            field("Client Name", Some("LIKE THIS")),
+            field("Child Name", None)
+        ]);
+        assert_eq!(parse("«Client:»«»"), vec![field("Client", None)]);
+    }
+
+    #[test]
+    fn instructions_in_any_case_are_not_fields() {
+        assert_eq!(parse("«IF Client»«Client»«END IF»"), vec![field("Client", None)]);
+        assert_eq!(parse("«if Client»«Client»«end if»«Repeat Children»«End Repeat»"), vec![field("Client", None)]);
+        // A name that only starts with a keyword is still a field
+        assert_eq!(parse("«Iffy»«Ending Date»«ENDS»"), vec![field("Iffy", None), field("Ending Date", None), field("ENDS", None)]);
+    }
+
+    #[test]
+    fn nested_fields_are_read_from_the_inside() {
+        assert_eq!(parse("«IF «Client» = \"x\"»"), vec![field("Client", None)]);
+        assert_eq!(parse("«Client «Spouse»"), vec![field("Spouse", None)]);
+        assert_eq!(parse("«Client"), vec![]);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::hd_script_parser::*;
+use crate::diagnostics::Diagnostic;
+use crate::repeats;
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ComponentMetrics {
+    pub name: String,
+    // Statements in the script, not counting comments
+    pub statements: usize,
+    // How deeply IF, REPEAT and WHILE statements are nested
+    pub depth: usize,
+    // One more than the number of decisions the script makes: each IF, ELSE IF, WHILE, REPEAT, AND and OR
+    pub complexity: usize,
+    pub reads: usize,
+    pub writes: usize,
+    // The components that use this one, and the components it uses
+    pub fan_in: usize,
+    pub fan_out: usize,
+    // Comments as a share of all the statements in the script, comments included
+    pub comment_ratio: f64
+}
+
+// The most each metric can be before a component is reported, metrics without a limit are never reported
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(default)]
+pub struct Thresholds {
+    pub statements: Option<usize>,
+    pub depth: Option<usize>,
+    pub complexity: Option<usize>,
+    pub fan_in: Option<usize>,
+    pub fan_out: Option<usize>,
+    // The least the comment ratio can be, only checked for scripts of at least this many statements
+    pub comment_ratio: Option<f64>,
+    pub commented_statements: usize
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            statements: Some(100),
+            depth: Some(4),
+            complexity: Some(10),
+            fan_in: None,
+            fan_out: Some(20),
+            comment_ratio: None,
+            commented_statements: 20
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Summary {
+    pub components: usize,
+    pub statements: usize,
+    pub average_complexity: f64,
+    pub max_complexity: usize,
+    pub max_depth: usize,
+    pub comment_ratio: f64,
+    // The components with at least one metric past its threshold
+    pub over_threshold: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MetricsReport {
+    pub components: Vec<ComponentMetrics>,
+    pub summary: Summary,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+#[derive(Default)]
+struct Counts<'a> {
+    statements: usize,
+    comments: usize,
+    depth: usize,
+    decisions: usize,
+    reads: BTreeSet<&'a str>,
+    writes: BTreeSet<&'a str>
+}
+
+fn count_operators(expression: &Expression) -> usize {
+    match expression {
+        Expression::Binary(b) => {
+            let here = b.right.iter().filter(|(op, _)| *op == BinaryOperation::And || *op == BinaryOperation::Or).count();
+            here + count_operators(&b.left) + b.right.iter().map(|(_, e)| count_operators(e)).sum::<usize>()
+        },
+        Expression::Unary(u) => count_operators(&u.expression),
+        Expression::FunctionCall(call) => call.args.iter().map(count_operators).sum(),
+        _ => 0
+    }
+}
+
+fn count<'a>(statements: &'a [Spanned<Statement>], depth: usize, counts: &mut Counts<'a>) {
+    counts.depth = counts.depth.max(depth);
+
+    for statement in statements.iter() {
+        let mut read = vec![];
+        match &statement.node {
+            Statement::Comment(_) => {
+                counts.comments += 1;
+                continue;
+            },
+            // An ELSE IF is a decision at the same depth as the IF it follows
+            Statement::If(s) => {
+                counts.decisions += 1 + count_operators(&s.condition);
+                repeats::expression_names(&s.condition, &mut read);
+                count(&s.then_body, depth + 1, counts);
+                match s.else_body.as_slice() {
+                    [next @ Spanned { node: Statement::If(_), .. }] => count(std::slice::from_ref(next), depth, counts),
+                    body => count(body, depth + 1, counts)
+                }
+            },
+            Statement::Loop(LoopStatement::While(s)) => {
+                counts.decisions += 1 + count_operators(&s.condition);
+                repeats::expression_names(&s.condition, &mut read);
+                count(&s.body, depth + 1, counts);
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                counts.decisions += 1;
+                count(&s.body, depth + 1, counts);
+            },
+            Statement::Return(e) => repeats::expression_names(e, &mut read),
+            Statement::Instruction(Instruction::Script(instruction)) => match instruction {
+                ScriptInstruction::Set(v, e) | ScriptInstruction::Add(v, e) | ScriptInstruction::Default(v, e) => {
+                    counts.writes.insert(v.name.trim());
+                    repeats::expression_names(e, &mut read);
+                },
+                ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) => {
+                    counts.writes.insert(v.name.trim());
+                    read.push(v.name.trim());
+                },
+                ScriptInstruction::Erase(v) => {
+                    counts.writes.insert(v.name.trim());
+                },
+                _ => ()
+            },
+            Statement::Instruction(Instruction::Display(_)) => ()
+        }
+        counts.statements += 1;
+        counts.reads.extend(read);
+    }
+}
+
+fn is_variable(components: &HashMap<String, Component>, name: &str) -> bool {
+    !matches!(components.get(name), None | Some(Component::Computation(_)) | Some(Component::Dialog { .. }))
+}
+
+fn uses(component: &Component) -> BTreeSet<&str> {
+    let names: Box<dyn Iterator<Item = &String>> = match component {
+        Component::Dialog { children, script, fields } => Box::new(children.iter().chain(script.iter()).chain(fields.iter())),
+        Component::Text(uses) | Component::Number(uses) | Component::TrueFalse(uses) | Component::Date(uses) | Component::Image(uses)
+        | Component::MultipleChoice(uses) | Component::DialogElement(uses) | Component::Computation(uses) => Box::new(uses.iter())
+    };
+    names.map(|n| n.trim()).collect()
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 { 0.0 } else { part as f64 / whole as f64 }
+}
+
+// Measures the script of each computation and dialog, and reports the ones past the thresholds
+pub fn measure(
+    components: &HashMap<String, Component>,
+    computations: &HashMap<&str, Script>,
+    dialogs: &HashMap<&str, Script>,
+    thresholds: &Thresholds
+) -> MetricsReport {
+    let mut fan_in: HashMap<&str, usize> = HashMap::new();
+    for (name, component) in components.iter() {
+        for used in uses(component).into_iter().filter(|u| *u != name && components.contains_key(*u)) {
+            *fan_in.entry(used).or_default() += 1;
+        }
+    }
+
+    let mut names: Vec<&String> = components
+        .iter()
+        .filter(|(_, c)| matches!(c, Component::Computation(_) | Component::Dialog { .. }))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    let mut measured = vec![];
+    let mut diagnostics = vec![];
+    let mut over_threshold = vec![];
+    let mut comments = 0;
+
+    for name in names {
+        let mut counts = Counts::default();
+        if let Some(script) = computations.get(name.as_str()).or_else(|| dialogs.get(name.as_str())) {
+            count(&script.body, 0, &mut counts);
+        }
+        comments += counts.comments;
+
+        let metrics = ComponentMetrics {
+            name: name.to_owned(),
+            statements: counts.statements,
+            depth: counts.depth,
+            complexity: counts.decisions + 1,
+            reads: counts.reads.iter().filter(|r| is_variable(components, r)).count(),
+            writes: counts.writes.len(),
+            fan_in: fan_in.get(name.as_str()).copied().unwrap_or(0),
+            fan_out: uses(&components[name]).into_iter().filter(|u| *u != name && components.contains_key(*u)).count(),
+            comment_ratio: ratio(counts.comments, counts.statements + counts.comments)
+        };
+
+        let limits = [
+            ("statement count", metrics.statements, thresholds.statements),
+            ("nesting depth", metrics.depth, thresholds.depth),
+            ("cyclomatic complexity", metrics.complexity, thresholds.complexity),
+            ("fan-in", metrics.fan_in, thresholds.fan_in),
+            ("fan-out", metrics.fan_out, thresholds.fan_out)
+        ];
+        let mut problems: Vec<String> = limits
+            .iter()
+            .filter_map(|(metric, value, limit)| limit.filter(|l| value > l).map(|l| format!("The {} is {}, above the limit of {}", metric, value, l)))
+            .collect();
+        if let Some(least) = thresholds.comment_ratio.filter(|l| metrics.statements >= thresholds.commented_statements && metrics.comment_ratio < *l) {
+            problems.push(format!("The comment ratio is {:.2}, below the limit of {:.2}", metrics.comment_ratio, least));
+        }
+
+        if !problems.is_empty() {
+            over_threshold.push(name.to_owned());
+        }
+        diagnostics.extend(problems.into_iter().map(|p| Diagnostic::warning(p).for_component(name)));
+        measured.push(metrics);
+    }
+
+    let statements = measured.iter().map(|m| m.statements).sum();
+    let summary = Summary {
+        components: measured.len(),
+        statements,
+        average_complexity: ratio(measured.iter().map(|m| m.complexity).sum(), measured.len()),
+        max_complexity: measured.iter().map(|m| m.complexity).max().unwrap_or(0),
+        max_depth: measured.iter().map(|m| m.depth).max().unwrap_or(0),
+        comment_ratio: ratio(comments, statements + comments),
+        over_threshold
+    };
+
+    MetricsReport { components: measured, summary, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+    use std::collections::HashSet;
+
+    const SCRIPT: &str = "// Works out the total
+IF Age > 18 AND Married
+IF Age > 65 OR Client = \"x\"
+SET Total TO 2
+END IF
+ELSE IF Age > 10
+INCREMENT Total
+ELSE
+REPEAT Children
+ERASE Client
+END REPEAT
+END IF
+WHILE Total < 3
+INCREMENT Total
+END WHILE";
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn report(thresholds: &Thresholds) -> MetricsReport {
+        let computations: HashMap<&str, Script> = vec![("Calc", hd_script_parser::parse(SCRIPT).unwrap().1)].into_iter().collect();
+        let components = vec![
+            (String::from("Age"), Component::Number(HashSet::new())),
+            (String::from("Married"), Component::TrueFalse(HashSet::new())),
+            (String::from("Client"), Component::Text(HashSet::new())),
+            (String::from("Total"), Component::Number(HashSet::new())),
+            (String::from("Calc"), Component::Computation(names(&["Age", "Married", "Client", "Total", "Children"]))),
+            (String::from("Children"), Component::Dialog { children: vec![String::from("Age")], script: names(&["Calc"]), fields: HashSet::new() })
+        ].into_iter().collect();
+        measure(&components, &computations, &HashMap::new(), thresholds)
+    }
+
+    #[test]
+    fn every_metric_is_measured() {
+        let report = report(&Thresholds::default());
+        assert!(report.diagnostics.is_empty(), "{:?}", report.diagnostics);
+
+        assert_eq!(report.components[0], ComponentMetrics {
+            name: String::from("Calc"),
+            statements: 9,
+            depth: 2,
+            // IF, AND, IF, OR, ELSE IF, REPEAT and WHILE
+            complexity: 8,
+            reads: 4,
+            writes: 2,
+            fan_in: 1,
+            fan_out: 5,
+            comment_ratio: 0.1
+        });
+        assert_eq!(report.components[1], ComponentMetrics {
+            name: String::from("Children"),
+            statements: 0,
+            depth: 0,
+            complexity: 1,
+            reads: 0,
+            writes: 0,
+            fan_in: 1,
+            fan_out: 2,
+            comment_ratio: 0.0
+        });
+
+        assert_eq!(report.summary, Summary {
+            components: 2,
+            statements: 9,
+            average_complexity: 4.5,
+            max_complexity: 8,
+            max_depth: 2,
+            comment_ratio: 0.1,
+            over_threshold: vec![]
+        });
+    }
+
+    #[test]
+    fn metrics_past_their_thresholds_are_reported() {
+        let thresholds = Thresholds {
+            statements: Some(8),
+            depth: Some(1),
+            complexity: Some(7),
+            fan_in: Some(0),
+            fan_out: Some(4),
+            comment_ratio: Some(0.5),
+            commented_statements: 5
+        };
+        let report = report(&thresholds);
+
+        let messages: Vec<(&str, Option<&str>)> = report.diagnostics.iter().map(|d| (d.message.as_str(), d.component.as_deref())).collect();
+        assert_eq!(messages, vec![
+            ("The statement count is 9, above the limit of 8", Some("Calc")),
+            ("The nesting depth is 2, above the limit of 1", Some("Calc")),
+            ("The cyclomatic complexity is 8, above the limit of 7", Some("Calc")),
+            ("The fan-in is 1, above the limit of 0", Some("Calc")),
+            ("The fan-out is 5, above the limit of 4", Some("Calc")),
+            ("The comment ratio is 0.10, below the limit of 0.50", Some("Calc")),
+            // The dialog has too few statements for its comment ratio to be checked
+            ("The fan-in is 1, above the limit of 0", Some("Children"))
+        ]);
+        assert_eq!(report.summary.over_threshold, vec![String::from("Calc"), String::from("Children")]);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::component_library::ComponentLibrary;
+use crate::diagnostics::{ Diagnostic, LineIndex, Span };
+use crate::xml_parser::XmlElement;
+use crate::xml_reader;
+use crate::xml_writer::{ self, Document, Edit };
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+// A change to one component of the library
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum Patch {
+    // Replaces the script of a computation or dialog, adding one when the component has none
+    SetScript { component: String, script: String },
+    // Changes an attribute of the component element, or removes it when there's no value
+    SetAttribute { component: String, attribute: String, value: Option<String> },
+    // Adds the component written as XML to the end of the components section
+    AddComponent { xml: String },
+    RemoveComponent { component: String }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Patched {
+    // The component file with the edits applied
+    pub xml: String,
+    // Byte offsets into the original component file
+    pub edits: Vec<Edit>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Patched {
+    pub fn unchanged(document: &str, diagnostics: Vec<Diagnostic>) -> Patched {
+        Patched { xml: document.to_owned(), edits: vec![], diagnostics }
+    }
+
+    // Edits that overlap an earlier one are left out and reported
+    pub fn from(document: Document, mut diagnostics: Vec<Diagnostic>) -> Patched {
+        let (xml, skipped) = document.write();
+        let lines = LineIndex::new(document.source());
+        for edit in skipped.iter() {
+            let span = Span { start: edit.start, end: edit.end };
+            let message = format!("An edit to bytes {} to {} overlaps another edit and was not applied", edit.start, edit.end);
+            diagnostics.push(Diagnostic::error(message).at(Some(span)).located(Some(lines.location(span))));
+        }
+
+        let mut edits: Vec<Edit> = document.edits().iter().filter(|e| !skipped.contains(e)).cloned().collect();
+        edits.sort_by_key(|e| (e.start, e.end));
+        Patched { xml, edits, diagnostics }
+    }
+}
+
+// The name of a HotDocs element with the same prefix as the given one, e.g. hd:script for hd:computation
+fn sibling_name(element: &XmlElement, local_name: &str) -> String {
+    match element.name.find(':') {
+        Some(i) => format!("{}{}", &element.name[..=i], local_name),
+        None => local_name.to_owned()
+    }
+}
+
+// Applies one patch, the names of the components added by earlier patches are kept so they aren't added twice
+fn patch<'a>(document: &mut Document<'a>, library: &ComponentLibrary<'a, '_>, added: &mut HashSet<String>, patch: &Patch) -> Result<(), Box<Diagnostic>> {
+    let component = |name: &str| {
+        library
+            .components
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.element)
+            .ok_or_else(|| Box::new(Diagnostic::error(format!("{} is not a component in this library", name))))
+    };
+
+    match patch {
+        Patch::SetScript { component: name, script } => {
+            let element = component(name)?;
+            let applied = match library.child(element, "script") {
+                Some(existing) => document.set_text(existing, script),
+                None => {
+                    let tag = sibling_name(element, "script");
+                    document.append_child(element, &format!("<{}>{}</{}>", tag, xml_writer::escape(script, false), tag))
+                }
+            };
+            if !applied {
+                return Err(Box::new(Diagnostic::error(String::from("The script could not be replaced")).for_component(name)));
+            }
+        },
+        Patch::SetAttribute { component: name, attribute, value } => {
+            let element = component(name)?;
+            match value {
+                Some(_) if !xml_writer::is_name(attribute) => {
+                    return Err(Box::new(Diagnostic::error(format!("\"{}\" is not a valid attribute name", attribute)).for_component(name)));
+                },
+                Some(value) => {
+                    if !document.set_attribute(element, attribute, value) {
+                        return Err(Box::new(Diagnostic::error(format!("The {} attribute could not be set", attribute)).for_component(name)));
+                    }
+                },
+                None => {
+                    if !document.remove_attribute(element, attribute) {
+                        return Err(Box::new(Diagnostic::warning(format!("There is no {} attribute to remove", attribute)).for_component(name)));
+                    }
+                }
+            }
+        },
+        Patch::AddComponent { xml } => {
+            let element = match xml_reader::read_tree(xml) {
+                Ok(element) if element.source == xml.trim() => element,
+                _ => return Err(Box::new(Diagnostic::error(String::from("The component to add is not a single XML element"))))
+            };
+            let name = match element.attributes.get("name") {
+                Some(name) if !name.is_empty() => name,
+                _ => return Err(Box::new(Diagnostic::error(format!("<{}> has no name attribute and was not added", element.name))))
+            };
+            if component(name).is_ok() || !added.insert(name.to_owned()) {
+                return Err(Box::new(Diagnostic::error(format!("{} is already a component in this library", name))));
+            }
+            let section = library.sections.get("components").ok_or_else(|| Box::new(Diagnostic::error(String::from("The component library has no components section"))))?;
+            document.append_child(section, xml.trim());
+        },
+        Patch::RemoveComponent { component: name } => {
+            document.remove(component(name)?);
+        }
+    }
+    Ok(())
+}
+
+// Applies the patches in order, skipping the ones that can't be applied
+pub fn apply(document: &str, library: &ComponentLibrary, patches: &[Patch]) -> Patched {
+    let mut edited = Document::new(document);
+    let mut added = HashSet::new();
+    let diagnostics = patches.iter().filter_map(|p| patch(&mut edited, library, &mut added, p).err().map(|d| *d)).collect();
+    Patched::from(edited, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ component_library, xml_reader };
+
+    const LIBRARY: &str = "<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:components>\n<hd:computation name=\"Greeting\"><hd:script>\"Hi\"</hd:script></hd:computation>\n</hd:components>\n</hd:componentLibrary>";
+
+    fn patched(patches: &[Patch]) -> Patched {
+        let root = xml_reader::read_tree(LIBRARY).unwrap();
+        let library = component_library::load(&root);
+        apply(LIBRARY, &library, patches)
+    }
+
+    #[test]
+    fn edits_that_overlap_are_reported() {
+        let set_script = |script: &str| Patch::SetScript { component: String::from("Greeting"), script: script.to_owned() };
+        let patched = patched(&[set_script("\"Hello\""), set_script("\"Bye\"")]);
+
+        assert_eq!(patched.xml, LIBRARY.replace("\"Hi\"", "\"Hello\""));
+        assert_eq!(patched.edits.len(), 1);
+        assert_eq!(patched.diagnostics.len(), 1);
+        assert!(patched.diagnostics[0].message.contains("overlaps another edit"));
+        assert_eq!(patched.diagnostics[0].location.unwrap().start.line, 3);
+    }
+
+    #[test]
+    fn attribute_names_must_be_xml_names() {
+        let patch = Patch::SetAttribute { component: String::from("Greeting"), attribute: String::from("result type"), value: Some(String::from("text")) };
+        let patched = patched(&[patch]);
+
+        assert_eq!(patched.xml, LIBRARY);
+        assert_eq!(patched.diagnostics[0].message, "\"result type\" is not a valid attribute name");
+    }
+
+    #[test]
+    fn components_are_added_with_the_reader_rules() {
+        let added = patched(&[Patch::AddComponent { xml: String::from("<hd:text name='Client-Name'/>") }]);
+        assert!(added.diagnostics.is_empty(), "{:?}", added.diagnostics);
+        assert!(added.xml.contains("</hd:computation>\n<hd:text name='Client-Name'/>\n</hd:components>"));
+
+        let two = patched(&[Patch::AddComponent { xml: String::from("<hd:text name='A'/><hd:text name='B'/>") }]);
+        assert_eq!(two.diagnostics[0].message, "The component to add is not a single XML element");
+    }
+}
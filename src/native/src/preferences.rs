@@ -0,0 +1,149 @@
+use crate::component_library::ComponentLibrary;
+use crate::diagnostics::Diagnostic;
+use crate::xml_parser::XmlElement;
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct LibraryPreferences {
+    pub interview_computation: Option<String>,
+    pub date_format: Option<String>,
+    pub number_format: Option<String>,
+    pub language: Option<String>,
+    pub default_answer_file: Option<String>,
+    pub save_answers: Option<bool>,
+    pub max_repeat_count: Option<u32>,
+    pub max_loop_count: Option<u32>,
+    // Every preference that isn't modelled above, keyed by its name in the component file
+    pub other: HashMap<String, String>
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None
+    }
+}
+
+fn parse_count(value: &str) -> Option<u32> {
+    value.trim().parse().ok()
+}
+
+fn invalid(preference: &XmlElement, name: &str, kind: &str, value: &str) -> Diagnostic {
+    Diagnostic::warning(format!("The {} preference should be {} but is \"{}\"", name, kind, value)).located(Some(preference.location))
+}
+
+// Preferences are stored either as <hd:preference name="X">value</hd:preference> or <hd:preference name="X" value="value"/>
+fn preference_value(element: &XmlElement) -> String {
+    match element.attributes.get("value") {
+        Some(value) => value.to_owned(),
+        None => element.text().trim().to_owned()
+    }
+}
+
+pub fn parse(library: &ComponentLibrary, element: &XmlElement, diagnostics: &mut Vec<Diagnostic>) -> LibraryPreferences {
+    let mut preferences = LibraryPreferences::default();
+
+    for preference in library.children(element) {
+        let name = match preference.attributes.get("name") {
+            Some(name) if library.is(preference, "preference") => name,
+            _ => {
+                diagnostics.push(Diagnostic::warning(format!("<{}> is not a named preference and was ignored", preference.name)).located(Some(preference.location)));
+                continue;
+            }
+        };
+
+        let value = preference_value(preference);
+
+        match name.as_str() {
+            "INTERVIEW_COMPUTATION" | "CUSTOM_INTERVIEW_COMPUTATION" => preferences.interview_computation = Some(value).filter(|v| !v.is_empty()),
+            "DATE_FORMAT" | "DEFAULT_DATE_FORMAT" => preferences.date_format = Some(value),
+            "NUMBER_FORMAT" | "DEFAULT_NUMBER_FORMAT" => preferences.number_format = Some(value),
+            "LANGUAGE" | "DEFAULT_LANGUAGE" => preferences.language = Some(value),
+            "DEFAULT_ANSWER_FILE" => preferences.default_answer_file = Some(value).filter(|v| !v.is_empty()),
+            "SAVE_ANSWERS" | "ASK_TO_SAVE_ANSWERS" => match parse_bool(&value) {
+                Some(b) => preferences.save_answers = Some(b),
+                None => diagnostics.push(invalid(preference, name, "true or false", &value))
+            },
+            "MAX_REPEAT_COUNT" => match parse_count(&value) {
+                Some(n) => preferences.max_repeat_count = Some(n),
+                None => diagnostics.push(invalid(preference, name, "a whole number", &value))
+            },
+            "MAX_LOOP_COUNT" | "MAX_WHILE_COUNT" => match parse_count(&value) {
+                Some(n) => preferences.max_loop_count = Some(n),
+                None => diagnostics.push(invalid(preference, name, "a whole number", &value))
+            },
+            _ => {
+                preferences.other.insert(name.to_owned(), value);
+            }
+        }
+    }
+
+    preferences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ analyze_component_file, Component };
+    use std::collections::HashSet;
+
+    // The example library from the README with its preferences filled in
+    const LIBRARY: &str = include_str!("../fixtures/person.cmp");
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn preferences_are_read_from_a_component_file() {
+        let analysis = analyze_component_file(LIBRARY);
+
+        assert!(analysis.diagnostics.is_empty(), "{:?}", analysis.diagnostics);
+        assert_eq!(analysis.preferences, LibraryPreferences {
+            interview_computation: Some(String::from("Interview")),
+            date_format: Some(String::from("d MONTH yyyy")),
+            number_format: Some(String::from("9,999.00")),
+            language: Some(String::from("ENG")),
+            default_answer_file: None,
+            save_answers: Some(true),
+            max_repeat_count: Some(100),
+            max_loop_count: Some(5000),
+            other: vec![(String::from("MAX_STACK_DEPTH"), String::from("100"))].into_iter().collect()
+        });
+    }
+
+    #[test]
+    fn dependencies_are_read_alongside_the_preferences() {
+        let analysis = analyze_component_file(LIBRARY);
+        let components = &analysis.components;
+
+        let dependencies = |name: &str| match &components[name] {
+            Component::Computation(uses) | Component::TrueFalse(uses) => uses.clone(),
+            other => panic!("{} was read as {:?}", name, other)
+        };
+        assert_eq!(dependencies("CalculateDiscountEligible"), names(&["AGE", "Eligible for Discount", "Person Date of Birth"]));
+        assert_eq!(dependencies("Eligible for Discount"), names(&["Person First Name"]));
+        assert_eq!(dependencies("Interview"), names(&["Person Details"]));
+        match &components["Person Details"] {
+            Component::Dialog { children, script, .. } => {
+                assert_eq!(children, &["Person First Name", "Person Last Name", "Person Date of Birth", "Eligible for Discount"]);
+                assert_eq!(script, &names(&["CalculateDiscountEligible"]));
+            },
+            other => panic!("Person Details was read as {:?}", other)
+        }
+    }
+
+    #[test]
+    fn invalid_values_are_reported_where_they_are_written() {
+        let library = LIBRARY.replace("<hd:preference name=\"MAX_REPEAT_COUNT\">100<", "<hd:preference name=\"MAX_REPEAT_COUNT\">many<");
+        let analysis = analyze_component_file(&library);
+
+        assert_eq!(analysis.preferences.max_repeat_count, None);
+        assert_eq!(analysis.diagnostics.len(), 1);
+        assert_eq!(analysis.diagnostics[0].message, "The MAX_REPEAT_COUNT preference should be a whole number but is \"many\"");
+        assert_eq!(analysis.diagnostics[0].location.unwrap().start.line, 10);
+    }
+}
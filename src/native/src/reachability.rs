@@ -0,0 +1,372 @@
+use crate::hd_script_parser::*;
+use crate::answers::{ AnswerSet, Date, Value };
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::interpreter::Interpreter;
+use crate::interval::Interval;
+use crate::preferences::LibraryPreferences;
+use crate::Component;
+
+use std::collections::HashMap;
+
+// Splits a binary expression into everything before its last operator, the operator, and the last operand. The
+// operators apply left to right, so this is the last operation evaluated.
+fn last_operation<'e, 'a>(b: &'e BinaryExpression<'a>) -> (Expression<'a>, &'e BinaryOperation, &'e Expression<'a>) {
+    let (last, init) = b.right.split_last().unwrap();
+    let left = if init.is_empty() {
+        b.left.clone()
+    } else {
+        Expression::Binary(Box::new(BinaryExpression { left: b.left.clone(), right: init.to_vec() }))
+    };
+    (left, &last.0, &last.1)
+}
+
+// The parts of a condition joined by the given operator, e.g. the conditions that all have to hold for an AND
+fn operands<'a>(expression: &Expression<'a>, joined_by: &BinaryOperation) -> Vec<Expression<'a>> {
+    match expression {
+        Expression::Binary(b) => match last_operation(b) {
+            (left, op, right) if op == joined_by => {
+                let mut parts = operands(&left, joined_by);
+                parts.extend(operands(right, joined_by));
+                parts
+            },
+            _ => vec![expression.clone()]
+        },
+        _ => vec![expression.clone()]
+    }
+}
+
+// A variable compared with a number, e.g. Age > 18 or 18 < Age
+fn comparison<'e>(expression: &'e Expression) -> Option<(&'e str, BinaryOperation, f64)> {
+    let (left, op, right) = match expression {
+        Expression::Binary(b) if b.right.len() == 1 => (&b.left, &b.right[0].0, &b.right[0].1),
+        _ => return None
+    };
+    match (left, right) {
+        (Expression::Variable(v), Expression::Literal(LiteralExpression::Number(n))) if v.indexer.is_none() => Some((v.name.trim(), op.clone(), *n)),
+        (Expression::Literal(LiteralExpression::Number(n)), Expression::Variable(v)) if v.indexer.is_none() => Some((v.name.trim(), op.flipped()?, *n)),
+        _ => None
+    }
+}
+
+fn atom_implies(condition: &Expression, other: &Expression) -> bool {
+    if condition.to_string() == other.to_string() {
+        return true;
+    }
+    match (comparison(condition), comparison(other)) {
+        (Some((a, a_op, a_n)), Some((b, b_op, b_n))) if a == b => match (Interval::of(&a_op, a_n), b_op) {
+            (Some(interval), BinaryOperation::NE) => !interval.contains(b_n),
+            (Some(interval), b_op) => Interval::of(&b_op, b_n).is_some_and(|other| interval.within(&other)),
+            _ => false
+        },
+        _ => false
+    }
+}
+
+// Whether the other condition holds whenever the condition does. Only simple comparisons of a variable with a number
+// are understood, along with conditions that are written the same way.
+fn implies(condition: &Expression, other: &Expression) -> bool {
+    let conditions = operands(condition, &BinaryOperation::And);
+    let alternatives = operands(other, &BinaryOperation::Or);
+    conditions.iter().any(|c| alternatives.iter().any(|a| atom_implies(c, a)))
+}
+
+fn ends_script(statements: &[Spanned<Statement>]) -> bool {
+    match statements.iter().rev().find(|s| !matches!(s.node, Statement::Comment(_))).map(|s| &s.node) {
+        Some(Statement::Instruction(Instruction::Script(ScriptInstruction::Quit))) => true,
+        Some(Statement::If(s)) => ends_script(&s.then_body) && !s.else_body.is_empty() && ends_script(&s.else_body),
+        _ => false
+    }
+}
+
+fn assigned_in<'a>(statements: &[Spanned<'a, Statement<'a>>], out: &mut Vec<&'a str>) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::If(s) => {
+                assigned_in(&s.then_body, out);
+                assigned_in(&s.else_body, out);
+            },
+            Statement::Loop(LoopStatement::While(s)) => assigned_in(&s.body, out),
+            Statement::Loop(LoopStatement::Repeat(s)) => assigned_in(&s.body, out),
+            Statement::Instruction(Instruction::Script(
+                ScriptInstruction::Set(v, _) | ScriptInstruction::Add(v, _) | ScriptInstruction::Default(v, _)
+                | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) | ScriptInstruction::Erase(v)
+            )) => out.push(v.name.trim()),
+            _ => ()
+        }
+    }
+}
+
+// One IF or ELSE IF of a chain
+struct Link<'s, 'a> {
+    statement: &'s Spanned<'a, Statement<'a>>,
+    condition: &'s Expression<'a>,
+    body: &'s [Spanned<'a, Statement<'a>>]
+}
+
+struct Linter<'s, 'a> {
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    preferences: &'s LibraryPreferences,
+    source: &'a str,
+    // The variables set to a constant value earlier in the script, with the SET that did it
+    known: HashMap<&'a str, (Value, &'a str)>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'s, 'a> Linter<'s, 'a> {
+    fn span(&self, slice: &str) -> Option<Span> {
+        Span::of(self.source, slice)
+    }
+
+    // Findings about an IF point at the line with its condition rather than the whole statement
+    fn warn(&mut self, message: String, statement: &Spanned<Statement>) {
+        let span = self.span(statement.first_line());
+        self.diagnostics.push(Diagnostic::warning(message).at(span));
+    }
+
+    // Collects the SETs an expression depends on, and whether everything it depends on is known
+    fn known_inputs(&self, expression: &Expression, sets: &mut Vec<&'a str>) -> bool {
+        match expression {
+            Expression::Literal(_) => true,
+            Expression::Variable(v) => match self.known.get(v.name.trim()) {
+                Some((_, set)) if v.indexer.is_none() => {
+                    if !sets.contains(set) {
+                        sets.push(set);
+                    }
+                    true
+                },
+                _ => false
+            },
+            Expression::FunctionCall(call) => !self.scripts.contains_key(call.name.trim()) && call.args.iter().all(|a| self.known_inputs(a, sets)),
+            Expression::Binary(b) => self.known_inputs(&b.left, sets) && b.right.iter().all(|(_, e)| self.known_inputs(e, sets)),
+            Expression::Unary(u) => self.known_inputs(&u.expression, sets),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.known_inputs(e, sets)
+        }
+    }
+
+    fn constant(&self, expression: &Expression) -> Option<(Value, Vec<&'a str>)> {
+        let mut sets = vec![];
+        if !self.known_inputs(expression, &mut sets) {
+            return None;
+        }
+
+        let mut answers = AnswerSet::new();
+        for (name, (value, _)) in self.known.iter() {
+            answers.set(name, vec![], value.clone());
+        }
+        let mut interpreter = Interpreter::new(self.components, self.scripts, self.preferences, answers, Date::from_days(0));
+        interpreter.value_of(expression).ok().map(|value| (value, sets))
+    }
+
+    fn chain(&mut self, statement: &'s Spanned<'a, Statement<'a>>) {
+        let mut links = vec![];
+        let mut current = statement;
+        let mut else_body: &'s [Spanned<'a, Statement<'a>>] = &[];
+        while let Statement::If(s) = &current.node {
+            links.push(Link { statement: current, condition: &s.condition, body: &s.then_body });
+            match s.else_body.as_slice() {
+                [next @ Spanned { node: Statement::If(_), .. }] => current = next,
+                body => {
+                    else_body = body;
+                    break;
+                }
+            }
+        }
+
+        for (i, link) in links.iter().enumerate() {
+            let name = if i == 0 { "IF" } else { "ELSE IF" };
+            let condition = link.condition.to_string();
+
+            if let Some(earlier) = links[..i].iter().find(|e| implies(link.condition, e.condition)) {
+                let earlier_condition = earlier.condition.to_string();
+                let reason = if earlier_condition == condition {
+                    String::from("it repeats an earlier condition of the chain")
+                } else {
+                    format!("whenever {} holds, the earlier condition {} holds as well", condition, earlier_condition)
+                };
+                let same = if link.body.iter().map(|s| &s.node).eq(earlier.body.iter().map(|s| &s.node)) {
+                    ", and it does the same as that branch"
+                } else {
+                    ""
+                };
+                self.warn(format!("{} {} can never run: {}{}", name, condition, reason, same), link.statement);
+                continue;
+            }
+
+            let (value, sets) = match self.constant(link.condition) {
+                Some((Value::TrueFalse(b), sets)) => (b, sets),
+                _ => continue
+            };
+            let after = if sets.is_empty() { String::new() } else { format!(" after {}", sets.join(" and ")) };
+            if !value {
+                self.warn(format!("{} {} can never run: the condition is always false{}", name, condition, after), link.statement);
+            } else if i + 1 < links.len() || !else_body.is_empty() {
+                self.warn(format!("The condition {} always holds{}, so the rest of the chain after it never runs", condition, after), link.statement);
+            } else if !sets.is_empty() {
+                self.warn(format!("The condition {} always holds{}", condition, after), link.statement);
+            }
+        }
+    }
+
+    fn forget(&mut self, statements: &[Spanned<'a, Statement<'a>>]) {
+        let mut assigned = vec![];
+        assigned_in(statements, &mut assigned);
+        for name in assigned {
+            self.known.remove(name);
+        }
+    }
+
+    // Walks a branch with what is known before it, keeping nothing it learns afterwards
+    fn branch(&mut self, statements: &'s [Spanned<'a, Statement<'a>>]) {
+        let known = self.known.clone();
+        self.block(statements);
+        self.known = known;
+    }
+
+    fn is_dialog(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Variable(v) => matches!(self.components.get(v.name.trim()), Some(Component::Dialog { .. })),
+            _ => false
+        }
+    }
+
+    fn block(&mut self, statements: &'s [Spanned<'a, Statement<'a>>]) {
+        let mut quit: Option<&Spanned<Statement>> = None;
+        // A result only stands until the script reaches the next one
+        let mut result: Option<&Spanned<Statement>> = None;
+
+        for (i, statement) in statements.iter().enumerate() {
+            if matches!(statement.node, Statement::Comment(_)) {
+                continue;
+            }
+            if let Some(quit) = quit {
+                let last = statements[i..].iter().rev().find(|s| !matches!(s.node, Statement::Comment(_))).unwrap();
+                let span = self.span(statement.source).zip(self.span(last.source)).map(|(a, b)| a.join(b));
+                let after = if matches!(quit.node, Statement::If(_)) { "an IF whose every branch QUITs" } else { "QUIT" };
+                self.diagnostics.push(Diagnostic::warning(format!("This code can never run, it comes after {}", after)).at(span));
+                return;
+            }
+
+            match &statement.node {
+                Statement::If(s) => {
+                    self.chain(statement);
+                    self.branch(&s.then_body);
+                    self.branch(&s.else_body);
+                    self.forget(std::slice::from_ref(statement));
+                },
+                Statement::Loop(LoopStatement::While(s)) => {
+                    // Anything the loop changes can be different on each time round it
+                    self.forget(&s.body);
+                    self.branch(&s.body);
+                },
+                Statement::Loop(LoopStatement::Repeat(s)) => {
+                    self.forget(&s.body);
+                    self.branch(&s.body);
+                },
+                Statement::Instruction(Instruction::Script(ScriptInstruction::Set(v, e))) if v.indexer.is_none() => {
+                    let name = v.name.trim();
+                    match self.constant(e) {
+                        Some((value, _)) if value.is_answered() => {
+                            self.known.insert(name, (value, statement.source));
+                        },
+                        _ => {
+                            self.known.remove(name);
+                        }
+                    }
+                },
+                // Asking a dialog can change any of the answers
+                Statement::Instruction(Instruction::Display(DisplayInstruction::Ask(_))) => self.known.clear(),
+                Statement::Return(e) if !self.is_dialog(e) => {
+                    if let Some(earlier) = result.replace(statement) {
+                        let message = format!("The result {} is never used, the result {} after it always replaces it", earlier.source, statement.source);
+                        self.diagnostics.push(Diagnostic::warning(message).at(self.span(earlier.source)));
+                    }
+                },
+                _ => self.forget(std::slice::from_ref(statement))
+            }
+
+            if ends_script(std::slice::from_ref(statement)) {
+                quit = Some(statement);
+            }
+        }
+    }
+}
+
+// Finds the branches of IF statements that can never run, the code after a QUIT, and results that a later result
+// always replaces. A result doesn't end a computation, the script carries on and the last result reached is returned.
+pub fn check(
+    components: &HashMap<String, Component>,
+    computations: &HashMap<&str, Script>,
+    dialogs: &HashMap<&str, Script>,
+    preferences: &LibraryPreferences
+) -> Vec<Diagnostic> {
+    let mut names: Vec<_> = computations.keys().map(|n| (*n, true)).chain(dialogs.keys().map(|n| (*n, false))).collect();
+    names.sort();
+
+    let mut diagnostics = vec![];
+    for (name, computation) in names {
+        let script = if computation { &computations[name] } else { &dialogs[name] };
+        let mut linter = Linter { components, scripts: computations, preferences, source: script.source, known: HashMap::new(), diagnostics: vec![] };
+        linter.block(&script.body);
+        diagnostics.extend(linter.diagnostics.into_iter().map(|d| d.for_component(name)));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+    use std::collections::HashSet;
+
+    // The message of each warning with the part of the script it points at
+    fn warnings(script: &str) -> Vec<(String, &str)> {
+        let (_, ast) = hd_script_parser::parse(script).unwrap();
+        let computations: HashMap<&str, Script> = vec![("Test", ast)].into_iter().collect();
+        let components = vec![(String::from("Age"), Component::Number(HashSet::new()))].into_iter().collect();
+        check(&components, &computations, &HashMap::new(), &LibraryPreferences::default())
+            .into_iter()
+            .map(|d| (d.message, d.span.map_or("", |span| &script[span.start..span.end])))
+            .collect()
+    }
+
+    #[test]
+    fn if_false_never_runs() {
+        assert_eq!(warnings("IF FALSE\n\"a\"\nEND IF"), vec![
+            (String::from("IF FALSE can never run: the condition is always false"), "IF FALSE")
+        ]);
+    }
+
+    #[test]
+    fn an_else_if_an_earlier_condition_covers_never_runs() {
+        assert_eq!(warnings("IF Age > 10\n\"a\"\nELSE IF Age >= 20\n\"b\"\nEND IF"), vec![
+            (String::from("ELSE IF Age >= 20 can never run: whenever Age >= 20 holds, the earlier condition Age > 10 holds as well"), "IF Age >= 20")
+        ]);
+    }
+
+    #[test]
+    fn a_repeated_condition_never_runs() {
+        assert_eq!(warnings("IF Age > 10\n\"a\"\nELSE IF Age > 10\n\"a\"\nEND IF"), vec![
+            (String::from("ELSE IF Age > 10 can never run: it repeats an earlier condition of the chain, and it does the same as that branch"), "IF Age > 10")
+        ]);
+    }
+
+    #[test]
+    fn a_condition_an_earlier_set_decides_always_holds() {
+        assert_eq!(warnings("SET Age TO 30\nIF Age > 18\n\"a\"\nELSE\n\"b\"\nEND IF"), vec![
+            (String::from("The condition Age > 18 always holds after SET Age TO 30, so the rest of the chain after it never runs"), "IF Age > 18")
+        ]);
+    }
+
+    #[test]
+    fn code_after_quit_never_runs() {
+        assert_eq!(warnings("\"a\"\nQUIT\n\"b\"\n// the end\n\"c\""), vec![
+            (String::from("This code can never run, it comes after QUIT"), "\"b\"\n// the end\n\"c\"")
+        ]);
+    }
+
+    #[test]
+    fn branches_that_can_run_are_not_reported() {
+        assert_eq!(warnings("IF Age > 10\n\"a\"\nELSE IF Age > 5\n\"b\"\nELSE\nQUIT\nEND IF\nSET Age TO Age + 1\nIF Age > 18\n\"c\"\nEND IF"), vec![]);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::hd_script_parser::{ self, * };
+use crate::component_library::ComponentLibrary;
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::merge_field_parser;
+use crate::xml_parser::{ self, Node, XmlElement };
+use crate::patch::Patched;
+use crate::repeats::expression_names;
+use crate::xml_writer::{ self, Document };
+
+use std::collections::HashMap;
+
+// The components HotDocs creates alongside a multiple choice variable, named after it
+const COMPANION_SUFFIXES: [&str; 4] = ["_SelectionVariable", "_MultiSelectVariable", "_OptionTable", "_TableVariable"];
+
+// Characters that would stop the name being read back as a single variable reference or merge field
+const RESERVED_CHARACTERS: &str = "«»[](),\":\r\n";
+
+fn call_names<'e>(call: &'e FunctionCall, names: &mut Vec<&'e str>) {
+    names.push(call.name.trim());
+    for arg in call.args.iter() {
+        expression_names(arg, names);
+    }
+}
+
+fn variable_names<'e>(variable: &'e VariableReference, names: &mut Vec<&'e str>) {
+    names.push(variable.name.trim());
+    for arg in variable.indexer.iter().flat_map(|i| i.args.iter()) {
+        expression_names(arg, names);
+    }
+}
+
+// Every name the statements refer to, as slices of the script they were parsed from
+fn statement_names<'e>(statements: &'e [Spanned<Statement>], names: &mut Vec<&'e str>) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::Comment(_) => (),
+            Statement::If(s) => {
+                expression_names(&s.condition, names);
+                statement_names(&s.then_body, names);
+                statement_names(&s.else_body, names);
+            },
+            Statement::Loop(LoopStatement::While(s)) => {
+                expression_names(&s.condition, names);
+                statement_names(&s.body, names);
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                variable_names(&s.dialog, names);
+                statement_names(&s.body, names);
+            },
+            Statement::Return(e) => expression_names(e, names),
+            Statement::Instruction(Instruction::Display(instruction)) => match instruction {
+                DisplayInstruction::Ask(name) | DisplayInstruction::Gray(name) | DisplayInstruction::Hide(name)
+                | DisplayInstruction::Show(name) | DisplayInstruction::Ungray(name) | DisplayInstruction::Require(name) => names.push(name.trim()),
+                DisplayInstruction::Limit(LimitExpression::Variable(name)) => names.push(name.trim()),
+                DisplayInstruction::Limit(LimitExpression::Function(call)) => call_names(call, names),
+                _ => ()
+            },
+            Statement::Instruction(Instruction::Script(instruction)) => match instruction {
+                ScriptInstruction::Set(v, e) | ScriptInstruction::Add(v, e) | ScriptInstruction::Default(v, e) => {
+                    variable_names(v, names);
+                    expression_names(e, names);
+                },
+                ScriptInstruction::Erase(v) | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v)
+                | ScriptInstruction::Assemble(v) => variable_names(v, names),
+                ScriptInstruction::Ascend(name) | ScriptInstruction::Descend(name) => names.push(name.trim()),
+                _ => ()
+            }
+        }
+    }
+}
+
+// Whether the byte of the raw text is inside a CDATA section
+fn in_cdata(raw: &str, at: usize) -> bool {
+    match raw[..at].rfind("<![CDATA[") {
+        Some(open) => !raw[open..at].contains("]]>"),
+        None => false
+    }
+}
+
+struct Renamer<'d> {
+    document: Document<'d>,
+    // The new name for each old one, the component and its companions
+    names: HashMap<String, String>
+}
+
+impl<'d> Renamer<'d> {
+    fn rename_attribute(&mut self, element: &XmlElement<'d>, attribute: &str) {
+        if let Some(new) = element.attributes.get(attribute).and_then(|value| self.names.get(value)) {
+            let new = new.to_owned();
+            self.document.set_attribute(element, attribute, &new);
+        }
+    }
+
+    // Renames the references in text that is only escaped characters, a script or a prompt, mapping each one back to
+    // where it was written in the raw text
+    fn rename_text(&mut self, content: &'d str, script: bool, diagnostics: &mut Vec<Diagnostic>) {
+        let (text, offsets) = xml_parser::unescape_with_offsets(content);
+
+        let mut names: Vec<&str> = merge_field_parser::parse(&text).into_iter().map(|f| f.name).collect();
+        let parsed = if script { Some(hd_script_parser::parse(&text)) } else { None };
+        match &parsed {
+            Some(Ok((remaining, ast))) => {
+                if !remaining.trim().is_empty() {
+                    diagnostics.push(Diagnostic::warning(String::from("Only part of the script could be parsed, references after that part were not renamed")));
+                }
+                statement_names(&ast.body, &mut names);
+            },
+            Some(Err(_)) => diagnostics.push(Diagnostic::warning(String::from("The script could not be parsed, only the merge fields in it were renamed"))),
+            None => ()
+        }
+
+        let mut spans: Vec<Span> = names
+            .into_iter()
+            .filter(|name| self.names.contains_key(*name))
+            .filter_map(|name| Span::of(&text, name))
+            .collect();
+        spans.sort();
+        spans.dedup();
+
+        for span in spans {
+            let new = &self.names[&text[span.start..span.end]];
+            // Entities aren't read inside CDATA, so the name is written there as it is
+            let new = if in_cdata(content, offsets[span.start]) { new.to_owned() } else { xml_writer::escape(new, false) };
+            self.document.replace(&content[offsets[span.start]..offsets[span.end]], new);
+        }
+    }
+
+    fn rename_contents(&mut self, library: &ComponentLibrary<'d, '_>, element: &XmlElement<'d>, diagnostics: &mut Vec<Diagnostic>) {
+        if library.is(element, "contents") {
+            for item in library.children(element) {
+                self.rename_attribute(item, "name");
+            }
+        }
+
+        if library.children(element).next().is_none() {
+            return self.rename_text(element.content, library.is(element, "script"), diagnostics);
+        }
+        // Text mixed in with elements, e.g. a prompt with formatting, is renamed a piece at a time
+        for child in element.children.iter() {
+            match child {
+                Node::XmlElement(child) => self.rename_contents(library, child, diagnostics),
+                Node::Value(text) => self.rename_text(text.source, false, diagnostics),
+                Node::Comment(_) | Node::ProcessingInstruction(..) => ()
+            }
+        }
+    }
+}
+
+fn invalid_name(name: &str) -> Option<String> {
+    if name.trim().is_empty() {
+        Some(String::from("The new name is empty"))
+    } else if name.trim() != name {
+        Some(format!("\"{}\" starts or ends with whitespace", name))
+    } else {
+        name.chars().find(|c| RESERVED_CHARACTERS.contains(*c)).map(|c| format!("\"{}\" can't be used in a component name", c))
+    }
+}
+
+// Renames the component, its companions and every reference to them, editing only the spans that name them
+pub fn rename(document: &str, library: &ComponentLibrary, old: &str, new: &str) -> Patched {
+    let failed = |diagnostic: Diagnostic| Patched::unchanged(document, vec![diagnostic]);
+
+    if let Some(message) = invalid_name(new) {
+        return failed(Diagnostic::error(message));
+    }
+
+    let companions = || COMPANION_SUFFIXES.iter().map(|s| (format!("{}{}", old, s), format!("{}{}", new, s)));
+    let names: HashMap<String, String> = std::iter::once((old.to_owned(), new.to_owned()))
+        .chain(companions())
+        .filter(|(from, _)| library.components.iter().any(|c| c.name == from))
+        .collect();
+
+    if names.is_empty() {
+        return failed(Diagnostic::error(format!("{} is not a component in this library", old)));
+    }
+    if old == new {
+        return Patched::unchanged(document, vec![]);
+    }
+    let mut taken = std::iter::once(new.to_owned()).chain(companions().map(|(_, to)| to));
+    if let Some(taken) = taken.find(|to| library.components.iter().any(|c| c.name == to.as_str())) {
+        return failed(Diagnostic::error(format!("{} is already a component in this library", taken)));
+    }
+    // Scripts refer to a multiple choice variable by the name without its companion suffix
+    let mut names = names;
+    names.insert(old.to_owned(), new.to_owned());
+
+    let mut renamer = Renamer { document: Document::new(document), names };
+    let mut diagnostics = vec![];
+
+    for component in library.components.iter() {
+        let mut component_diagnostics = vec![];
+        renamer.rename_attribute(component.element, "name");
+        renamer.rename_contents(library, component.element, &mut component_diagnostics);
+        diagnostics.extend(component_diagnostics.into_iter().map(|d| d.for_component(component.name)));
+    }
+
+    if let Some(preferences) = library.sections.get("preferences") {
+        let interview = library
+            .children(preferences)
+            .filter(|p| matches!(p.attributes.get("name").map(|n| n.as_str()), Some("INTERVIEW_COMPUTATION") | Some("CUSTOM_INTERVIEW_COMPUTATION")));
+        for preference in interview {
+            match preference.attributes.get("value") {
+                Some(_) => renamer.rename_attribute(preference, "value"),
+                None if preference.text().trim() == old => {
+                    renamer.document.replace(preference.content.trim(), xml_writer::escape(new, false));
+                },
+                None => ()
+            }
+        }
+    }
+
+    Patched::from(renamer.document, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ component_library, xml_reader };
+
+    fn library(components: &str) -> String {
+        format!("<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:components>\n{}\n</hd:components>\n</hd:componentLibrary>", components)
+    }
+
+    fn renamed(document: &str, old: &str, new: &str) -> Patched {
+        let root = xml_reader::read_tree(document).unwrap();
+        let library = component_library::load(&root);
+        rename(document, &library, old, new)
+    }
+
+    #[test]
+    fn names_in_cdata_are_written_unescaped() {
+        let document = library("<hd:text name=\"Client\"/>\n<hd:computation name=\"Greeting\"><hd:script><![CDATA[IF Client != \"\"\n\"Hi «Client»\"\nEND IF]]></hd:script></hd:computation>");
+        let patched = renamed(&document, "Client", "Client & Co");
+
+        assert!(patched.diagnostics.is_empty(), "{:?}", patched.diagnostics);
+        assert_eq!(patched.xml, document
+            .replace("name=\"Client\"", "name=\"Client &amp; Co\"")
+            .replace("IF Client", "IF Client & Co")
+            .replace("«Client»", "«Client & Co»"));
+    }
+
+    #[test]
+    fn merge_fields_in_prompts_are_renamed() {
+        let document = library("<hd:text name=\"Client\"/>\n<hd:text name=\"Spouse\"><hd:prompt>Name of «Client»'s spouse</hd:prompt></hd:text>\n<hd:text name=\"Child\"><hd:prompt>Name of <b>«Client»</b>'s child, not «Client:LIKE THIS»</hd:prompt></hd:text>");
+        let patched = renamed(&document, "Client", "Customer");
+
+        assert!(patched.diagnostics.is_empty(), "{:?}", patched.diagnostics);
+        assert_eq!(patched.xml, document.replace("\"Client\"", "\"Customer\"").replace("«Client", "«Customer"));
+    }
+
+    #[test]
+    fn dialog_items_are_renamed() {
+        let document = library("<hd:text name=\"Client\"/>\n<hd:dialog name=\"Details\"><hd:contents><hd:item name=\"Client\"/><hd:item name=\"Clients\"/></hd:contents></hd:dialog>");
+        let patched = renamed(&document, "Client", "Customer");
+
+        assert_eq!(patched.xml, document.replace("\"Client\"", "\"Customer\""));
+    }
+
+    #[test]
+    fn scripts_that_only_partly_parse_are_renamed_up_to_where_parsing_stopped() {
+        let document = library("<hd:text name=\"Client\"/>\n<hd:computation name=\"Greeting\"><hd:script>SET Client TO \"«Client»\"\n)))\nSET Client TO \"b\"</hd:script></hd:computation>");
+        let patched = renamed(&document, "Client", "Customer");
+
+        assert_eq!(patched.xml, document
+            .replace("\"Client\"", "\"Customer\"")
+            .replace("SET Client TO \"«Client»\"", "SET Customer TO \"«Customer»\""));
+        assert_eq!(patched.diagnostics.len(), 1);
+        assert_eq!(patched.diagnostics[0].message, "Only part of the script could be parsed, references after that part were not renamed");
+        assert_eq!(patched.diagnostics[0].component.as_deref(), Some("Greeting"));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::hd_script_parser::*;
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::merge_field_parser;
+use crate::Component;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+fn collect_repeats(statements: &[Spanned<Statement>], depth: usize, repeats: &mut HashMap<String, BTreeSet<usize>>) {
+    for statement in statements.iter() {
+        match &statement.node {
+            Statement::If(s) => {
+                collect_repeats(&s.then_body, depth, repeats);
+                collect_repeats(&s.else_body, depth, repeats);
+            },
+            Statement::Loop(LoopStatement::While(s)) => collect_repeats(&s.body, depth, repeats),
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                repeats.entry(s.dialog.name.trim().to_owned()).or_default().insert(depth + 1);
+                collect_repeats(&s.body, depth + 1, repeats);
+            },
+            _ => ()
+        }
+    }
+}
+
+// How deeply each dialog is repeated by the scripts in the library, a dialog can be repeated at more than one depth
+pub fn dialog_depths(scripts: &HashMap<&str, Script>) -> HashMap<String, BTreeSet<usize>> {
+    let mut repeats = HashMap::new();
+    for script in scripts.values() {
+        collect_repeats(&script.body, 0, &mut repeats);
+    }
+    repeats
+}
+
+// How deeply each variable is repeated through the dialogs it is on. Variables that are only on dialogs no script
+// repeats have no depths, they may still be repeated by a template.
+pub fn variable_depths(components: &HashMap<String, Component>, scripts: &HashMap<&str, Script>) -> HashMap<String, BTreeSet<usize>> {
+    let repeats = dialog_depths(scripts);
+    let mut depths: HashMap<String, BTreeSet<usize>> = HashMap::new();
+
+    for (name, component) in components.iter() {
+        if let Component::Dialog { children, .. } = component {
+            for child in children.iter() {
+                depths.entry(child.to_owned()).or_default().extend(repeats.get(name).into_iter().flatten());
+            }
+        }
+    }
+
+    depths
+}
+
+// A place a script runs another script: a computation it uses, a dialog it asks, or a dialog it repeats
+fn collect_entries<'s>(statements: &'s [Spanned<Statement>], depth: usize, entries: &mut Vec<(&'s str, usize)>) {
+    for statement in statements.iter() {
+        let mut names = vec![];
+        match &statement.node {
+            Statement::If(s) => {
+                expression_names(&s.condition, &mut names);
+                collect_entries(&s.then_body, depth, entries);
+                collect_entries(&s.else_body, depth, entries);
+            },
+            Statement::Loop(LoopStatement::While(s)) => {
+                expression_names(&s.condition, &mut names);
+                collect_entries(&s.body, depth, entries);
+            },
+            Statement::Loop(LoopStatement::Repeat(s)) => {
+                entries.push((s.dialog.name.trim(), depth + 1));
+                collect_entries(&s.body, depth + 1, entries);
+            },
+            Statement::Return(e) => expression_names(e, &mut names),
+            Statement::Instruction(Instruction::Script(ScriptInstruction::Set(_, e)))
+            | Statement::Instruction(Instruction::Script(ScriptInstruction::Add(_, e)))
+            | Statement::Instruction(Instruction::Script(ScriptInstruction::Default(_, e))) => expression_names(e, &mut names),
+            Statement::Instruction(Instruction::Display(DisplayInstruction::Ask(name))) => names.push(name.trim()),
+            _ => ()
+        }
+        entries.extend(names.into_iter().map(|n| (n, depth)));
+    }
+}
+
+// The names of the variables and functions an expression uses, including the fields merged into its text
+pub fn expression_names<'e>(expression: &'e Expression, names: &mut Vec<&'e str>) {
+    match expression {
+        Expression::Variable(v) => {
+            names.push(v.name.trim());
+            for arg in v.indexer.iter().flat_map(|i| i.args.iter()) {
+                expression_names(arg, names);
+            }
+        },
+        Expression::FunctionCall(call) => {
+            names.push(call.name.trim());
+            for arg in call.args.iter() {
+                expression_names(arg, names);
+            }
+        },
+        Expression::Binary(b) => {
+            expression_names(&b.left, names);
+            for (_, e) in b.right.iter() {
+                expression_names(e, names);
+            }
+        },
+        Expression::Unary(u) => expression_names(&u.expression, names),
+        Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => expression_names(e, names),
+        Expression::Literal(LiteralExpression::Text(t)) => names.extend(merge_field_parser::parse(t).iter().map(|f| f.name.trim())),
+        Expression::Literal(_) => ()
+    }
+}
+
+// How deeply nested in REPEATs each script is when it starts running. A computation runs inside the REPEATs around
+// the places it is used, and a dialog's script inside the REPEATs around the places it is asked or repeated. Scripts
+// used in more than one place get the shallowest, and scripts nothing uses start outside any REPEAT.
+pub fn entry_depths(computations: &HashMap<&str, Script>, dialogs: &HashMap<&str, Script>) -> HashMap<String, usize> {
+    let scripts: Vec<(&str, &Script)> = computations.iter().chain(dialogs.iter()).map(|(n, s)| (*n, s)).collect();
+    let entries: Vec<(&str, Vec<(&str, usize)>)> = scripts
+        .iter()
+        .map(|(name, script)| {
+            let mut entries = vec![];
+            collect_entries(&script.body, 0, &mut entries);
+            (*name, entries)
+        })
+        .collect();
+
+    let is_script = |name: &str| computations.contains_key(name) || dialogs.contains_key(name);
+    let used: HashSet<&str> = entries.iter().flat_map(|(_, uses)| uses.iter().map(|(callee, _)| *callee)).collect();
+    let mut depths: HashMap<String, usize> = scripts.iter().filter(|(name, _)| !used.contains(name)).map(|(name, _)| ((*name).to_owned(), 0)).collect();
+
+    // Depths only get shallower from one round to the next, so this settles within a round per script
+    for _ in 0..=scripts.len() {
+        let mut changed = false;
+        for (caller, uses) in entries.iter() {
+            let start = match depths.get(*caller) {
+                Some(start) => *start,
+                None => continue
+            };
+            for (callee, depth) in uses.iter().filter(|(callee, _)| is_script(callee)) {
+                let entry = depths.entry((*callee).to_owned()).or_insert(usize::MAX);
+                if start + depth < *entry {
+                    *entry = start + depth;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    depths
+}
+
+fn uses_counter(expression: &Expression) -> bool {
+    let mut names = vec![];
+    expression_names(expression, &mut names);
+    names.contains(&"COUNTER")
+}
+
+struct Checker<'s, 'a> {
+    source: &'a str,
+    depths: &'s HashMap<String, BTreeSet<usize>>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'s, 'a> Checker<'s, 'a> {
+    fn report(&mut self, message: String, name: &str) {
+        let span = Span::of(self.source, name);
+        self.diagnostics.push(Diagnostic::warning(message).at(span));
+    }
+
+    fn variable(&mut self, v: &VariableReference<'a>, depth: usize, read: bool) {
+        let name = v.name.trim();
+        match &v.indexer {
+            Some(indexer) => {
+                let relative = indexer.args.iter().any(uses_counter);
+                // An index can pick an answer of any REPEAT around it or of a dialog the variable is repeated on.
+                // Variables no script repeats may be repeated by a template, so only their COUNTERs are checked.
+                let deepest = self.depths.get(name).and_then(|depths| depths.iter().max().copied());
+                let allowed = match deepest {
+                    Some(deepest) => Some(deepest.max(depth)),
+                    None if relative => Some(depth),
+                    None => None
+                };
+                if relative && depth == 0 {
+                    self.report(format!("{} is indexed with COUNTER outside any REPEAT", v), v.name);
+                } else if let Some(allowed) = allowed.filter(|a| indexer.args.len() > *a) {
+                    self.report(format!("{} has {} index(es) but is repeated at most {} level(s) deep here", v, indexer.args.len(), allowed), v.name);
+                }
+                // A COUNTER outside any REPEAT has already been reported along with the variable it indexes
+                for arg in indexer.args.iter().filter(|a| depth > 0 || !uses_counter(a)) {
+                    self.expression(arg, depth);
+                }
+            },
+            None if name == "COUNTER" && depth == 0 => self.report(String::from("COUNTER is used outside any REPEAT"), v.name),
+            None if read && depth == 0 && self.depths.get(name).is_some_and(|d| d.iter().any(|d| *d > 0)) => {
+                self.report(format!("{} is repeated but is read without an index outside any REPEAT, so only one of its answers is used", name), v.name);
+            },
+            None => ()
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression<'a>, depth: usize) {
+        match expression {
+            Expression::Variable(v) => self.variable(v, depth, true),
+            // COUNT takes the name of a repeated dialog rather than one of its answers
+            Expression::FunctionCall(call) if call.name.trim() == "COUNT" => (),
+            Expression::FunctionCall(call) => {
+                for arg in call.args.iter() {
+                    self.expression(arg, depth);
+                }
+            },
+            Expression::Binary(b) => {
+                self.expression(&b.left, depth);
+                for (_, e) in b.right.iter() {
+                    self.expression(e, depth);
+                }
+            },
+            Expression::Unary(u) => self.expression(&u.expression, depth),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.expression(e, depth),
+            Expression::Literal(_) => ()
+        }
+    }
+
+    fn statements(&mut self, statements: &[Spanned<'a, Statement<'a>>], depth: usize) {
+        for statement in statements.iter() {
+            match &statement.node {
+                Statement::If(s) => {
+                    self.expression(&s.condition, depth);
+                    self.statements(&s.then_body, depth);
+                    self.statements(&s.else_body, depth);
+                },
+                Statement::Loop(LoopStatement::While(s)) => {
+                    self.expression(&s.condition, depth);
+                    self.statements(&s.body, depth);
+                },
+                Statement::Loop(LoopStatement::Repeat(s)) => self.statements(&s.body, depth + 1),
+                Statement::Return(e) => self.expression(e, depth),
+                Statement::Instruction(Instruction::Script(instruction)) => match instruction {
+                    ScriptInstruction::Set(v, e) | ScriptInstruction::Add(v, e) | ScriptInstruction::Default(v, e) => {
+                        self.variable(v, depth, false);
+                        self.expression(e, depth);
+                    },
+                    ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) => self.variable(v, depth, true),
+                    ScriptInstruction::Erase(v) => self.variable(v, depth, false),
+                    _ => ()
+                },
+                _ => ()
+            }
+        }
+    }
+}
+
+// Checks that indexes and repeated variables fit how deeply nested in REPEATs each part of a script is
+pub fn check(components: &HashMap<String, Component>, computations: &HashMap<&str, Script>, dialogs: &HashMap<&str, Script>) -> Vec<Diagnostic> {
+    let depths = variable_depths(components, computations);
+    let entries = entry_depths(computations, dialogs);
+
+    let mut names: Vec<_> = computations.keys().map(|n| (*n, true)).chain(dialogs.keys().map(|n| (*n, false))).collect();
+    names.sort();
+
+    let mut diagnostics = vec![];
+    for (name, computation) in names {
+        let script = if computation { &computations[name] } else { &dialogs[name] };
+        let mut checker = Checker { source: script.source, depths: &depths, diagnostics: vec![] };
+        checker.statements(&script.body, entries.get(name).copied().unwrap_or(0));
+        diagnostics.extend(checker.diagnostics.into_iter().map(|d| d.for_component(name)));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+
+    // The message of each warning for a computation, with Age asked on the Children dialog
+    fn warnings(script: &str) -> Vec<String> {
+        let (_, ast) = hd_script_parser::parse(script).unwrap();
+        let computations: HashMap<&str, Script> = vec![("Test", ast)].into_iter().collect();
+        let components = vec![
+            (String::from("Age"), Component::Number(HashSet::new())),
+            (String::from("Total"), Component::Number(HashSet::new())),
+            (String::from("Children"), Component::Dialog { children: vec![String::from("Age")], script: HashSet::new(), fields: HashSet::new() })
+        ].into_iter().collect();
+        check(&components, &computations, &HashMap::new()).into_iter().map(|d| d.message).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn counter_is_only_used_inside_a_repeat() {
+        assert_eq!(warnings("SET Total TO Age[COUNTER]"), vec![String::from("Age[COUNTER] is indexed with COUNTER outside any REPEAT")]);
+        assert_eq!(warnings("REPEAT Children\nSET Total TO Total + Age[COUNTER]\nEND REPEAT"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn indexes_past_the_repeat_depth_are_flagged() {
+        assert_eq!(warnings("REPEAT Children\nSET Total TO Age[COUNTER, COUNTER]\nEND REPEAT"), vec![
+            String::from("Age[COUNTER, COUNTER] has 2 index(es) but is repeated at most 1 level(s) deep here")
+        ]);
+        assert_eq!(warnings("REPEAT Children\nEND REPEAT\nSET Total TO Age[1, 2]"), vec![
+            String::from("Age[1, 2] has 2 index(es) but is repeated at most 1 level(s) deep here")
+        ]);
+    }
+
+    #[test]
+    fn a_repeated_variable_can_be_indexed_outside_a_repeat() {
+        assert_eq!(warnings("REPEAT Children\nEND REPEAT\nSET Total TO Age[2]"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn a_repeated_variable_read_without_an_index_outside_a_repeat_is_flagged() {
+        assert_eq!(warnings("REPEAT Children\nEND REPEAT\nSET Total TO Age"), vec![
+            String::from("Age is repeated but is read without an index outside any REPEAT, so only one of its answers is used")
+        ]);
+    }
+}
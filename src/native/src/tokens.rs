@@ -0,0 +1,277 @@
+use crate::hd_script_parser::{ self, * };
+use crate::diagnostics::{ Diagnostic, LineIndex, Location, Span, Utf16Index };
+use crate::builtins;
+
+use nom::{ error::ErrorKind, number::complete::double };
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum TokenKind {
+    Keyword,
+    Operator,
+    Number,
+    Text,
+    Boolean,
+    Comment,
+    // A name that is a component of the library
+    Variable,
+    UnresolvedVariable,
+    // A built in function
+    Function,
+    Punctuation,
+    // Text after the point the script stopped parsing that isn't a keyword, operator or literal
+    Invalid
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    // Byte offsets into the script
+    pub span: Span,
+    pub location: Location
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Tokens {
+    pub tokens: Vec<Token>,
+    pub diagnostics: Vec<Diagnostic>
+}
+
+impl Tokens {
+    // Changes the byte offsets into UTF-16 offsets, for highlighting the script as a JavaScript string
+    pub fn in_utf16(mut self, script: &str) -> Tokens {
+        let index = Utf16Index::new(script);
+        for token in self.tokens.iter_mut() {
+            token.span = index.span(token.span);
+            token.location = index.location(token.location);
+        }
+        for diagnostic in self.diagnostics.iter_mut() {
+            diagnostic.span = diagnostic.span.map(|span| index.span(span));
+            diagnostic.location = diagnostic.location.map(|location| index.location(location));
+        }
+        self
+    }
+}
+
+// The names a script refers to, with whether each one is called as a function
+struct Names<'a> {
+    names: Vec<(&'a str, bool)>
+}
+
+impl<'a> Names<'a> {
+    fn expression(&mut self, expression: &'a Expression) {
+        match expression {
+            Expression::Variable(v) => self.variable(v),
+            Expression::Literal(_) => (),
+            Expression::FunctionCall(call) => self.call(call),
+            Expression::Binary(b) => {
+                self.expression(&b.left);
+                for (_, e) in b.right.iter() {
+                    self.expression(e);
+                }
+            },
+            Expression::Unary(u) => self.expression(&u.expression),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.expression(e)
+        }
+    }
+
+    fn call(&mut self, call: &'a FunctionCall) {
+        self.names.push((call.name, true));
+        for arg in call.args.iter() {
+            self.expression(arg);
+        }
+    }
+
+    fn variable(&mut self, variable: &'a VariableReference) {
+        self.names.push((variable.name, false));
+        for arg in variable.indexer.iter().flat_map(|i| i.args.iter()) {
+            self.expression(arg);
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Spanned<Statement>]) {
+        for statement in statements.iter() {
+            match &statement.node {
+                Statement::Comment(_) => (),
+                Statement::If(s) => {
+                    self.expression(&s.condition);
+                    self.statements(&s.then_body);
+                    self.statements(&s.else_body);
+                },
+                Statement::Loop(LoopStatement::While(s)) => {
+                    self.expression(&s.condition);
+                    self.statements(&s.body);
+                },
+                Statement::Loop(LoopStatement::Repeat(s)) => {
+                    self.variable(&s.dialog);
+                    self.statements(&s.body);
+                },
+                Statement::Return(e) => self.expression(e),
+                Statement::Instruction(Instruction::Display(instruction)) => match instruction {
+                    DisplayInstruction::Ask(name) | DisplayInstruction::Gray(name) | DisplayInstruction::Hide(name)
+                    | DisplayInstruction::Show(name) | DisplayInstruction::Ungray(name) | DisplayInstruction::Require(name)
+                    | DisplayInstruction::Limit(LimitExpression::Variable(name)) => self.names.push((name, false)),
+                    DisplayInstruction::Limit(LimitExpression::Function(call)) => self.call(call),
+                    _ => ()
+                },
+                Statement::Instruction(Instruction::Script(instruction)) => match instruction {
+                    ScriptInstruction::Set(v, e) | ScriptInstruction::Add(v, e) | ScriptInstruction::Default(v, e) => {
+                        self.variable(v);
+                        self.expression(e);
+                    },
+                    ScriptInstruction::Erase(v) | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v)
+                    | ScriptInstruction::Assemble(v) => self.variable(v),
+                    ScriptInstruction::Ascend(name) | ScriptInstruction::Descend(name) => self.names.push((name, false)),
+                    _ => ()
+                }
+            }
+        }
+    }
+}
+
+fn word_kind(word: &str) -> TokenKind {
+    if builtins::is_keyword(word) || builtins::constant(word).is_some() {
+        TokenKind::Keyword
+    } else if builtins::OPERATOR_WORDS.contains(&word) {
+        TokenKind::Operator
+    } else if word == "TRUE" || word == "FALSE" {
+        TokenKind::Boolean
+    } else {
+        TokenKind::Invalid
+    }
+}
+
+// Splits text between the names into tokens the same way the parser reads it. Words that aren't keywords can only be
+// left over when the text wasn't parsed.
+fn lex(text: &str, start: usize, tokens: &mut Vec<(TokenKind, Span)>) {
+    let mut offset = 0;
+    while let Some(c) = text[offset..].chars().next() {
+        let rest = &text[offset..];
+        let (kind, length) = if c.is_whitespace() {
+            offset += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            (TokenKind::Comment, rest.find(['\r', '\n']).unwrap_or(rest.len()))
+        } else if c == '"' {
+            // Quotes are escaped by doubling them, a literal without its closing quote runs to the end of the script
+            let mut end = 1;
+            loop {
+                match rest[end..].find('"') {
+                    Some(i) if rest[end + i..].starts_with("\"\"") => end += i + 2,
+                    Some(i) => break end += i + 1,
+                    None => break end = rest.len()
+                }
+            }
+            (TokenKind::Text, end)
+        } else if c.is_ascii_digit() || c == '.' {
+            match double::<_, (&str, ErrorKind)>(rest) {
+                Ok((remaining, _)) => (TokenKind::Number, rest.len() - remaining.len()),
+                Err(_) => (TokenKind::Invalid, 1)
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            let length = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            (word_kind(&rest[..length]), length)
+        } else if rest.starts_with("<=") || rest.starts_with(">=") || rest.starts_with("!=") {
+            (TokenKind::Operator, 2)
+        } else if "+-*/<>=!".contains(c) {
+            (TokenKind::Operator, 1)
+        } else if "()[],".contains(c) {
+            (TokenKind::Punctuation, 1)
+        } else {
+            (TokenKind::Invalid, c.len_utf8())
+        };
+        tokens.push((kind, Span { start: start + offset, end: start + offset + length }));
+        offset += length;
+    }
+}
+
+// Splits a script into tokens for highlighting. Names are taken from what the parser read, so a name with spaces or
+// keywords in it is one token, and are resolved against the names of the components in the library.
+pub fn tokenize(script: &str, components: &HashSet<String>) -> Tokens {
+    let (parsed_length, ast) = match hd_script_parser::parse(script) {
+        Ok((remaining, ast)) => (script.len() - remaining.len(), Some(ast)),
+        Err(_) => (0, None)
+    };
+
+    let mut names = Names { names: vec![] };
+    if let Some(ast) = &ast {
+        names.statements(&ast.body);
+    }
+    let mut spans: Vec<(Span, TokenKind)> = names
+        .names
+        .into_iter()
+        .filter_map(|(name, called)| {
+            let name = name.trim();
+            let kind = if builtins::constant(name).is_some() {
+                TokenKind::Keyword
+            } else if called && builtins::is_function(name) {
+                TokenKind::Function
+            } else if components.contains(name) {
+                TokenKind::Variable
+            } else {
+                TokenKind::UnresolvedVariable
+            };
+            Span::of(script, name).filter(|s| s.start < s.end).map(|span| (span, kind))
+        })
+        .collect();
+    spans.sort_by_key(|(span, _)| (span.start, span.end));
+    spans.dedup_by_key(|(span, _)| *span);
+
+    let mut tokens = vec![];
+    let mut offset = 0;
+    for (span, kind) in spans {
+        if span.start < offset {
+            continue;
+        }
+        lex(&script[offset..span.start], offset, &mut tokens);
+        tokens.push((kind, span));
+        offset = span.end;
+    }
+    lex(&script[offset..parsed_length.max(offset)], offset, &mut tokens);
+
+    let mut diagnostics = vec![];
+    let remaining = &script[parsed_length.max(offset)..];
+    if !remaining.trim().is_empty() {
+        let start = script.len() - remaining.trim_start().len();
+        let span = Span { start, end: script.len() - (remaining.len() - remaining.trim_end().len()) };
+        diagnostics.push(Diagnostic::error(String::from("The script could not be parsed from here")).at(Some(span)));
+        lex(remaining, script.len() - remaining.len(), &mut tokens);
+    }
+
+    let lines = LineIndex::new(script);
+    let tokens = tokens.into_iter().map(|(kind, span)| Token { kind, span, location: lines.location(span) }).collect();
+    for diagnostic in diagnostics.iter_mut() {
+        diagnostic.location = diagnostic.span.map(|span| lines.location(span));
+    }
+    Tokens { tokens, diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_in_utf16_index_javascript_strings() {
+        let script = "\"«Client Name»\" + Age + Nme";
+        let components: HashSet<String> = vec![String::from("Age")].into_iter().collect();
+
+        let tokens = tokenize(script, &components);
+        let kinds: Vec<(TokenKind, &str)> = tokens.tokens.iter().map(|t| (t.kind, &script[t.span.start..t.span.end])).collect();
+        assert_eq!(kinds, vec![
+            (TokenKind::Text, "\"«Client Name»\""),
+            (TokenKind::Operator, "+"),
+            (TokenKind::Variable, "Age"),
+            (TokenKind::Operator, "+"),
+            (TokenKind::UnresolvedVariable, "Nme")
+        ]);
+
+        let utf16: Vec<u16> = script.encode_utf16().collect();
+        let tokens = tokens.in_utf16(script);
+        let spans: Vec<(usize, usize)> = tokens.tokens.iter().map(|t| (t.span.start, t.span.end)).collect();
+        assert_eq!(spans, vec![(0, 15), (16, 17), (18, 21), (22, 23), (24, 27)]);
+        assert_eq!(String::from_utf16(&utf16[18..21]).unwrap(), "Age");
+        assert_eq!(tokens.tokens[2].location.start.offset, 18);
+        assert_eq!(tokens.tokens[2].location.start.column, 19);
+    }
+}
@@ -0,0 +1,511 @@
+use crate::hd_script_parser::*;
+use crate::builtins;
+use crate::diagnostics::{ Diagnostic, Span };
+use crate::Component;
+
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Type {
+    Text,
+    Number,
+    Date,
+    TrueFalse,
+    MultipleChoice,
+    Dialog,
+    // The DAYS, MONTHS and YEARS that can be added to or subtracted from a date
+    Interval,
+    // Anything the checker can't know the type of, it is compatible with every other type
+    Unknown
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Type::Text => "text",
+            Type::Number => "number",
+            Type::Date => "date",
+            Type::TrueFalse => "true/false",
+            Type::MultipleChoice => "multiple choice",
+            Type::Dialog => "dialog",
+            Type::Interval => "DAYS, MONTHS or YEARS",
+            Type::Unknown => "unknown"
+        })
+    }
+}
+
+impl Type {
+    // Reads the result type of a computation as it is written in the component file, e.g. resultType="trueFalse"
+    pub fn parse(name: &str) -> Option<Type> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(Type::Text),
+            "number" => Some(Type::Number),
+            "date" => Some(Type::Date),
+            "truefalse" | "true/false" => Some(Type::TrueFalse),
+            "multiplechoice" => Some(Type::MultipleChoice),
+            _ => None
+        }
+    }
+
+    // Whether a value of the other type can be used where this type is expected
+    pub fn accepts(self, other: Type) -> bool {
+        self == other || self == Type::Unknown || other == Type::Unknown || (self == Type::MultipleChoice && other == Type::Text)
+    }
+}
+
+fn component_type(component: &Component) -> Type {
+    match component {
+        Component::Text(_) => Type::Text,
+        Component::Number(_) => Type::Number,
+        Component::Date(_) => Type::Date,
+        Component::TrueFalse(_) => Type::TrueFalse,
+        Component::MultipleChoice(_) => Type::MultipleChoice,
+        Component::Dialog { .. } => Type::Dialog,
+        _ => Type::Unknown
+    }
+}
+
+fn binary_type(op: &BinaryOperation, left: Type, right: Type) -> Option<Type> {
+    use Type::*;
+    match (op, left, right) {
+        (BinaryOperation::And, _, _) | (BinaryOperation::Or, _, _) =>
+            Some(TrueFalse).filter(|_| TrueFalse.accepts(left) && TrueFalse.accepts(right)),
+        (BinaryOperation::Add, Date, Interval) | (BinaryOperation::Subtract, Date, Interval) => Some(Date),
+        (BinaryOperation::Add, Unknown, Interval) | (BinaryOperation::Subtract, Unknown, Interval) => Some(Date),
+        (_, _, Interval) | (_, Interval, _) => None,
+        (BinaryOperation::Add, Text, Text) => Some(Text),
+        (BinaryOperation::Add, Unknown, t) | (BinaryOperation::Add, t, Unknown) if t == Text || t == Number || t == Unknown => Some(t),
+        (BinaryOperation::Add, _, _) | (BinaryOperation::Subtract, _, _) | (BinaryOperation::Multiply, _, _) | (BinaryOperation::Divide, _, _) =>
+            Some(Number).filter(|_| Number.accepts(left) && Number.accepts(right)),
+        (BinaryOperation::EQ, _, _) | (BinaryOperation::NE, _, _) =>
+            Some(TrueFalse).filter(|_| left != Dialog && right != Dialog && (left.accepts(right) || right.accepts(left))),
+        (BinaryOperation::Contains, MultipleChoice, _) => Some(TrueFalse).filter(|_| Text.accepts(right)),
+        (BinaryOperation::Contains, _, _) | (BinaryOperation::StartsWith, _, _) | (BinaryOperation::EndsWith, _, _) =>
+            Some(TrueFalse).filter(|_| Text.accepts(left) && Text.accepts(right)),
+        // The ordering comparisons work on numbers, dates and text of the same type
+        _ => Some(TrueFalse).filter(|_| {
+            let ordered = |t: Type| t == Number || t == Date || t == Text || t == Unknown;
+            ordered(left) && ordered(right) && left.accepts(right)
+        })
+    }
+}
+
+struct Checker<'s, 'a> {
+    components: &'s HashMap<String, Component>,
+    scripts: &'s HashMap<&'s str, Script<'a>>,
+    result_types: &'s HashMap<String, Type>,
+    inferred: HashMap<String, Type>,
+    inferring: HashSet<String>,
+    // The script being checked and the span of the statement being checked, used when an expression has no names
+    // to take its span from
+    source: &'a str,
+    statement: Option<Span>,
+    diagnostics: Vec<Diagnostic>
+}
+
+impl<'s, 'a> Checker<'s, 'a> {
+    fn new(components: &'s HashMap<String, Component>, scripts: &'s HashMap<&'s str, Script<'a>>, result_types: &'s HashMap<String, Type>) -> Checker<'s, 'a> {
+        Checker {
+            components,
+            scripts,
+            result_types,
+            inferred: HashMap::new(),
+            inferring: HashSet::new(),
+            source: "",
+            statement: None,
+            diagnostics: vec![]
+        }
+    }
+
+    fn span(&self, slice: &str) -> Option<Span> {
+        Span::of(self.source, slice.trim())
+    }
+
+    // The span of an expression covers the names it uses, literals alone have no span of their own
+    fn expression_span(&self, expression: &Expression) -> Option<Span> {
+        fn join(a: Option<Span>, b: Option<Span>) -> Option<Span> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.join(b)),
+                (a, b) => a.or(b)
+            }
+        }
+
+        match expression {
+            Expression::Variable(v) => {
+                let args = v.indexer.iter().flat_map(|i| i.args.iter());
+                args.fold(self.span(v.name), |span, arg| join(span, self.expression_span(arg)))
+            },
+            Expression::FunctionCall(call) => call.args.iter().fold(self.span(call.name), |span, arg| join(span, self.expression_span(arg))),
+            Expression::Binary(b) => b.right.iter().fold(self.expression_span(&b.left), |span, (_, e)| join(span, self.expression_span(e))),
+            Expression::Unary(u) => self.expression_span(&u.expression),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.expression_span(e),
+            Expression::Literal(_) => None
+        }
+    }
+
+    fn report(&mut self, message: String, span: Option<Span>) {
+        self.diagnostics.push(Diagnostic::error(message).at(span.or(self.statement)));
+    }
+
+    fn expect(&mut self, expected: Type, expression: &Expression, what: &str) {
+        let actual = self.expression(expression);
+        if !expected.accepts(actual) {
+            let span = self.expression_span(expression);
+            self.report(format!("{} should be a {} value but is a {} value", what, expected, actual), span);
+        }
+    }
+
+    fn result_type(&mut self, computation: &str) -> Type {
+        if let Some(t) = self.result_types.get(computation) {
+            return *t;
+        }
+        if let Some(t) = self.inferred.get(computation) {
+            return *t;
+        }
+        let scripts = self.scripts;
+        let script = match scripts.get(computation) {
+            Some(script) if !self.inferring.contains(computation) => script,
+            _ => return Type::Unknown
+        };
+
+        // Problems found while inferring belong to the other computation and are reported when it is checked
+        self.inferring.insert(computation.to_owned());
+        let saved = (self.source, self.statement, std::mem::take(&mut self.diagnostics));
+        self.source = script.source;
+
+        let mut returns = vec![];
+        self.collect_returns(&script.body, &mut returns);
+
+        self.source = saved.0;
+        self.statement = saved.1;
+        self.diagnostics = saved.2;
+        self.inferring.remove(computation);
+
+        let inferred = returns.into_iter().find(|t| *t != Type::Unknown).unwrap_or(Type::Unknown);
+        self.inferred.insert(computation.to_owned(), inferred);
+        inferred
+    }
+
+    fn collect_returns(&mut self, statements: &[Spanned<Statement>], returns: &mut Vec<Type>) {
+        for statement in statements.iter() {
+            match &statement.node {
+                Statement::If(s) => {
+                    self.collect_returns(&s.then_body, returns);
+                    self.collect_returns(&s.else_body, returns);
+                },
+                Statement::Loop(LoopStatement::While(s)) => self.collect_returns(&s.body, returns),
+                Statement::Loop(LoopStatement::Repeat(s)) => self.collect_returns(&s.body, returns),
+                Statement::Return(e) => match self.expression(e) {
+                    Type::Dialog => (),
+                    t => returns.push(t)
+                },
+                _ => ()
+            }
+        }
+    }
+
+    fn indexer(&mut self, variable: &VariableReference) {
+        let indexer = match &variable.indexer {
+            Some(indexer) => indexer,
+            None => return
+        };
+        for arg in indexer.args.iter() {
+            self.expect(Type::Number, arg, "An index");
+        }
+
+        // Whether the number of indexes fits how deeply the variable is repeated is checked by repeats
+        let name = variable.name.trim();
+        if let Some(Component::Computation(_)) | Some(Component::Dialog { .. }) = self.components.get(name) {
+            let span = self.span(variable.name);
+            self.report(format!("{} is not a variable and can't be indexed", name), span);
+        }
+    }
+
+    fn variable(&mut self, variable: &VariableReference) -> Type {
+        self.indexer(variable);
+        let name = variable.name.trim();
+        if let Some(constant) = builtins::constant(name) {
+            return constant;
+        }
+        match self.components.get(name) {
+            Some(Component::Computation(_)) => self.result_type(name),
+            Some(component) => component_type(component),
+            None => Type::Unknown
+        }
+    }
+
+    fn function(&mut self, call: &FunctionCall) -> Type {
+        let name = call.name.trim();
+        let (parameters, result) = match builtins::function_named(name) {
+            Some(function) => (function.parameters, function.result),
+            None => {
+                for arg in call.args.iter() {
+                    self.expression(arg);
+                }
+                return match self.components.get(name) {
+                    Some(Component::Computation(_)) => self.result_type(name),
+                    _ => Type::Unknown
+                };
+            }
+        };
+
+        if parameters.len() != call.args.len() {
+            let span = self.span(call.name);
+            self.report(format!("{} expects {} argument(s) but was given {}", name, parameters.len(), call.args.len()), span);
+        }
+        for (i, arg) in call.args.iter().enumerate() {
+            match parameters.get(i) {
+                Some(expected) => self.expect(*expected, arg, &format!("Argument {} of {}", i + 1, name)),
+                None => {
+                    self.expression(arg);
+                }
+            }
+        }
+        result
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Type {
+        match expression {
+            Expression::Literal(LiteralExpression::Number(_)) => Type::Number,
+            Expression::Literal(LiteralExpression::Text(_)) => Type::Text,
+            Expression::Literal(LiteralExpression::Boolean(_)) => Type::TrueFalse,
+            Expression::Literal(_) => Type::Unknown,
+            Expression::Variable(v) => self.variable(v),
+            Expression::FunctionCall(call) => self.function(call),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => {
+                self.expect(Type::Number, e, "The number of DAYS, MONTHS or YEARS");
+                Type::Interval
+            },
+            Expression::Unary(u) => {
+                let (expected, what) = match u.operation {
+                    UnaryOperation::Not => (Type::TrueFalse, "The value of NOT"),
+                    UnaryOperation::Negate => (Type::Number, "A negated value")
+                };
+                self.expect(expected, &u.expression, what);
+                expected
+            },
+            Expression::Binary(b) => {
+                let mut left = self.expression(&b.left);
+                let mut span = self.expression_span(&b.left);
+                for (op, e) in b.right.iter() {
+                    let right = self.expression(e);
+                    span = match (span, self.expression_span(e)) {
+                        (Some(a), Some(b)) => Some(a.join(b)),
+                        (a, b) => a.or(b)
+                    };
+                    left = match binary_type(op, left, right) {
+                        Some(t) => t,
+                        None => {
+                            self.report(format!("{} can't be used with {} and {} values", op, left, right), span);
+                            match op {
+                                BinaryOperation::Add | BinaryOperation::Subtract | BinaryOperation::Multiply | BinaryOperation::Divide => Type::Unknown,
+                                _ => Type::TrueFalse
+                            }
+                        }
+                    };
+                }
+                left
+            }
+        }
+    }
+
+    // Checks a value can be written to the variable and returns the variable's type
+    fn target(&mut self, variable: &VariableReference, instruction: &str) -> Type {
+        let t = self.variable(variable);
+        let name = variable.name.trim();
+        match self.components.get(name) {
+            Some(Component::Computation(_)) | Some(Component::Dialog { .. }) => {
+                let span = self.span(variable.name);
+                self.report(format!("{} can't be used with {} as it is not a variable", instruction, name), span);
+                Type::Unknown
+            },
+            _ => t
+        }
+    }
+
+    fn instruction(&mut self, instruction: &ScriptInstruction) {
+        match instruction {
+            ScriptInstruction::Set(variable, e) | ScriptInstruction::Default(variable, e) => {
+                let keyword = if let ScriptInstruction::Set(..) = instruction { "SET" } else { "DEFAULT" };
+                let t = self.target(variable, keyword);
+                self.expect(t, e, &format!("The value {} to {}", keyword, variable.name.trim()));
+            },
+            ScriptInstruction::Add(variable, e) => {
+                let t = self.target(variable, "ADD");
+                if t != Type::Text && t != Type::MultipleChoice && t != Type::Unknown {
+                    let span = self.span(variable.name);
+                    self.report(format!("ADD needs a text or multiple choice variable but {} is a {} variable", variable.name.trim(), t), span);
+                }
+                self.expect(Type::Text, e, "The value to ADD");
+            },
+            ScriptInstruction::Increment(variable) | ScriptInstruction::Decrement(variable) => {
+                let t = self.target(variable, "INCREMENT and DECREMENT");
+                if !Type::Number.accepts(t) {
+                    let span = self.span(variable.name);
+                    self.report(format!("INCREMENT and DECREMENT need a number variable but {} is a {} variable", variable.name.trim(), t), span);
+                }
+            },
+            ScriptInstruction::Erase(variable) | ScriptInstruction::Assemble(variable) => {
+                self.variable(variable);
+            },
+            _ => ()
+        }
+    }
+
+    // The result type is the computation's declared type or the type of the first value it returns
+    fn statements(&mut self, statements: &[Spanned<Statement>], result: &mut Option<(Type, Option<Span>)>, computation: bool) {
+        for statement in statements.iter() {
+            self.statement = self.span(statement.source);
+            match &statement.node {
+                Statement::Comment(_) => (),
+                Statement::If(s) => {
+                    self.expect(Type::TrueFalse, &s.condition, "The IF condition");
+                    self.statements(&s.then_body, result, computation);
+                    self.statements(&s.else_body, result, computation);
+                },
+                Statement::Loop(LoopStatement::While(s)) => {
+                    self.expect(Type::TrueFalse, &s.condition, "The WHILE condition");
+                    self.statements(&s.body, result, computation);
+                },
+                Statement::Loop(LoopStatement::Repeat(s)) => {
+                    let t = self.variable(&s.dialog);
+                    if !Type::Dialog.accepts(t) {
+                        let span = self.span(s.dialog.name);
+                        self.report(format!("REPEAT needs a dialog but {} is a {} variable", s.dialog.name.trim(), t), span);
+                    }
+                    self.statements(&s.body, result, computation);
+                },
+                Statement::Instruction(Instruction::Script(instruction)) => self.instruction(instruction),
+                Statement::Instruction(Instruction::Display(_)) => (),
+                Statement::Return(e) => {
+                    let t = self.expression(e);
+                    // Naming a dialog on its own asks it rather than returning a value
+                    if !computation || t == Type::Dialog || t == Type::Unknown {
+                        continue;
+                    }
+                    let span = self.expression_span(e).or(self.statement);
+                    match *result {
+                        None => *result = Some((t, span)),
+                        Some((expected, _)) if expected.accepts(t) => (),
+                        Some((expected, _)) => self.report(format!("The computation should return a {} value but returns a {} value here", expected, t), span)
+                    }
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, name: &str, script: &Script<'a>, computation: bool) -> Vec<Diagnostic> {
+        self.source = script.source;
+        self.statement = None;
+
+        let mut result = if computation { self.result_types.get(name).map(|t| (*t, None)) } else { None };
+        self.statements(&script.body, &mut result, computation);
+
+        std::mem::take(&mut self.diagnostics).into_iter().map(|d| d.for_component(name)).collect()
+    }
+}
+
+// Type checks the scripts of the computations and dialogs, the spans of the diagnostics are offsets into each script
+pub fn check(
+    components: &HashMap<String, Component>,
+    computations: &HashMap<&str, Script>,
+    dialogs: &HashMap<&str, Script>,
+    result_types: &HashMap<String, Type>
+) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(components, computations, result_types);
+
+    let mut diagnostics = vec![];
+    let mut names: Vec<_> = computations.keys().map(|n| (*n, true)).chain(dialogs.keys().map(|n| (*n, false))).collect();
+    names.sort();
+
+    for (name, computation) in names {
+        let script = if computation { &computations[name] } else { &dialogs[name] };
+        diagnostics.extend(checker.check(name, script, computation));
+    }
+    diagnostics
+}
+
+// The type of every component, computations without a result type take the type of what they return
+pub fn types(
+    components: &HashMap<String, Component>,
+    computations: &HashMap<&str, Script>,
+    result_types: &HashMap<String, Type>
+) -> HashMap<String, Type> {
+    let mut checker = Checker::new(components, computations, result_types);
+    components
+        .iter()
+        .map(|(name, component)| match component {
+            Component::Computation(_) => (name.to_owned(), checker.result_type(name)),
+            _ => (name.to_owned(), component_type(component))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hd_script_parser;
+
+    // The message of each error in the Test computation with the part of the script it points at
+    fn errors(script: &str, result_type: Option<Type>) -> Vec<(String, &str)> {
+        let (_, ast) = hd_script_parser::parse(script).unwrap();
+        let computations: HashMap<&str, Script> = vec![("Test", ast)].into_iter().collect();
+        let components = vec![
+            (String::from("Amount"), Component::Number(HashSet::new())),
+            (String::from("Signed"), Component::Date(HashSet::new())),
+            (String::from("Client"), Component::Text(HashSet::new())),
+            (String::from("Test"), Component::Computation(HashSet::new()))
+        ].into_iter().collect();
+        let result_types = result_type.into_iter().map(|t| (String::from("Test"), t)).collect();
+        check(&components, &computations, &HashMap::new(), &result_types)
+            .into_iter()
+            .map(|d| (d.message, d.span.map_or("", |span| &script[span.start..span.end])))
+            .collect()
+    }
+
+    #[test]
+    fn text_set_to_a_number_variable() {
+        assert_eq!(errors("SET Amount TO \"abc\"", None), vec![
+            (String::from("The value SET to Amount should be a number value but is a text value"), "SET Amount TO \"abc\"")
+        ]);
+    }
+
+    #[test]
+    fn a_date_compared_with_a_number() {
+        assert_eq!(errors("IF Signed > 5\n\"a\"\nEND IF", None), vec![
+            (String::from("> can't be used with date and number values"), "Signed")
+        ]);
+    }
+
+    #[test]
+    fn contains_on_a_number() {
+        assert_eq!(errors("IF Amount CONTAINS \"1\"\n\"a\"\nEND IF", None), vec![
+            (String::from("CONTAINS can't be used with number and text values"), "Amount")
+        ]);
+    }
+
+    #[test]
+    fn a_return_that_does_not_match_the_result_type() {
+        assert_eq!(errors("IF Amount > 5\nClient\nELSE\nAmount\nEND IF", Some(Type::Text)), vec![
+            (String::from("The computation should return a text value but returns a number value here"), "Amount")
+        ]);
+        assert_eq!(errors("IF Amount > 5\nClient\nELSE\nAmount\nEND IF", None), vec![
+            (String::from("The computation should return a text value but returns a number value here"), "Amount")
+        ]);
+    }
+
+    #[test]
+    fn only_variables_can_be_indexed() {
+        assert_eq!(errors("SET Amount TO Test[1]", Some(Type::Number)), vec![
+            (String::from("Test is not a variable and can't be indexed"), "Test")
+        ]);
+    }
+
+    #[test]
+    fn valid_scripts_have_no_errors() {
+        let script = "IF Amount > 5 AND Client CONTAINS \"Ltd\"\nSET Signed TO Signed + 3 DAYS\nINCREMENT Amount\n\"«Client»\"\nELSE IF Signed < TODAY\n\"late\"\nEND IF";
+        assert_eq!(errors(script, Some(Type::Text)), vec![]);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::hd_script_parser::{ self, * };
+use crate::builtins;
+use crate::component_library::ComponentLibrary;
+use crate::diagnostics::{ Diagnostic, LineIndex, Location, Span };
+use crate::merge_field_parser;
+use crate::xml_parser::{ Node, Text, XmlElement };
+use crate::{ multiple_choice_name, script_text, text_span };
+
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum UseKind {
+    // Listed in the hd:contents of a dialog
+    DialogContents,
+    // A script reads its value
+    Read,
+    // A script sets, adds to, defaults, erases, increments or decrements it
+    Write,
+    Ask,
+    // A script grays, ungrays, shows, hides, requires or limits it
+    Display,
+    // A script repeats the dialog
+    Repeat,
+    // In the options of a multiple choice variable or the script of an option table
+    OptionTable,
+    // Merged into a prompt, resource or other text of a component, or into a text literal in a script
+    MergeField
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Use {
+    // The component the use is in
+    pub component: String,
+    pub kind: UseKind,
+    // Byte offsets into the component file
+    pub span: Option<Span>,
+    pub location: Option<Location>,
+    // Byte offsets into the component's script, for uses in scripts
+    pub script_span: Option<Span>
+}
+
+// The names a script refers to with how each one is used
+struct ScriptUses<'a> {
+    names: Vec<(&'a str, UseKind)>
+}
+
+impl<'a> ScriptUses<'a> {
+    fn expression(&mut self, expression: &'a Expression) {
+        match expression {
+            Expression::Variable(v) => self.variable(v, UseKind::Read),
+            Expression::Literal(LiteralExpression::Text(text)) => {
+                for field in merge_field_parser::parse(text) {
+                    self.names.push((field.name, UseKind::MergeField));
+                }
+            },
+            Expression::Literal(_) => (),
+            Expression::FunctionCall(call) => self.call(call),
+            Expression::Binary(b) => {
+                self.expression(&b.left);
+                for (_, e) in b.right.iter() {
+                    self.expression(e);
+                }
+            },
+            Expression::Unary(u) => self.expression(&u.expression),
+            Expression::Days(e) | Expression::Months(e) | Expression::Years(e) => self.expression(e)
+        }
+    }
+
+    // Computations can be called like functions too
+    fn call(&mut self, call: &'a FunctionCall) {
+        if !builtins::is_function(call.name.trim()) {
+            self.names.push((call.name, UseKind::Read));
+        }
+        for arg in call.args.iter() {
+            self.expression(arg);
+        }
+    }
+
+    fn variable(&mut self, variable: &'a VariableReference, kind: UseKind) {
+        self.names.push((variable.name, kind));
+        for arg in variable.indexer.iter().flat_map(|i| i.args.iter()) {
+            self.expression(arg);
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Spanned<Statement>]) {
+        for statement in statements.iter() {
+            match &statement.node {
+                Statement::Comment(_) => (),
+                Statement::If(s) => {
+                    self.expression(&s.condition);
+                    self.statements(&s.then_body);
+                    self.statements(&s.else_body);
+                },
+                Statement::Loop(LoopStatement::While(s)) => {
+                    self.expression(&s.condition);
+                    self.statements(&s.body);
+                },
+                Statement::Loop(LoopStatement::Repeat(s)) => {
+                    self.variable(&s.dialog, UseKind::Repeat);
+                    self.statements(&s.body);
+                },
+                Statement::Return(e) => self.expression(e),
+                Statement::Instruction(Instruction::Display(instruction)) => match instruction {
+                    DisplayInstruction::Ask(name) => self.names.push((name, UseKind::Ask)),
+                    DisplayInstruction::Gray(name) | DisplayInstruction::Hide(name) | DisplayInstruction::Show(name)
+                    | DisplayInstruction::Ungray(name) | DisplayInstruction::Require(name)
+                    | DisplayInstruction::Limit(LimitExpression::Variable(name)) => self.names.push((name, UseKind::Display)),
+                    DisplayInstruction::Limit(LimitExpression::Function(call)) => self.call(call),
+                    _ => ()
+                },
+                Statement::Instruction(Instruction::Script(instruction)) => match instruction {
+                    ScriptInstruction::Set(v, e) | ScriptInstruction::Add(v, e) | ScriptInstruction::Default(v, e) => {
+                        self.variable(v, UseKind::Write);
+                        self.expression(e);
+                    },
+                    ScriptInstruction::Erase(v) | ScriptInstruction::Increment(v) | ScriptInstruction::Decrement(v) => {
+                        self.variable(v, UseKind::Write)
+                    },
+                    ScriptInstruction::Assemble(v) => self.variable(v, UseKind::Read),
+                    ScriptInstruction::Ascend(name) | ScriptInstruction::Descend(name) => self.names.push((name, UseKind::Read)),
+                    _ => ()
+                }
+            }
+        }
+    }
+}
+
+// Every use of every component in a library, found once so each query is a lookup
+#[derive(Default)]
+pub struct Index {
+    // The uses of each component by the name scripts use for it, in the order they are in the file
+    uses: HashMap<String, Vec<Use>>,
+    // Why the library couldn't be read, nothing is used then
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// Collects the uses of the components one at a time, so they can be indexed as they are read
+pub struct Builder<'c> {
+    component: &'c str,
+    lines: LineIndex<'c>,
+    uses: HashMap<String, Vec<Use>>
+}
+
+impl<'c> Builder<'c> {
+    pub fn new(component: &'c str) -> Builder<'c> {
+        Builder { component, lines: LineIndex::new(component), uses: HashMap::new() }
+    }
+
+    fn add(&mut self, name: &str, user: &str, kind: UseKind, span: Option<Span>, script_span: Option<Span>) {
+        let location = span.map(|span| self.lines.location(span));
+        let used = Use { component: user.to_owned(), kind, span, location, script_span };
+        self.uses.entry(multiple_choice_name(name.trim())).or_default().push(used);
+    }
+
+    fn script(&mut self, library: &ComponentLibrary, user: &str, element: &XmlElement, option_table: bool) {
+        let text = match script_text(library, element) {
+            Some(text) => text,
+            None => return
+        };
+        let ast = match hd_script_parser::parse(&text.value) {
+            Ok((_, ast)) => ast,
+            Err(_) => return
+        };
+
+        let mut uses = ScriptUses { names: vec![] };
+        uses.statements(&ast.body);
+        // Text literals are unescaped into strings of their own, so their merge fields are found in the script in order
+        let mut fields: Vec<&str> = merge_field_parser::parse(&text.value).into_iter().map(|f| f.name.trim()).collect();
+        for (name, kind) in uses.names {
+            let kind = if option_table { UseKind::OptionTable } else { kind };
+            let name = name.trim();
+            let script_span = Span::of(&text.value, name).or_else(|| {
+                let i = fields.iter().position(|field| *field == name)?;
+                Span::of(&text.value, fields.remove(i))
+            });
+            let span = script_span.and_then(|span| text_span(self.component, text, span));
+            self.add(name, user, kind, span, script_span);
+        }
+    }
+
+    fn merge_fields(&mut self, text: &Text, user: &str, kind: UseKind) {
+        for field in merge_field_parser::parse(&text.value) {
+            let span = Span::of(&text.value, field.name).and_then(|span| text_span(self.component, text, span));
+            self.add(field.name, user, kind, span, None);
+        }
+    }
+
+    // Merge fields in the text of the component, scripts are read separately
+    fn texts(&mut self, library: &ComponentLibrary, user: &str, element: &XmlElement, kind: UseKind) {
+        for child in element.children.iter() {
+            match child {
+                Node::XmlElement(xe) if library.is(xe, "script") => (),
+                Node::XmlElement(xe) if library.is(xe, "options") => self.texts(library, user, xe, UseKind::OptionTable),
+                Node::XmlElement(xe) => self.texts(library, user, xe, kind),
+                Node::Value(text) => self.merge_fields(text, user, kind),
+                Node::Comment(_) | Node::ProcessingInstruction(..) => ()
+            }
+        }
+    }
+
+    fn contents(&mut self, library: &ComponentLibrary, user: &str, element: &XmlElement) {
+        let contents = match library.child(element, "contents") {
+            Some(contents) => contents,
+            None => return
+        };
+        for item in library.children(contents) {
+            if let Some(attribute) = item.attribute("name") {
+                let span = Span::of(self.component, attribute.raw_value);
+                self.add(&item.attributes["name"], user, UseKind::DialogContents, span, None);
+            }
+        }
+    }
+}
+
+impl<'c> Builder<'c> {
+    // Adds the uses in a component of the library
+    pub fn add_component(&mut self, library: &ComponentLibrary, name: &str, element: &XmlElement) {
+        let user = multiple_choice_name(name);
+        self.contents(library, &user, element);
+        self.script(library, &user, element, name.ends_with("_OptionTable"));
+        self.texts(library, &user, element, UseKind::MergeField);
+    }
+
+    pub fn finish(self) -> Index {
+        let mut uses = self.uses;
+        for list in uses.values_mut() {
+            list.sort_by_key(|u| u.span);
+        }
+        Index { uses, diagnostics: vec![] }
+    }
+}
+
+impl Index {
+    pub fn unreadable(diagnostic: Diagnostic) -> Index {
+        Index { uses: HashMap::new(), diagnostics: vec![diagnostic] }
+    }
+
+    // Multiple choice variables can be asked for with or without their suffix
+    pub fn uses(&self, name: &str) -> &[Use] {
+        self.uses.get(&multiple_choice_name(name)).map_or(&[], |uses| uses.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ analyze_and_index, component_library, index_uses, xml_reader };
+
+    const LIBRARY: &str = "<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:components>\n<hd:text name=\"Client Name\"/>\n<hd:number name=\"Age\"/>\n<hd:computation name=\"Double\"><hd:script>Age * 2</hd:script></hd:computation>\n<hd:computation name=\"Greeting\"><hd:script>\"Dear «Client Name», \"\"«Age:9»\"\"\" + FORMAT(Double(Age), \"9\") + \"«IF Age»\"</hd:script></hd:computation>\n</hd:components>\n</hd:componentLibrary>";
+
+    fn index() -> Index {
+        index_uses(LIBRARY)
+    }
+
+    // The component, kind and text of each use
+    fn located(uses: &[Use]) -> Vec<(&str, UseKind, &'static str)> {
+        uses.iter().map(|u| (u.component.as_str(), u.kind, u.span.map_or("", |span| &LIBRARY[span.start..span.end]))).collect()
+    }
+
+    #[test]
+    fn computations_called_as_functions_are_uses() {
+        let index = index();
+        assert_eq!(located(index.uses("Double")), vec![("Greeting", UseKind::Read, "Double")]);
+        assert!(index.uses("FORMAT").is_empty());
+    }
+
+    #[test]
+    fn merge_fields_in_text_literals_are_uses() {
+        let index = index();
+        assert_eq!(located(index.uses("Client Name")), vec![("Greeting", UseKind::MergeField, "Client Name")]);
+        let age = located(index.uses("Age"));
+        assert_eq!(age.len(), 3);
+        assert_eq!(age[0], ("Double", UseKind::Read, "Age"));
+        assert_eq!(age[1], ("Greeting", UseKind::MergeField, "Age"));
+        assert_eq!(age[2], ("Greeting", UseKind::Read, "Age"));
+    }
+
+    #[test]
+    fn indexing_the_whole_tree_finds_the_same_uses_as_streaming() {
+        let root = xml_reader::read_tree(LIBRARY).unwrap();
+        let library = component_library::load(&root);
+        let mut builder = Builder::new(LIBRARY);
+        for c in library.components.iter() {
+            builder.add_component(&library, c.name, c.element);
+        }
+        let tree = builder.finish();
+
+        let (_, streamed) = analyze_and_index(LIBRARY);
+        for name in ["Client Name", "Age", "Double", "Greeting"].iter() {
+            assert_eq!(streamed.uses(name), tree.uses(name));
+            assert_eq!(index().uses(name), tree.uses(name));
+        }
+    }
+
+    #[test]
+    fn an_unreadable_library_is_reported_rather_than_indexed_as_empty() {
+        let index = index_uses("<hd:componentLibrary>");
+        let messages: Vec<&str> = index.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["The component file is not valid XML at line 1, column 22: The document ends before </hd:componentLibrary>"]);
+    }
+}
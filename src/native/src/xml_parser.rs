@@ -2,13 +2,16 @@ use nom::{
   IResult,
   multi::{ many_till, fold_many0 },
   branch::alt,
-  combinator::{ map, opt, value, peek, not },
-  bytes::complete::{ take_while, tag, take },
-  character::complete::{ anychar, multispace0 },
-  sequence::{ separated_pair, preceded, tuple, delimited }
+  combinator::{ map, map_opt, opt, value, peek, not },
+  bytes::complete::{ take_while, take_while1, take_until, tag, take },
+  character::complete::{ anychar, multispace0, digit1 },
+  sequence::{ preceded, tuple, delimited }
 };
   
+use crate::diagnostics::{ LineIndex, Location };
+
 use std::{
+  borrow::Cow,
   iter::{ FromIterator, IntoIterator },
   collections::HashMap
 };
@@ -16,53 +19,150 @@ use std::{
 #[derive(Debug, PartialEq)]
 pub enum Node<'a> {
   XmlElement(XmlElement<'a>),
-  Value(String)
+  Value(Text<'a>),
+  // The text between <!-- and -->
+  Comment(&'a str),
+  // The target and data of a processing instruction, <?target data?>
+  ProcessingInstruction(&'a str, &'a str)
 }
 
 impl Node<'_> {
   pub fn as_value(&self) -> &str {
     match self {
-      Node::Value(value) => value,
+      Node::Value(text) => &text.value,
       _ => "undefined"
     }
   }
-  
-  pub fn as_element(&self) -> &XmlElement {
-    match self {
-      Node::XmlElement(e) => e,
-      _ => panic!("Node is not an element")
-    }
-  }
+}
+
+#[derive(Debug, PartialEq, Default, Clone)]
+pub struct Text<'a> {
+  // The text with CDATA sections read as they were written and everything else unescaped
+  pub value: String,
+  // The text as it was written in the document
+  pub source: &'a str,
+  pub location: Location
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attribute<'a> {
+  pub name: &'a str,
+  // The value as it was written, still escaped
+  pub raw_value: &'a str,
+  // From the start of the name to the closing quote
+  pub source: &'a str,
+  pub location: Location
 }
   
 #[derive(Debug, PartialEq)]
 pub struct XmlElement<'a> {
   pub name: &'a str,
   pub attributes: HashMap<&'a str, String>,
-  pub children: Vec<Node<'a>>
+  pub children: Vec<Node<'a>>,
+  // The text the element was read from, from its start tag to its end tag
+  pub source: &'a str,
+  // The text between the start and end tags, still escaped
+  pub content: &'a str,
+  // The attributes in the order they were written, with their values still escaped
+  pub raw_attributes: Vec<Attribute<'a>>,
+  // The URI the element's prefix, or the default namespace when it has none, is bound to by the xmlns declarations
+  // in scope
+  pub namespace: Option<String>,
+  pub location: Location
+}
+
+impl<'a> XmlElement<'a> {
+  // The name without its prefix, e.g. script for hd:script
+  pub fn local_name(&self) -> &'a str {
+    match self.name.find(':') {
+      Some(i) => &self.name[i + 1..],
+      None => self.name
+    }
+  }
+
+  // The prefix namespace declarations bind, e.g. hd for hd:script, and empty for the default namespace
+  fn prefix(&self) -> &'a str {
+    match self.name.find(':') {
+      Some(i) => &self.name[..i],
+      None => ""
+    }
+  }
+
+  pub fn attribute(&self, name: &str) -> Option<&Attribute<'a>> {
+    self.raw_attributes.iter().find(|a| a.name == name)
+  }
+
+  // The text content of the element, ignoring any child elements
+  pub fn text(&self) -> String {
+    self.children.iter().fold(String::new(), |mut s, n| {
+      if let Node::Value(text) = n {
+        s.push_str(&text.value);
+      }
+      s
+    })
+  }
 }
   
-fn unescape_xml_literal(bad_char: char) -> impl Fn(&str) -> IResult<&str, &str> {
+// A character reference, &#65; or &#x41;, once the & has been read
+fn read_character_reference(input: &str) -> IResult<&str, Cow<'_, str>> {
+    map_opt(
+      delimited(
+        tag("#"),
+        alt((
+          map(preceded(tag("x"), take_while1(|c: char| c.is_ascii_hexdigit())), |hex| u32::from_str_radix(hex, 16).ok()),
+          map(digit1, |decimal: &str| decimal.parse().ok())
+        )),
+        tag(";")
+      ),
+      |code: Option<u32>| code.and_then(std::char::from_u32).map(|c| Cow::Owned(c.to_string()))
+    )(input)
+}
+
+fn unescape_xml_literal(bad_char: char) -> impl Fn(&str) -> IResult<&str, Cow<'_, str>> {
     move |input| {
         let _ = peek(not(nom::character::complete::char(bad_char)))(input)?;
         let (input, ch) = take(1usize)(input)?;
 
         if ch == "&" {
             alt((
-                value("<", tag("lt;")),
-                value(">", tag("gt;")),
-                value("\"", tag("quot;")),
-                value("'", tag("apos;")),
-                value("&", tag("amp;")),
-                value("\r", tag("#xD;")),
-                value("\n", tag("#xA;"))
+                value(Cow::Borrowed("<"), tag("lt;")),
+                value(Cow::Borrowed(">"), tag("gt;")),
+                value(Cow::Borrowed("\""), tag("quot;")),
+                value(Cow::Borrowed("'"), tag("apos;")),
+                value(Cow::Borrowed("&"), tag("amp;")),
+                read_character_reference
             ))(input)
         } else {
-            Ok((input, ch))
+            Ok((input, Cow::Borrowed(ch)))
         }
     }
 }
 
+fn read_cdata(input: &str) -> IResult<&str, &str> {
+  delimited(tag("<![CDATA["), take_until("]]>"), tag("]]>"))(input)
+}
+
+fn read_comment(input: &str) -> IResult<&str, &str> {
+  delimited(tag("<!--"), take_until("-->"), tag("-->"))(input)
+}
+
+fn read_processing_instruction(input: &str) -> IResult<&str, (&str, &str)> {
+  let (input, body) = delimited(tag("<?"), take_until("?>"), tag("?>"))(input)?;
+  let target = body.split(|c: char| c.is_whitespace()).next().unwrap_or_default();
+  Ok((input, (target, body[target.len()..].trim_start())))
+}
+
+// Comments and processing instructions, which can come before and after the root element as well as inside it
+fn read_misc(input: &str) -> IResult<&str, Node> {
+  preceded(
+    multispace0,
+    alt((
+      map(read_comment, Node::Comment),
+      map(read_processing_instruction, |(target, data)| Node::ProcessingInstruction(target, data))
+    ))
+  )(input)
+}
+
 fn read_element_start(input: &str) -> IResult<&str, ()> { 
   value((), preceded(multispace0, tag("<")))(input)
 }
@@ -78,50 +178,70 @@ fn read_element_end(input: &str) -> IResult<&str, bool> {
   ))(input)
 }
 
-fn read_attribute(input: &str) -> IResult<&str, (&str, String)> {
-  let (input, (attr_name, attr_value)) =
-    separated_pair(
-      take_while(|c| c != '='),
-      tag("="),
-      delimited(tag("\""), fold_many0(unescape_xml_literal('"'), String::new(), |mut s: String, r| { s.push_str(r); s }), tag("\""))
-    )(input)?;
+fn read_attribute(input: &str) -> IResult<&str, (String, Attribute)> {
+  let start = input;
+  let (input, attr_name) = take_while(|c| c != '=')(input)?;
+  let (raw_value, _) = tag("=\"")(input)?;
+  let (input, attr_value) = fold_many0(unescape_xml_literal('"'), String::new(), |mut s: String, r| { s.push_str(&r); s })(raw_value)?;
+  let raw_value = &raw_value[..raw_value.len() - input.len()];
+  let (input, _) = tag("\"")(input)?;
 
-  Ok((input, (attr_name, attr_value)))
+  let attribute = Attribute { name: attr_name, raw_value, source: consumed(start, input), location: Location::default() };
+  Ok((input, (attr_value, attribute)))
 }
 
-fn read_text_element(input: &str) -> IResult<&str, String> {
-  fold_many0(unescape_xml_literal('<'), String::new(), |mut s: String, r| { s.push_str(r); s } )(input)
+fn read_text_element(input: &str) -> IResult<&str, Text> {
+  let (rest, value) = fold_many0(
+    alt((map(read_cdata, Cow::Borrowed), unescape_xml_literal('<'))),
+    String::new(),
+    |mut s: String, r| { s.push_str(&r); s }
+  )(input)?;
+  Ok((rest, Text { value, source: consumed(input, rest), location: Location::default() }))
+}
+
+// The part of the input a parser consumed, given the input before and after it ran
+fn consumed<'a>(before: &'a str, after: &'a str) -> &'a str {
+  &before[..before.len() - after.len()]
 }
 
 fn read_element(input: &str) -> IResult<&str, XmlElement> {
+  let element_start = input.trim_start_matches(|c| c == ' ' || c == '\t' || c == '\r' || c == '\n');
   let (input, (name, (attributes, is_closed))) =
     tuple((
       preceded(read_element_start, read_element_name),
       many_till(preceded(multispace0, read_attribute), preceded(multispace0, read_element_end))
     ))
     (input)?;
+
+  let (values, raw_attributes): (Vec<String>, Vec<Attribute>) = attributes.into_iter().unzip();
+  let attributes = HashMap::from_iter(raw_attributes.iter().map(|a| a.name).zip(values));
   
   match is_closed {
     true =>
       // The element is self closing, therefore it cannot have children
-      Ok((input, XmlElement { name: name, attributes: HashMap::from_iter(attributes.into_iter()), children: vec![] })),
+      Ok((input, XmlElement { name: name, attributes, children: vec![], source: consumed(element_start, input), content: "", raw_attributes, namespace: None, location: Location::default() })),
     false => {
       // This element is still open, so it could have children
+      let content_start = input;
+      let end_tag = format!("</{}>", name);
       let (input, (children, _)) = many_till(
         map(
           opt(alt((
+              read_misc,
               map(read_element, Node::XmlElement),
               map(read_text_element, Node::Value)
           ))), 
           |r| match r {
-              Option::None => Node::Value(String::from("")),
+              Option::None => Node::Value(Text::default()),
               Option::Some(a) => a
           }
         ), 
-        preceded(multispace0, tag(format!("</{}>", name).as_str()))
+        preceded(multispace0, tag(end_tag.as_str()))
       )(input)?;
-      
-      Ok((input, XmlElement { name: name, attributes: HashMap::from_iter(attributes.into_iter()), children: children }))
+
+      let content = consumed(content_start, input);
+      let content = &content[..content.len() - end_tag.len()];
+      Ok((input, XmlElement { name: name, attributes, children: children, source: consumed(element_start, input), content, raw_attributes, namespace: None, location: Location::default() }))
     }
   }
 }
@@ -131,13 +251,130 @@ fn read_xml_declaration(input: &str) -> IResult<&str, ()> {
       preceded(
           opt(tag("\u{feff}")),
           tuple((
-              tag("<?"),
+              tag("<?xml"),
               many_till(anychar, tag("?>"))
           ))
       )(input)?;
   Ok((input, ()))
 }
 
-pub fn parse(input: &str) -> IResult<&str, XmlElement> {
-  preceded(opt(read_xml_declaration), read_element)(input)
-}
\ No newline at end of file
+fn read_doctype(input: &str) -> IResult<&str, &str> {
+  preceded(multispace0, delimited(tag("<!DOCTYPE"), take_until(">"), tag(">")))(input)
+}
+
+// The comments, processing instructions and document type declaration between the XML declaration and the root element
+fn read_prolog(input: &str) -> IResult<&str, ()> {
+  let (input, _) = opt(read_xml_declaration)(input)?;
+  let (input, _) = fold_many0(alt((value((), read_misc), value((), read_doctype))), (), |_, _| ())(input)?;
+  Ok((input, ()))
+}
+
+// Binds each element to the namespace its prefix refers to, using the declarations on the element and its ancestors,
+// and finds where each element, attribute and text node is in the document. The default namespace is kept under the
+// empty prefix.
+fn resolve<'a>(element: &mut XmlElement<'a>, scope: &HashMap<&'a str, String>, lines: &LineIndex) {
+  let mut declared = element.attributes.iter().filter_map(|(name, uri)| match *name {
+    "xmlns" => Some(("", uri)),
+    n if n.starts_with("xmlns:") => Some((&n["xmlns:".len()..], uri)),
+    _ => None
+  }).peekable();
+
+  let mut inner;
+  let scope = match declared.peek() {
+    Some(_) => {
+      inner = scope.clone();
+      inner.extend(declared.map(|(prefix, uri)| (prefix, uri.to_owned())));
+      &inner
+    },
+    None => scope
+  };
+
+  // xmlns="" takes elements without a prefix out of the default namespace
+  element.namespace = scope.get(element.prefix()).filter(|uri| !uri.is_empty()).cloned();
+  element.location = lines.locate(element.source).unwrap_or_default();
+  for attribute in element.raw_attributes.iter_mut() {
+    attribute.location = lines.locate(attribute.source).unwrap_or_default();
+  }
+  for child in element.children.iter_mut() {
+    match child {
+      Node::XmlElement(child) => resolve(child, scope, lines),
+      Node::Value(text) => text.location = lines.locate(text.source).unwrap_or_default(),
+      _ => ()
+    }
+  }
+}
+
+pub fn parse(document: &str) -> IResult<&str, XmlElement> {
+  let (input, mut root) = preceded(read_prolog, read_element)(document)?;
+  resolve(&mut root, &HashMap::new(), &LineIndex::new(document));
+  Ok((input, root))
+}
+// Unescapes text read from the document along with where each byte of the unescaped text came from in the raw text, the
+// extra offset at the end is the length of the raw text so the end of a range can be looked up too
+pub fn unescape_with_offsets(raw: &str) -> (String, Vec<usize>) {
+  let mut text = String::with_capacity(raw.len());
+  let mut offsets = Vec::with_capacity(raw.len() + 1);
+  let mut input = raw;
+
+  while !input.is_empty() {
+    let at = raw.len() - input.len();
+    if let Ok((rest, cdata)) = read_cdata(input) {
+      // CDATA is copied as it is, so each byte comes from its own place in the raw text
+      let cdata_start = at + "<![CDATA[".len();
+      offsets.extend(cdata_start..cdata_start + cdata.len());
+      text.push_str(cdata);
+      input = rest;
+      continue;
+    }
+    if let Ok((rest, _)) = alt((read_comment, map(read_processing_instruction, |(target, _)| target)))(input) {
+      input = rest;
+      continue;
+    }
+
+    let (rest, piece) = match unescape_xml_literal('<')(input) {
+      Ok(result) => result,
+      // Anything that isn't a known entity is kept as it was written
+      Err(_) => {
+        let length = input.chars().next().map_or(1, char::len_utf8);
+        (&input[length..], Cow::Borrowed(&input[..length]))
+      }
+    };
+    offsets.extend(std::iter::repeat_n(at, piece.len()));
+    text.push_str(&piece);
+    input = rest;
+  }
+
+  offsets.push(raw.len());
+  (text, offsets)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn namespaces(element: &XmlElement) -> Vec<(String, Option<String>)> {
+    let mut found = vec![(element.name.to_owned(), element.namespace.clone())];
+    for child in element.children.iter() {
+      if let Node::XmlElement(child) = child {
+        found.extend(namespaces(child));
+      }
+    }
+    found
+  }
+
+  #[test]
+  fn elements_are_bound_to_the_namespace_of_their_prefix() {
+    let document = "<hd:library xmlns:hd=\"urn:hd\" xmlns=\"urn:default\"><hd:text/><plain><x:other xmlns:x=\"urn:x\"/></plain><none xmlns=\"\"/></hd:library>";
+    let (_, root) = parse(document).unwrap();
+    let expected = vec![
+      ("hd:library", Some("urn:hd")),
+      ("hd:text", Some("urn:hd")),
+      ("plain", Some("urn:default")),
+      ("x:other", Some("urn:x")),
+      ("none", None)
+    ];
+    let expected: Vec<(String, Option<String>)> = expected.into_iter().map(|(n, u)| (n.to_owned(), u.map(String::from))).collect();
+    assert_eq!(namespaces(&root), expected);
+    assert_eq!(namespaces(&crate::xml_reader::read_tree(document).unwrap()), expected);
+  }
+}
@@ -0,0 +1,518 @@
+use crate::component_library::is_hotdocs_namespace;
+use crate::diagnostics::{ LineIndex, Location };
+use crate::xml_parser::{ self, Attribute, Node, Text, XmlElement };
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StartTag<'a> {
+    pub name: &'a str,
+    // In the order they were written, the locations are filled in once the tag is part of a tree
+    pub attributes: Vec<Attribute<'a>>,
+    pub self_closing: bool,
+    // From the < to the closing > or />
+    pub source: &'a str
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event<'a> {
+    Start(StartTag<'a>),
+    // Sent straight after the start of a self closing element too, with an empty source
+    End { name: &'a str, source: &'a str },
+    // Text between markup, still escaped
+    Text(&'a str),
+    CData(&'a str),
+    Comment(&'a str),
+    ProcessingInstruction(&'a str, &'a str),
+    Doctype(&'a str)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReadError {
+    pub offset: usize,
+    pub message: String
+}
+
+// Reads a document one event at a time. Only the names of the open elements and the namespaces they declare are kept,
+// so reading takes the same memory however large the document is.
+pub struct Reader<'a> {
+    document: &'a str,
+    offset: usize,
+    open: Vec<&'a str>,
+    // The name of a self closing element whose end hasn't been sent yet
+    closing: Option<&'a str>,
+    // The xmlns declarations in scope, with how many elements were open when they were declared
+    namespaces: Vec<(usize, &'a str, String)>,
+    failed: bool
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '-'
+}
+
+fn unescape(raw: &str) -> String {
+    xml_parser::unescape_with_offsets(raw).0
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(document: &'a str) -> Reader<'a> {
+        let offset = if document.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+        Reader { document, offset, open: vec![], closing: None, namespaces: vec![], failed: false }
+    }
+
+    // How many elements are open, the root element is at depth 1 once it has started
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    // The URI the prefix of the name is bound to, or the default namespace for a name without one
+    pub fn namespace(&self, name: &str) -> Option<&str> {
+        let prefix = name.find(':').map_or("", |i| &name[..i]);
+        self.namespaces
+            .iter()
+            .rev()
+            .find(|(_, p, _)| *p == prefix)
+            .map(|(_, _, uri)| uri.as_str())
+            .filter(|uri| !uri.is_empty())
+    }
+
+    fn error(&mut self, offset: usize, message: String) -> Option<Result<Event<'a>, ReadError>> {
+        self.failed = true;
+        Some(Err(ReadError { offset, message }))
+    }
+
+    // Reads markup that runs from the opening delimiter to the closing one, returning what's between them
+    fn delimited(&mut self, open: &str, close: &str) -> Option<&'a str> {
+        let start = self.offset + open.len();
+        let length = self.document[start..].find(close)?;
+        self.offset = start + length + close.len();
+        Some(&self.document[start..start + length])
+    }
+
+    fn close(&mut self, name: &'a str, source: &'a str) -> Event<'a> {
+        self.open.pop();
+        let depth = self.open.len();
+        self.namespaces.retain(|(declared, _, _)| *declared <= depth);
+        Event::End { name, source }
+    }
+
+    fn read_start_tag(&mut self) -> Result<StartTag<'a>, ReadError> {
+        let start = self.offset;
+        let document = self.document;
+        let name_start = start + 1;
+        let name_end = document[name_start..].find(|c: char| !is_name_char(c)).map_or(document.len(), |i| name_start + i);
+        let name = &document[name_start..name_end];
+        if name.is_empty() {
+            return Err(ReadError { offset: start, message: String::from("Expected an element name after <") });
+        }
+
+        let mut attributes = vec![];
+        let mut at = name_end;
+        let self_closing = loop {
+            at += document[at..].len() - document[at..].trim_start().len();
+            let rest = &document[at..];
+            if rest.starts_with("/>") {
+                at += 2;
+                break true;
+            }
+            if rest.starts_with('>') {
+                at += 1;
+                break false;
+            }
+
+            let attribute_start = at;
+            let length = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+            let attribute_name = &rest[..length];
+            at += length;
+            at += document[at..].len() - document[at..].trim_start().len();
+            if attribute_name.is_empty() || !document[at..].starts_with('=') {
+                return Err(ReadError { offset: attribute_start, message: format!("Expected an attribute or the end of <{}>", name) });
+            }
+            at += 1;
+            at += document[at..].len() - document[at..].trim_start().len();
+            let quote = match document[at..].chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(ReadError { offset: at, message: format!("Expected the value of {} in quotes", attribute_name) })
+            };
+            let value_start = at + 1;
+            let value_end = match document[value_start..].find(quote) {
+                Some(length) => value_start + length,
+                None => return Err(ReadError { offset: at, message: format!("The value of {} is never closed", attribute_name) })
+            };
+            at = value_end + 1;
+            attributes.push(Attribute {
+                name: attribute_name,
+                raw_value: &document[value_start..value_end],
+                source: &document[attribute_start..at],
+                location: Location::default()
+            });
+        };
+
+        self.offset = at;
+        Ok(StartTag { name, attributes, self_closing, source: &document[start..at] })
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Event<'a>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(name) = self.closing.take() {
+            return Some(Ok(self.close(name, "")));
+        }
+
+        let start = self.offset;
+        let rest = &self.document[start..];
+        if rest.is_empty() {
+            return match self.open.last() {
+                Some(name) => {
+                    let message = format!("The document ends before </{}>", name);
+                    self.error(start, message)
+                },
+                None => None
+            };
+        }
+
+        let event = if rest.starts_with("<!--") {
+            self.delimited("<!--", "-->").map(Event::Comment)
+        } else if rest.starts_with("<![CDATA[") {
+            self.delimited("<![CDATA[", "]]>").map(Event::CData)
+        } else if rest.starts_with("<!DOCTYPE") {
+            self.delimited("<!DOCTYPE", ">").map(|d| Event::Doctype(d.trim()))
+        } else if rest.starts_with("<?") {
+            self.delimited("<?", "?>").map(|body| {
+                let target = body.split(char::is_whitespace).next().unwrap_or_default();
+                Event::ProcessingInstruction(target, body[target.len()..].trim_start())
+            })
+        } else if rest.starts_with("</") {
+            let body = match self.delimited("</", ">") {
+                Some(body) => body,
+                None => return self.error(start, String::from("An end tag is never closed"))
+            };
+            let name = body.trim_end();
+            let source = &self.document[start..self.offset];
+            return match self.open.last() {
+                Some(open) if *open == name => Some(Ok(self.close(name, source))),
+                Some(open) => {
+                    let message = format!("Expected </{}> but found </{}>", open, name);
+                    self.error(start, message)
+                },
+                None => self.error(start, format!("</{}> has no start tag", name))
+            };
+        } else if rest.starts_with('<') {
+            return match self.read_start_tag() {
+                Ok(tag) => {
+                    let depth = self.open.len() + 1;
+                    for attribute in tag.attributes.iter() {
+                        let prefix = match attribute.name {
+                            "xmlns" => "",
+                            n if n.starts_with("xmlns:") => &n["xmlns:".len()..],
+                            _ => continue
+                        };
+                        self.namespaces.push((depth, prefix, unescape(attribute.raw_value)));
+                    }
+                    self.open.push(tag.name);
+                    if tag.self_closing {
+                        self.closing = Some(tag.name);
+                    }
+                    Some(Ok(Event::Start(tag)))
+                },
+                Err(e) => {
+                    self.failed = true;
+                    Some(Err(e))
+                }
+            };
+        } else {
+            let length = rest.find('<').unwrap_or(rest.len());
+            self.offset += length;
+            Some(Event::Text(&rest[..length]))
+        };
+
+        match event {
+            Some(event) => Some(Ok(event)),
+            None => self.error(start, String::from("Markup is never closed"))
+        }
+    }
+}
+
+// Builds elements from the events of a reader without recursing, keeping only the elements that are still open
+struct TreeBuilder<'a, 'l> {
+    document: &'a str,
+    lines: &'l LineIndex<'a>,
+    open: Vec<XmlElement<'a>>,
+    // Where the text and CDATA read since the last markup start and end, and whether any of it was CDATA
+    text: Option<(usize, usize, bool)>
+}
+
+impl<'a, 'l> TreeBuilder<'a, 'l> {
+    fn offset(&self, slice: &str) -> usize {
+        slice.as_ptr() as usize - self.document.as_ptr() as usize
+    }
+
+    fn add_text(&mut self, raw: &'a str, cdata: bool) {
+        let start = self.offset(raw) - if cdata { "<![CDATA[".len() } else { 0 };
+        let end = self.offset(raw) + raw.len() + if cdata { "]]>".len() } else { 0 };
+        self.text = match self.text {
+            Some((from, _, had_cdata)) => Some((from, end, had_cdata || cdata)),
+            None => Some((start, end, cdata))
+        };
+    }
+
+    // Text is only kept when it's more than the whitespace between markup
+    fn flush_text(&mut self) {
+        if let Some((start, end, cdata)) = self.text.take() {
+            let source = &self.document[start..end];
+            if let Some(parent) = self.open.last_mut() {
+                if cdata || !source.trim().is_empty() {
+                    let location = self.lines.locate(source).unwrap_or_default();
+                    parent.children.push(Node::Value(Text { value: unescape(source), source, location }));
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, tag: StartTag<'a>, namespace: Option<&str>) {
+        let attributes = tag.attributes.iter().map(|a| (a.name, unescape(a.raw_value))).collect();
+        let raw_attributes = tag
+            .attributes
+            .into_iter()
+            .map(|a| Attribute { location: self.lines.locate(a.source).unwrap_or_default(), ..a })
+            .collect();
+        let content = if tag.self_closing { "" } else { &self.document[self.offset(tag.source) + tag.source.len()..][..0] };
+        self.open.push(XmlElement {
+            name: tag.name,
+            attributes,
+            children: vec![],
+            source: tag.source,
+            content,
+            raw_attributes,
+            namespace: namespace.map(|uri| uri.to_owned()),
+            location: Location::default()
+        });
+    }
+
+    // Finishes the innermost open element, returning it when it was the outermost one
+    fn end(&mut self, source: &'a str) -> Option<XmlElement<'a>> {
+        let mut element = self.open.pop()?;
+        let start = self.offset(element.source);
+        if !source.is_empty() {
+            let content_start = self.offset(element.content);
+            element.content = &self.document[content_start..self.offset(source)];
+            element.source = &self.document[start..self.offset(source) + source.len()];
+        }
+        element.location = self.lines.locate(element.source).unwrap_or_default();
+
+        match self.open.last_mut() {
+            Some(parent) => {
+                parent.children.push(Node::XmlElement(element));
+                None
+            },
+            None => Some(element)
+        }
+    }
+
+    // Adds an event to the tree, returning the outermost element once it ends
+    fn event(&mut self, event: Event<'a>, reader: &Reader<'a>) -> Option<XmlElement<'a>> {
+        match event {
+            Event::Text(raw) => {
+                self.add_text(raw, false);
+                return None;
+            },
+            Event::CData(raw) => {
+                self.add_text(raw, true);
+                return None;
+            },
+            _ => self.flush_text()
+        }
+
+        match event {
+            Event::Start(tag) => {
+                let namespace = reader.namespace(tag.name);
+                self.start(tag, namespace);
+                None
+            },
+            Event::End { source, .. } => self.end(source),
+            Event::Comment(comment) => {
+                if let Some(parent) = self.open.last_mut() {
+                    parent.children.push(Node::Comment(comment));
+                }
+                None
+            },
+            Event::ProcessingInstruction(target, data) => {
+                if let Some(parent) = self.open.last_mut() {
+                    parent.children.push(Node::ProcessingInstruction(target, data));
+                }
+                None
+            },
+            _ => None
+        }
+    }
+}
+
+// Reads the whole document into a tree without recursing, so deeply nested documents can't exhaust the stack
+pub fn read_tree(document: &str) -> Result<XmlElement<'_>, ReadError> {
+    read(document, None)
+}
+
+// Called with each component of the library and the root element it's in
+type ComponentCallback<'c, 'a> = dyn FnMut(XmlElement<'a>, &XmlElement<'a>) + 'c;
+
+// Reads the library into a tree that leaves out the children of the components section, handing each component to the
+// callback along with the root element as soon as the component ends. Only the component being read is held in memory.
+pub fn read_library<'a>(document: &'a str, mut component: impl FnMut(XmlElement<'a>, &XmlElement<'a>)) -> Result<XmlElement<'a>, ReadError> {
+    read(document, Some(&mut component))
+}
+
+fn read<'a>(document: &'a str, mut component: Option<&mut ComponentCallback<'_, 'a>>) -> Result<XmlElement<'a>, ReadError> {
+    let lines = LineIndex::new(document);
+    let mut builder = TreeBuilder { document, lines: &lines, open: vec![], text: None };
+    let mut component_builder = TreeBuilder { document, lines: &lines, open: vec![], text: None };
+    let mut reader = Reader::new(document);
+    // Whether the reader is in the first components section, and whether it has been read
+    let mut in_components = false;
+    let mut read_components = false;
+
+    while let Some(event) = reader.next() {
+        let event = event?;
+        if builder.open.is_empty() && !matches!(event, Event::Start(_)) {
+            // The prolog, and whatever follows the root element
+            continue;
+        }
+        if let Some(component) = component.as_mut() {
+            match &event {
+                Event::Start(tag) if reader.depth() == 2 && !read_components && is_components_section(&reader, tag.name) => {
+                    in_components = true;
+                    read_components = true;
+                },
+                Event::End { .. } if reader.depth() == 1 => in_components = false,
+                _ => ()
+            }
+            // Everything from the start of a component to its end is deeper than the section
+            let in_component = reader.depth() > 2 || (reader.depth() == 2 && matches!(event, Event::End { .. }));
+            if in_components && in_component {
+                // Text either side of a component is separate, not one run spanning the component
+                builder.flush_text();
+                if let (Some(element), Some(root)) = (component_builder.event(event, &reader), builder.open.first()) {
+                    component(element, root);
+                }
+                continue;
+            }
+        }
+        if let Some(root) = builder.event(event, &reader) {
+            return Ok(root);
+        }
+    }
+    Err(ReadError { offset: document.len(), message: String::from("The document has no root element") })
+}
+
+fn is_components_section(reader: &Reader, name: &str) -> bool {
+    let local = name.find(':').map_or(name, |i| &name[i + 1..]);
+    let in_namespace = match reader.namespace(name) {
+        Some(uri) => is_hotdocs_namespace(uri),
+        None => name.starts_with("hd:")
+    };
+    local == "components" && in_namespace
+}
+
+// Reads the children of the library's components section one at a time, building the tree of each component as it
+// streams by. Only the component being read is held in memory.
+pub struct Components<'a> {
+    reader: Reader<'a>,
+    lines: LineIndex<'a>,
+    // The depth of the components section once it has started
+    section: Option<usize>
+}
+
+impl<'a> Components<'a> {
+    pub fn new(document: &'a str) -> Components<'a> {
+        Components { reader: Reader::new(document), lines: LineIndex::new(document), section: None }
+    }
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Result<XmlElement<'a>, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut builder: Option<TreeBuilder> = None;
+
+        while let Some(event) = self.reader.next() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e))
+            };
+
+            if let Some(builder) = builder.as_mut() {
+                if let Some(component) = builder.event(event, &self.reader) {
+                    return Some(Ok(component));
+                }
+                continue;
+            }
+
+            match (&event, self.section) {
+                (Event::Start(tag), None) if self.reader.depth() == 2 && is_components_section(&self.reader, tag.name) => {
+                    self.section = Some(2);
+                },
+                (Event::Start(_), Some(depth)) if self.reader.depth() == depth + 1 => {
+                    let mut component = TreeBuilder { document: self.reader.document, lines: &self.lines, open: vec![], text: None };
+                    component.event(event, &self.reader);
+                    builder = Some(component);
+                },
+                // The rest of the document after the components section is skipped
+                (Event::End { .. }, Some(depth)) if self.reader.depth() < depth => return None,
+                _ => ()
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = "<?xml version=\"1.0\"?>\n<hd:componentLibrary xmlns:hd=\"http://www.hotdocs.com/schemas/component_library/2009\">\n<hd:preferences/>\n<hd:components>\n  <hd:text name='Client Name'><hd:prompt>Name</hd:prompt></hd:text>\n  <!-- between -->\n  <hd:number name=\"Age\"/>\n</hd:components>\n</hd:componentLibrary>\n";
+
+    fn element_names<'a>(element: &XmlElement<'a>) -> Vec<&'a str> {
+        element.children.iter().filter_map(|n| match n {
+            Node::XmlElement(e) => Some(e.name),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn libraries_are_read_without_their_components() {
+        let mut components = vec![];
+        let root = read_library(LIBRARY, |component, root| components.push((component.source, root.name))).unwrap();
+        assert_eq!(element_names(&root), vec!["hd:preferences", "hd:components"]);
+        let section = root.children.iter().find_map(|n| match n {
+            Node::XmlElement(e) if e.name == "hd:components" => Some(e),
+            _ => None
+        }).unwrap();
+        assert_eq!(element_names(section), Vec::<&str>::new());
+        assert!(section.children.iter().all(|n| !matches!(n, Node::Value(_))));
+        assert!(section.source.ends_with("</hd:components>"));
+        assert_eq!(components, vec![
+            ("<hd:text name='Client Name'><hd:prompt>Name</hd:prompt></hd:text>", "hd:componentLibrary"),
+            ("<hd:number name=\"Age\"/>", "hd:componentLibrary")
+        ]);
+    }
+
+    #[test]
+    fn components_are_streamed_one_at_a_time() {
+        let components: Vec<XmlElement> = Components::new(LIBRARY).map(|c| c.unwrap()).collect();
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].attributes["name"], "Client Name");
+        assert_eq!(element_names(&components[0]), vec!["hd:prompt"]);
+        assert_eq!(components[0].location.start.line, 5);
+        assert_eq!(components[1].source, "<hd:number name=\"Age\"/>");
+        assert_eq!(components[1].namespace.as_deref(), Some("http://www.hotdocs.com/schemas/component_library/2009"));
+    }
+
+    #[test]
+    fn names_can_have_hyphens_and_values_single_quotes() {
+        let root = read_tree("<my-element data-name='a \"b\" &amp; c'/>").unwrap();
+        assert_eq!(root.name, "my-element");
+        assert_eq!(root.attributes["data-name"], "a \"b\" & c");
+    }
+}
@@ -0,0 +1,315 @@
+use crate::diagnostics::Span;
+use crate::xml_parser::{ Node, XmlElement };
+
+use serde::{Serialize, Deserialize};
+
+// Replaces the text between two byte offsets of the document
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String
+}
+
+impl Edit {
+    // Replaces a slice taken from the document, slices from elsewhere can't be edited
+    pub fn replace(document: &str, slice: &str, text: String) -> Option<Edit> {
+        Span::of(document, slice).map(|Span { start, end }| Edit { start, end, text })
+    }
+}
+
+// Escapes text for use between tags, or inside an attribute value quoted with either quote character
+pub fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '\'' if attribute => escaped.push_str("&apos;"),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+// Whether the text can be written as the name of an element or attribute
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_name = |c: char| c.is_alphabetic() || c == '_' || c == ':';
+    match chars.next() {
+        Some(c) if starts_name(c) => chars.all(|c| starts_name(c) || c.is_numeric() || c == '-' || c == '.' || c == '\u{B7}'),
+        _ => false
+    }
+}
+
+// Applies the edits to the document, leaving every byte outside them as it was. Edits that overlap an earlier one or
+// don't fit in the document are not applied, they are returned with the document.
+pub fn apply<'e>(document: &str, edits: &'e [Edit]) -> (String, Vec<&'e Edit>) {
+    let mut sorted: Vec<&Edit> = edits.iter().collect();
+    sorted.sort_by_key(|e| (e.start, e.end));
+
+    let mut output = String::with_capacity(document.len());
+    let mut skipped = vec![];
+    let mut copied = 0;
+    for edit in sorted {
+        let fits = edit.start <= edit.end && edit.end <= document.len()
+            && document.is_char_boundary(edit.start) && document.is_char_boundary(edit.end);
+        if edit.start < copied || !fits {
+            skipped.push(edit);
+            continue;
+        }
+        output.push_str(&document[copied..edit.start]);
+        output.push_str(&edit.text);
+        copied = edit.end;
+    }
+    output.push_str(&document[copied..]);
+    (output, skipped)
+}
+
+// A parsed document and the edits made to it so far. Writing it copies every byte the edits don't touch, so a document
+// without edits is written exactly as it was read.
+pub struct Document<'a> {
+    source: &'a str,
+    edits: Vec<Edit>
+}
+
+impl<'a> Document<'a> {
+    pub fn new(source: &'a str) -> Document<'a> {
+        Document { source, edits: vec![] }
+    }
+
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    // The edited document, and the edits that couldn't be applied because they overlap another
+    pub fn write(&self) -> (String, Vec<&Edit>) {
+        apply(self.source, &self.edits)
+    }
+
+    fn span(&self, slice: &str) -> Option<Span> {
+        Span::of(self.source, slice)
+    }
+
+    fn push(&mut self, start: usize, end: usize, text: String) {
+        let edit = Edit { start, end, text };
+        if !self.edits.contains(&edit) {
+            self.edits.push(edit);
+        }
+    }
+
+    fn line_ending(&self) -> &'static str {
+        if self.source.contains("\r\n") { "\r\n" } else { "\n" }
+    }
+
+    // Replaces a slice of the document with the given raw text, which is written as it is
+    pub fn replace(&mut self, slice: &str, text: String) -> bool {
+        match self.span(slice) {
+            Some(span) => {
+                self.push(span.start, span.end, text);
+                true
+            },
+            None => false
+        }
+    }
+
+    // Where the start tag of the element ends, before its closing > or />
+    fn start_tag_end(&self, element: &XmlElement<'a>) -> Option<usize> {
+        let start = self.span(element.source)?.start;
+        let end = match element.raw_attributes.last() {
+            Some(attribute) => self.span(attribute.source)?.end,
+            None => start + 1 + element.name.len()
+        };
+        Some(end)
+    }
+
+    fn is_self_closing(&self, element: &XmlElement<'a>) -> bool {
+        element.content.is_empty() && element.source.ends_with("/>")
+    }
+
+    // Changes the value of an attribute, adding it after the last attribute when the element doesn't have it yet
+    pub fn set_attribute(&mut self, element: &XmlElement<'a>, name: &str, value: &str) -> bool {
+        if !is_name(name) {
+            return false;
+        }
+        let escaped = escape(value, true);
+        match element.attribute(name) {
+            Some(attribute) => self.replace(attribute.raw_value, escaped),
+            None => match self.start_tag_end(element) {
+                Some(end) => {
+                    self.push(end, end, format!(" {}=\"{}\"", name, escaped));
+                    true
+                },
+                None => false
+            }
+        }
+    }
+
+    pub fn remove_attribute(&mut self, element: &XmlElement<'a>, name: &str) -> bool {
+        let source = match element.attribute(name) {
+            Some(attribute) => attribute.source,
+            None => return false
+        };
+        match self.span(source) {
+            Some(span) => {
+                // Remove the whitespace before the name too, so the remaining attributes stay evenly spaced
+                let start = self.source[..span.start].trim_end().len();
+                self.push(start, span.end, String::new());
+                true
+            },
+            None => false
+        }
+    }
+
+    // Replaces everything between the element's tags with the given text, escaping it
+    pub fn set_text(&mut self, element: &XmlElement<'a>, text: &str) -> bool {
+        let escaped = escape(text, false);
+        if self.is_self_closing(element) {
+            return match self.span(element.source) {
+                Some(span) => {
+                    self.push(span.end - "/>".len(), span.end, format!(">{}</{}>", escaped, element.name));
+                    true
+                },
+                None => false
+            };
+        }
+        match self.span(element.content) {
+            Some(span) => {
+                self.push(span.start, span.end, escaped);
+                true
+            },
+            None => false
+        }
+    }
+
+    // The whitespace the line holding the slice starts with, when nothing else comes before the slice on that line
+    fn indent_of(&self, span: Span) -> Option<&'a str> {
+        let line_start = self.source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let indent = &self.source[line_start..span.start];
+        if indent.chars().all(|c| c == ' ' || c == '\t') { Some(indent) } else { None }
+    }
+
+    // Adds raw XML as the last child of the element, on its own line indented like the children before it
+    pub fn append_child(&mut self, parent: &XmlElement<'a>, xml: &str) -> bool {
+        let parent_span = match self.span(parent.source) {
+            Some(span) => span,
+            None => return false
+        };
+
+        if self.is_self_closing(parent) {
+            self.push(parent_span.end - "/>".len(), parent_span.end, format!(">{}</{}>", xml, parent.name));
+            return true;
+        }
+
+        let last_child = parent.children.iter().rev().find_map(|n| match n {
+            Node::XmlElement(e) => self.span(e.source),
+            _ => None
+        });
+        match last_child {
+            Some(last) => {
+                let text = match self.indent_of(last) {
+                    Some(indent) => format!("{}{}{}", self.line_ending(), indent, xml),
+                    None => xml.to_owned()
+                };
+                self.push(last.end, last.end, text);
+            },
+            None => {
+                let end = parent_span.end - parent.name.len() - "</>".len();
+                self.push(end, end, xml.to_owned());
+            }
+        }
+        true
+    }
+
+    // Removes the element, and the line it was on when nothing else was written there
+    pub fn remove(&mut self, element: &XmlElement<'a>) -> bool {
+        let span = match self.span(element.source) {
+            Some(span) => span,
+            None => return false
+        };
+
+        let rest_of_line = &self.source[span.end..];
+        let line_end = rest_of_line.find('\n').map(|i| span.end + i + 1);
+        let trailing_blank = line_end.is_some_and(|end| self.source[span.end..end].trim().is_empty());
+        match (self.indent_of(span), line_end) {
+            (Some(indent), Some(end)) if trailing_blank => self.push(span.start - indent.len(), end, String::new()),
+            _ => self.push(span.start, span.end, String::new())
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xml_reader;
+
+    const DOCUMENT: &str = "<?xml version=\"1.0\"?>\r\n<!-- a comment & more -->\n<library  version = '12' >\n\t<component name='Client \"Name\"'   kind=\"text\"/>\n  <script><![CDATA[IF A < B & C]]></script>\n</library>\n";
+
+    fn first_child<'a, 'b>(element: &'b XmlElement<'a>) -> &'b XmlElement<'a> {
+        element.children.iter().find_map(|n| match n {
+            Node::XmlElement(e) => Some(e),
+            _ => None
+        }).unwrap()
+    }
+
+    #[test]
+    fn documents_without_edits_are_written_as_they_were_read() {
+        let document = Document::new(DOCUMENT);
+        assert_eq!(document.write(), (DOCUMENT.to_owned(), vec![]));
+    }
+
+    #[test]
+    fn setting_an_attribute_changes_only_its_value() {
+        let root = xml_reader::read_tree(DOCUMENT).unwrap();
+        let mut document = Document::new(DOCUMENT);
+        assert!(document.set_attribute(first_child(&root), "kind", "number"));
+        let (written, skipped) = document.write();
+        assert!(skipped.is_empty());
+        assert_eq!(written, DOCUMENT.replace("kind=\"text\"", "kind=\"number\""));
+    }
+
+    #[test]
+    fn values_are_escaped_for_either_quote() {
+        let root = xml_reader::read_tree(DOCUMENT).unwrap();
+        let mut document = Document::new(DOCUMENT);
+        assert!(document.set_attribute(first_child(&root), "name", "Bob's \"A&B\""));
+        assert!(document.set_attribute(&root, "version", "13"));
+        let (written, _) = document.write();
+        let expected = DOCUMENT
+            .replace("name='Client \"Name\"'", "name='Bob&apos;s &quot;A&amp;B&quot;'")
+            .replace("version = '12'", "version = '13'");
+        assert_eq!(written, expected);
+
+        let reread = xml_reader::read_tree(&written).unwrap();
+        assert_eq!(first_child(&reread).attributes["name"], "Bob's \"A&B\"");
+    }
+
+    #[test]
+    fn added_attributes_go_after_the_last_one() {
+        let root = xml_reader::read_tree(DOCUMENT).unwrap();
+        let mut document = Document::new(DOCUMENT);
+        assert!(document.set_attribute(first_child(&root), "id", "1"));
+        assert!(!document.set_attribute(first_child(&root), "not a name", "1"));
+        assert!(!document.set_attribute(first_child(&root), "1st", "1"));
+        assert_eq!(document.write().0, DOCUMENT.replace("kind=\"text\"", "kind=\"text\" id=\"1\""));
+    }
+
+    #[test]
+    fn overlapping_edits_are_not_applied() {
+        let edits = vec![
+            Edit { start: 0, end: 3, text: String::from("x") },
+            Edit { start: 2, end: 4, text: String::from("y") },
+            Edit { start: 5, end: 9, text: String::from("z") }
+        ];
+        let (written, skipped) = apply("abcdef", &edits);
+        assert_eq!(written, "xdef");
+        assert_eq!(skipped, vec![&edits[1], &edits[2]]);
+    }
+}
@@ -0,0 +1,41 @@
+export type AnswerValue =
+    'Unanswered'
+    | { Text: string }
+    | { Number: number }
+    | { Date: { year: number, month: number, day: number } }
+    | { TrueFalse: boolean }
+    | { MultipleChoice: string[] };
+
+export interface Answer {
+    name: string,
+    // The zero based iteration of each repeat the answer was given in
+    index: number[],
+    value: AnswerValue
+}
+
+export interface Evaluation {
+    result: AnswerValue,
+    answers: Answer[],
+    asked: string[]
+}
+
+export interface Diagnostic {
+    severity: 'Error' | 'Warning',
+    message: string,
+    component: string | null,
+    // Offsets into the component's script
+    span: { start: number, end: number } | null,
+    // Where the diagnostic points to in the component file, lines and columns count from 1
+    location: Location | null
+}
+
+export interface Position {
+    offset: number,
+    line: number,
+    column: number
+}
+
+export interface Location {
+    start: Position,
+    end: Position
+}
@@ -0,0 +1,24 @@
+import { Diagnostic } from './Answers';
+
+export interface CloneOptions {
+    // How alike two scripts must be to be near-clones, from 0 to 1
+    threshold?: number,
+    min_statements?: number
+}
+
+export interface CloneMember {
+    component: string,
+    // The statements of this script that differ from the rest of the group
+    differences: { start: number, end: number }[]
+}
+
+export interface CloneGroup {
+    kind: 'Exact' | 'Renamed' | 'Near',
+    similarity: number,
+    members: CloneMember[]
+}
+
+export interface CloneReport {
+    groups: CloneGroup[],
+    diagnostics: Diagnostic[]
+}
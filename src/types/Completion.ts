@@ -0,0 +1,29 @@
+import { Diagnostic } from './Answers';
+
+export type CompletionKind =
+    | 'Keyword'
+    | 'Operator'
+    | 'Variable'
+    | 'Computation'
+    | 'Dialog'
+    | 'Function'
+    // A value of a multiple choice variable
+    | 'Option';
+
+export interface Completion {
+    label: string,
+    kind: CompletionKind,
+    detail: string | null,
+    // The text that replaces what has been typed, functions include their opening parenthesis
+    insert: string,
+    // Higher scores fit the position better, candidates are sorted by it
+    score: number
+}
+
+export interface Completions {
+    // UTF-16 offsets of the part of the script the candidates replace
+    span: { start: number, end: number },
+    items: Completion[],
+    // Why the component file couldn't be read, there are no candidates then
+    diagnostics: Diagnostic[]
+}
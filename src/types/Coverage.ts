@@ -0,0 +1,25 @@
+import { Answer, AnswerValue, Diagnostic } from './Answers';
+
+export interface Branch {
+    condition: string,
+    // Whether this is the branch run when the condition holds, or the ELSE branch
+    holds: boolean,
+    span: { start: number, end: number } | null,
+    covered: boolean
+}
+
+export interface TestCase {
+    answers: Answer[],
+    result: AnswerValue,
+    // Indices into the coverage's branches
+    branches: number[],
+    // The same answers as a HotDocs answer file
+    answer_file: string
+}
+
+export interface Coverage {
+    computation: string,
+    branches: Branch[],
+    cases: TestCase[],
+    diagnostics: Diagnostic[]
+}
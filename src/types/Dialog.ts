@@ -0,0 +1,16 @@
+import { Answer, Diagnostic } from './Answers';
+
+export interface ControlState {
+    name: string,
+    visible: boolean,
+    grayed: boolean,
+    required: boolean
+}
+
+export interface DialogState {
+    dialog: string,
+    controls: ControlState[],
+    limit: number | null,
+    answers: Answer[],
+    diagnostics: Diagnostic[]
+}
@@ -0,0 +1,38 @@
+import { Diagnostic } from './Answers';
+
+export interface ComponentMetrics {
+    name: string,
+    statements: number,
+    depth: number,
+    complexity: number,
+    reads: number,
+    writes: number,
+    fan_in: number,
+    fan_out: number,
+    comment_ratio: number
+}
+
+// Limits left out use their defaults, a null limit is never reported
+export interface Thresholds {
+    statements?: number | null,
+    depth?: number | null,
+    complexity?: number | null,
+    fan_in?: number | null,
+    fan_out?: number | null,
+    comment_ratio?: number | null,
+    commented_statements?: number
+}
+
+export interface MetricsReport {
+    components: ComponentMetrics[],
+    summary: {
+        components: number,
+        statements: number,
+        average_complexity: number,
+        max_complexity: number,
+        max_depth: number,
+        comment_ratio: number,
+        over_threshold: string[]
+    },
+    diagnostics: Diagnostic[]
+}
@@ -0,0 +1,23 @@
+export type OutlineStep =
+    { Ask: { dialog: string, inserted: OutlineStep[] } }
+    | { Condition: { condition: string, then_body: OutlineStep[], else_body: OutlineStep[] } }
+    | { Repeat: { dialog: string, body: OutlineStep[] } }
+    | { While: { condition: string, body: OutlineStep[] } }
+    | { Computation: { name: string, body: OutlineStep[] } }
+    | { Recursion: { name: string } };
+
+export interface Outline {
+    root: string | null,
+    steps: OutlineStep[]
+}
+
+export interface FlowNode {
+    id: string,
+    label: string,
+    symbolType: string
+}
+
+export interface FlowLink {
+    source: string,
+    target: string
+}
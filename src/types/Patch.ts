@@ -0,0 +1,22 @@
+import { Diagnostic } from './Answers';
+
+// Replaces the text between two byte offsets of the component file
+export interface Edit {
+    start: number,
+    end: number,
+    text: string
+}
+
+export type Patch =
+    | { SetScript: { component: string, script: string } }
+    // A null value removes the attribute
+    | { SetAttribute: { component: string, attribute: string, value: string | null } }
+    | { AddComponent: { xml: string } }
+    | { RemoveComponent: { component: string } };
+
+export interface Patched {
+    // The component file with the edits applied
+    xml: string,
+    edits: Edit[],
+    diagnostics: Diagnostic[]
+}
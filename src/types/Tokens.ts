@@ -0,0 +1,30 @@
+import { Diagnostic, Location } from './Answers';
+
+export type TokenKind =
+    | 'Keyword'
+    | 'Operator'
+    | 'Number'
+    | 'Text'
+    | 'Boolean'
+    | 'Comment'
+    // A name that is a component of the library
+    | 'Variable'
+    | 'UnresolvedVariable'
+    // A built in function
+    | 'Function'
+    | 'Punctuation'
+    // Text after the point the script stopped parsing
+    | 'Invalid';
+
+export interface Token {
+    kind: TokenKind,
+    // UTF-16 offsets into the script, so they index the script string directly
+    span: { start: number, end: number },
+    // Lines and columns in the script, counting from 1
+    location: Location
+}
+
+export interface Tokens {
+    tokens: Token[],
+    diagnostics: Diagnostic[]
+}
@@ -0,0 +1,30 @@
+import { Location } from './Answers';
+
+export type UseKind =
+    // Listed in the hd:contents of a dialog
+    | 'DialogContents'
+    // A script reads its value
+    | 'Read'
+    // A script sets, adds to, defaults, erases, increments or decrements it
+    | 'Write'
+    | 'Ask'
+    // A script grays, ungrays, shows, hides, requires or limits it
+    | 'Display'
+    // A script repeats the dialog
+    | 'Repeat'
+    // In the options of a multiple choice variable or the script of an option table
+    | 'OptionTable'
+    // Merged into a prompt, resource or other text of a component
+    | 'MergeField';
+
+export interface Use {
+    // The component the use is in
+    component: string,
+    kind: UseKind,
+    // Byte offsets into the component file
+    span: { start: number, end: number } | null,
+    // Lines and columns in the component file, counting from 1
+    location: Location | null,
+    // Byte offsets into the component's script, for uses in scripts
+    script_span: { start: number, end: number } | null
+}
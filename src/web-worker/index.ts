@@ -1,10 +1,22 @@
 // https://blog.logrocket.com/integrating-web-workers-in-a-react-app-with-comlink/
 
 import { expose } from 'comlink';
-import { analyze } from '../analyzer';
+import { analyze, outline, evaluate, checkAnswers, simulateDialog, coverage, metrics, clones, rename, patch, tokens, complete, whereUsed } from '../analyzer';
 
 const exports = {
-    analyze
+    analyze,
+    outline,
+    evaluate,
+    checkAnswers,
+    simulateDialog,
+    coverage,
+    metrics,
+    clones,
+    rename,
+    patch,
+    tokens,
+    complete,
+    whereUsed
 };
 export type Analyzer = typeof exports;
 